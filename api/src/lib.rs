@@ -1,4 +1,5 @@
-use hexchesscore::{Hexagon, PieceType, Board};
+use hexchesscore::{Hexagon, PieceType, Board, GameResult, HexChessError, Move};
+use hexchesscore::variants::VariantKind;
 use serde::{Serialize, Deserialize};
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
@@ -22,6 +23,21 @@ pub enum GameEndReason {
     Checkmate,
     Stalemate,
     Resignation,
+    ThreefoldRepetition,
+    FiftyMoveRule,
+    InsufficientMaterial,
+}
+
+impl From<GameResult> for GameEndReason {
+    fn from(result: GameResult) -> Self {
+        match result {
+            GameResult::Checkmate => GameEndReason::Checkmate,
+            GameResult::Stalemate => GameEndReason::Stalemate,
+            GameResult::ThreefoldRepetition => GameEndReason::ThreefoldRepetition,
+            GameResult::FiftyMoveRule => GameEndReason::FiftyMoveRule,
+            GameResult::InsufficientMaterial => GameEndReason::InsufficientMaterial,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -46,6 +62,9 @@ pub enum IncomingMessage {
     CreateGame {
         user_id: String,
         is_multiplayer: bool,
+        /// Which rules to play by. Gliński's, if left out.
+        #[serde(default)]
+        variant: VariantKind,
     },
     JoinGame {
         user_id: String,
@@ -68,6 +87,9 @@ pub enum OutgoingMessage {
     },
     BoardState {
         board: Board,
+        /// The move that led to this board, if there was one
+        #[serde(default)]
+        last_move: Option<Move>,
     },
     JoinGameSuccess {
         color: PlayerColor,
@@ -83,5 +105,9 @@ pub enum OutgoingMessage {
     },
     GameStatus {
         game_started: bool
-    }
+    },
+    /// Sent back to a player whose move wasn't played
+    MoveRejected {
+        reason: HexChessError,
+    },
 }
@@ -0,0 +1,531 @@
+//! Matches between two engines, to tell whether a change makes bumblebot
+//! stronger.
+//!
+//! Games are played in pairs from the same opening, with the engines
+//! swapping colours, so neither gets the better openings. The results are
+//! summed up as an Elo difference, and a sequential probability ratio test
+//! can end the match as soon as it's clear whether the change gained what
+//! it was hoped to.
+
+use std::{
+    fmt,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use hexchesscore::{
+    engine_protocol::{CoordinateMove, ProtocolError},
+    game_record::{GameRecord, RecordResult},
+    Board, Color, Move,
+};
+
+use crate::engine::{Engine, Score, SearchLimits, SearchResult};
+
+/// Where a pair of games starts: a position, and any moves played from it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Opening {
+    pub start: Board,
+    pub moves: Vec<Move>,
+}
+
+impl Opening {
+    /// The usual starting position, with no moves played.
+    pub fn standard() -> Opening {
+        Opening {
+            start: Board::setup_default_board(),
+            moves: Vec::new(),
+        }
+    }
+
+    /// Read a line of a book. It's either a position in the notation from
+    /// [`hexchesscore::notation`], or moves like `f5f6 f7f6` played from
+    /// the usual starting position.
+    pub fn parse(line: &str) -> Result<Opening, ProtocolError> {
+        if let Ok(start) = Board::from_notation(line) {
+            return Ok(Opening { start, moves: Vec::new() });
+        }
+        let mut board = Board::setup_default_board();
+        let mut moves = Vec::new();
+        for text in line.split_whitespace() {
+            let movement = text.parse::<CoordinateMove>()?.to_move(&board);
+            board.play(movement).map_err(|reason| ProtocolError::IllegalMove {
+                movement: text.to_string(),
+                reason,
+            })?;
+            moves.push(movement);
+        }
+        Ok(Opening {
+            start: Board::setup_default_board(),
+            moves,
+        })
+    }
+}
+
+/// Read a book of openings, one per line. Blank lines, and lines starting
+/// with `#`, are skipped.
+pub fn read_book(path: impl AsRef<Path>) -> io::Result<Vec<Opening>> {
+    let mut openings = Vec::new();
+    for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let opening = Opening::parse(line)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {e}", number + 1)))?;
+        openings.push(opening);
+    }
+    Ok(openings)
+}
+
+/// Add a finished game to the end of a file of game records.
+pub fn append_record(path: impl AsRef<Path>, record: &GameRecord) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", record.to_pgn())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchOptions {
+    pub move_time: Duration,
+    /// How far past the move time a search can run before it loses on time.
+    /// Searches only look at the clock every so often, and not at all while
+    /// they finish off captures, so they all run over a little.
+    pub allowance: Duration,
+    /// Games still going after this many plies are drawn
+    pub max_plies: usize,
+}
+
+impl Default for MatchOptions {
+    fn default() -> Self {
+        MatchOptions {
+            move_time: Duration::from_millis(100),
+            allowance: Duration::from_secs(1),
+            max_plies: 400,
+        }
+    }
+}
+
+/// The comment stored with a move: the engine's opinion of the position
+/// it moved from, and the time it took.
+fn move_comment(score: Option<Score>, took: Duration) -> String {
+    let took = format!("{:.2}s", took.as_secs_f32());
+    match score {
+        Some(Score::Pawns(pawns)) if pawns.is_finite() => format!("{pawns:+.2} {took}"),
+        Some(Score::Pawns(pawns)) if pawns > 0.0 => format!("mating {took}"),
+        Some(Score::Pawns(_)) => format!("mated {took}"),
+        Some(Score::Expectation(expectation)) => format!("{expectation:.3} {took}"),
+        None => took,
+    }
+}
+
+/// End a game that was lost by `loser` without being played out.
+fn forfeit(record: &mut GameRecord, loser: Color, reason: &str) {
+    record.result = match loser {
+        Color::White => RecordResult::BlackWins,
+        Color::Black => RecordResult::WhiteWins,
+    };
+    record.set_tag("Termination", reason);
+}
+
+/// Search with `engine`, stopping it once it's run past `limit`, so one that
+/// ignores the time can't hold up the match forever.
+fn search_within(engine: &mut dyn Engine, limits: &SearchLimits, limit: Duration) -> SearchResult {
+    let stop = engine.stop_signal();
+    let (finished, search_over) = mpsc::channel::<()>();
+    let timer = thread::spawn(move || {
+        // the search finishing drops the sender, which ends the wait early
+        if let Err(mpsc::RecvTimeoutError::Timeout) = search_over.recv_timeout(limit) {
+            stop.stop();
+        }
+    });
+    let result = engine.search(limits);
+    drop(finished);
+    timer.join().expect("the timer doesn't panic");
+    result
+}
+
+/// Play a game from `opening`. Breaking the rules - taking too long, or
+/// answering with an illegal move or none at all - loses the game. An engine
+/// still searching when its time and allowance are up is told to stop, and
+/// loses on time once it does.
+pub fn play_game(
+    white: &mut dyn Engine,
+    black: &mut dyn Engine,
+    opening: &Opening,
+    options: &MatchOptions,
+) -> GameRecord {
+    let mut record = GameRecord::new(opening.start.clone());
+    let mut board = opening.start.clone();
+    for movement in &opening.moves {
+        board.play(*movement).expect("the opening's moves are legal");
+        record.push_move(*movement);
+    }
+    white.new_game();
+    black.new_game();
+
+    let limits = SearchLimits {
+        time: Some(options.move_time),
+        depth: None,
+    };
+    while record.moves.len() < options.max_plies {
+        let mover = board.current_player;
+        let engine: &mut dyn Engine = match mover {
+            Color::White => &mut *white,
+            Color::Black => &mut *black,
+        };
+        engine.set_position(&board);
+        let started = Instant::now();
+        let result = search_within(engine, &limits, options.move_time + options.allowance);
+        let took = started.elapsed();
+
+        if took > options.move_time + options.allowance {
+            forfeit(&mut record, mover, "time forfeit");
+            return record;
+        }
+        let Some(movement) = result.best_move else {
+            forfeit(&mut record, mover, "no move");
+            return record;
+        };
+        let outcome = match board.play(movement) {
+            Ok(outcome) => outcome,
+            Err(_) => {
+                forfeit(&mut record, mover, "illegal move");
+                return record;
+            }
+        };
+        record.push_move(movement);
+        if let Some(last) = record.moves.last_mut() {
+            last.comment = Some(move_comment(result.score, took));
+        }
+        if let Some(result) = outcome.result {
+            record.result = RecordResult::from_game_result(result, mover);
+            return record;
+        }
+    }
+    record.result = RecordResult::Draw;
+    record.set_tag("Termination", "move limit");
+    record
+}
+
+/// The 95% confidence interval is this many standard errors either side
+const CONFIDENCE_Z: f64 = 1.96;
+
+/// The expected score of a player this much stronger than their opponent.
+fn expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+/// The difference in strength that gives this expected score.
+fn elo_from_score(score: f64) -> f64 {
+    400.0 * (score / (1.0 - score)).log10()
+}
+
+/// A match so far, from the first engine's point of view. Stalemates are
+/// worth ¾ of the point to the engine that gives them, as in the game records.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MatchScore {
+    pub wins: u32,
+    /// Games the first engine won by stalemating its opponent
+    pub stalemate_wins: u32,
+    pub draws: u32,
+    /// Games the first engine lost by being stalemated
+    pub stalemate_losses: u32,
+    pub losses: u32,
+}
+
+impl MatchScore {
+    /// Count a finished game in which the first engine played `color`.
+    pub fn add(&mut self, result: RecordResult, color: Color) {
+        match (result, color) {
+            (RecordResult::WhiteWins, Color::White) | (RecordResult::BlackWins, Color::Black) => self.wins += 1,
+            (RecordResult::WhiteWins, Color::Black) | (RecordResult::BlackWins, Color::White) => self.losses += 1,
+            (RecordResult::WhiteStalemates, Color::White) | (RecordResult::BlackStalemates, Color::Black) => {
+                self.stalemate_wins += 1
+            }
+            (RecordResult::WhiteStalemates, Color::Black) | (RecordResult::BlackStalemates, Color::White) => {
+                self.stalemate_losses += 1
+            }
+            (RecordResult::Draw, _) => self.draws += 1,
+            (RecordResult::Ongoing, _) => {}
+        }
+    }
+
+    /// How many games ended each way, with the points each was worth.
+    fn outcomes(&self) -> [(u32, f64); 5] {
+        [
+            (self.wins, 1.0),
+            (self.stalemate_wins, 0.75),
+            (self.draws, 0.5),
+            (self.stalemate_losses, 0.25),
+            (self.losses, 0.0),
+        ]
+    }
+
+    pub fn games(&self) -> u32 {
+        self.outcomes().iter().map(|(games, _)| games).sum()
+    }
+
+    /// The share of the points the first engine has taken, from 0 to 1.
+    pub fn score(&self) -> f64 {
+        let points: f64 = self.outcomes().iter().map(|(games, points)| *games as f64 * points).sum();
+        points / self.games() as f64
+    }
+
+    /// How much the score varies from game to game.
+    fn variance(&self) -> f64 {
+        let score = self.score();
+        let spread: f64 = self
+            .outcomes()
+            .iter()
+            .map(|(games, points)| *games as f64 * (points - score).powi(2))
+            .sum();
+        spread / self.games() as f64
+    }
+
+    /// How much stronger the first engine looks, in Elo. Infinite if one
+    /// engine has won every game.
+    pub fn elo(&self) -> f64 {
+        elo_from_score(self.score())
+    }
+
+    /// How far either side of [`MatchScore::elo`] the true difference could
+    /// be, with 95% confidence.
+    pub fn elo_error(&self) -> f64 {
+        if !self.elo().is_finite() {
+            return f64::INFINITY;
+        }
+        let error = CONFIDENCE_Z * (self.variance() / self.games() as f64).sqrt();
+        let low = elo_from_score((self.score() - error).max(0.0));
+        let high = elo_from_score((self.score() + error).min(1.0));
+        (high - low) / 2.0
+    }
+
+    /// The log-likelihood ratio of the first engine being `elo1` stronger,
+    /// rather than `elo0`, using the normal approximation to the score.
+    pub fn log_likelihood_ratio(&self, elo0: f64, elo1: f64) -> f64 {
+        let variance = self.variance();
+        if self.games() == 0 || variance == 0.0 {
+            return 0.0;
+        }
+        let (score0, score1) = (expected_score(elo0), expected_score(elo1));
+        self.games() as f64 * (score1 - score0) * (2.0 * self.score() - score0 - score1) / (2.0 * variance)
+    }
+}
+
+impl fmt::Display for MatchScore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "+{} ={} -{}", self.wins, self.draws, self.losses)?;
+        if self.stalemate_wins + self.stalemate_losses > 0 {
+            write!(f, " (stalemates +{} -{})", self.stalemate_wins, self.stalemate_losses)?;
+        }
+        if self.games() > 0 {
+            write!(f, ", Elo {:+.1} ± {:.1}", self.elo(), self.elo_error())?;
+        }
+        Ok(())
+    }
+}
+
+/// What a sequential probability ratio test decided.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SprtResult {
+    /// The first engine is at least `elo1` stronger
+    Pass,
+    /// The first engine is no more than `elo0` stronger
+    Fail,
+}
+
+/// A test of whether the first engine is `elo1` stronger than the second,
+/// rather than only `elo0`, that stops the match once the answer is known.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    /// The chance of passing when the first engine is only `elo0` stronger
+    pub alpha: f64,
+    /// The chance of failing when it's really `elo1` stronger
+    pub beta: f64,
+}
+
+impl Sprt {
+    pub fn new(elo0: f64, elo1: f64) -> Sprt {
+        Sprt {
+            elo0,
+            elo1,
+            alpha: 0.05,
+            beta: 0.05,
+        }
+    }
+
+    /// The log-likelihood ratios at which the test fails and passes.
+    pub fn bounds(&self) -> (f64, f64) {
+        let lower = (self.beta / (1.0 - self.alpha)).ln();
+        let upper = ((1.0 - self.beta) / self.alpha).ln();
+        (lower, upper)
+    }
+
+    pub fn log_likelihood_ratio(&self, score: &MatchScore) -> f64 {
+        score.log_likelihood_ratio(self.elo0, self.elo1)
+    }
+
+    /// The test's answer, if it's reached one yet.
+    pub fn result(&self, score: &MatchScore) -> Option<SprtResult> {
+        let ratio = self.log_likelihood_ratio(score);
+        let (lower, upper) = self.bounds();
+        if ratio >= upper {
+            Some(SprtResult::Pass)
+        } else if ratio <= lower {
+            Some(SprtResult::Fail)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{engine_by_name, StopSignal};
+
+    #[test]
+    fn test_reading_openings() {
+        let opening = Opening::parse("e4e5 e7e6").unwrap();
+        assert_eq!(opening.start, Board::setup_default_board());
+        assert_eq!(opening.moves.len(), 2);
+
+        let position = Board::setup_default_board().to_notation();
+        assert_eq!(Opening::parse(&position).unwrap(), Opening::standard());
+        assert!(Opening::parse("e4e5 e4e5").is_err());
+        assert!(Opening::parse("not an opening").is_err());
+
+        let path = std::env::temp_dir().join(format!("arena-book-{}.txt", std::process::id()));
+        fs::write(&path, "# a tiny book\ne4e5 e7e6\n\nf5f6\nf5f7 f7f6\n").unwrap();
+        let error = read_book(&path).unwrap_err();
+        assert!(error.to_string().starts_with("line 5"), "{error}");
+        fs::write(&path, "# a tiny book\ne4e5 e7e6\n\nf5f6\n").unwrap();
+        assert_eq!(read_book(&path).unwrap().len(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_playing_a_game() {
+        let mut white = engine_by_name("alphabeta").unwrap();
+        let mut black = engine_by_name("alphabeta").unwrap();
+        let options = MatchOptions {
+            move_time: Duration::from_millis(20),
+            // debug builds are slow to notice the time
+            allowance: Duration::from_secs(60),
+            max_plies: 4,
+        };
+        let opening = Opening::parse("e4e5 e7e6").unwrap();
+        let record = play_game(white.as_mut(), black.as_mut(), &opening, &options);
+        assert_eq!(record.moves.len(), 4);
+        assert_eq!(record.result, RecordResult::Draw);
+        assert_eq!(record.tag("Termination"), Some("move limit"));
+        assert!(record.moves[2].comment.is_some());
+        let reread = GameRecord::from_pgn(&record.to_pgn()).unwrap();
+        assert_eq!(reread.replay().unwrap(), record.replay().unwrap());
+
+        // no search can answer in no time at all
+        let options = MatchOptions {
+            allowance: Duration::ZERO,
+            ..options
+        };
+        let record = play_game(white.as_mut(), black.as_mut(), &opening, &options);
+        assert_eq!(record.result, RecordResult::BlackWins);
+        assert_eq!(record.tag("Termination"), Some("time forfeit"));
+    }
+
+    /// Searches until it's told to stop, whatever the limits say.
+    struct Stubborn(StopSignal);
+
+    impl Engine for Stubborn {
+        fn name(&self) -> &'static str {
+            "stubborn"
+        }
+
+        fn new_game(&mut self) {}
+
+        fn set_position(&mut self, _board: &Board) {}
+
+        fn search(&mut self, _limits: &SearchLimits) -> SearchResult {
+            self.0.reset();
+            while !self.0.is_stopped() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            SearchResult::game_over()
+        }
+
+        fn stop_signal(&self) -> StopSignal {
+            self.0.clone()
+        }
+    }
+
+    #[test]
+    fn test_engines_ignoring_the_time_are_stopped() {
+        let mut white = Stubborn(StopSignal::new());
+        let mut black = Stubborn(StopSignal::new());
+        let options = MatchOptions {
+            move_time: Duration::from_millis(10),
+            allowance: Duration::from_millis(10),
+            max_plies: 4,
+        };
+        let record = play_game(&mut white, &mut black, &Opening::standard(), &options);
+        assert_eq!(record.result, RecordResult::BlackWins);
+        assert_eq!(record.tag("Termination"), Some("time forfeit"));
+    }
+
+    #[test]
+    fn test_elo() {
+        let even = MatchScore { wins: 10, draws: 20, losses: 10, ..MatchScore::default() };
+        assert_eq!(even.elo(), 0.0);
+        let ahead = MatchScore { wins: 30, draws: 20, losses: 10, ..MatchScore::default() };
+        assert!((ahead.score() - 2.0 / 3.0).abs() < 1e-9);
+        // two thirds of the points is about 120 Elo
+        assert!((ahead.elo() - 120.4).abs() < 0.1, "{}", ahead.elo());
+
+        // more games narrow the error bars
+        let more = MatchScore { wins: 300, draws: 200, losses: 100, ..MatchScore::default() };
+        assert!(more.elo_error() < ahead.elo_error() / 3.0);
+
+        let whitewash = MatchScore { wins: 0, draws: 0, losses: 4, ..MatchScore::default() };
+        assert_eq!(whitewash.elo(), f64::NEG_INFINITY);
+        assert_eq!(whitewash.elo_error(), f64::INFINITY);
+
+        let mut score = MatchScore::default();
+        score.add(RecordResult::WhiteWins, Color::White);
+        score.add(RecordResult::WhiteWins, Color::Black);
+        score.add(RecordResult::Draw, Color::Black);
+        score.add(RecordResult::BlackWins, Color::Black);
+        assert_eq!(score, MatchScore { wins: 2, draws: 1, losses: 1, ..MatchScore::default() });
+
+        // stalemating is worth ¾ of a win
+        let mut score = MatchScore::default();
+        score.add(RecordResult::WhiteStalemates, Color::White);
+        score.add(RecordResult::WhiteStalemates, Color::Black);
+        assert_eq!(score, MatchScore { stalemate_wins: 1, stalemate_losses: 1, ..MatchScore::default() });
+        assert_eq!(score.score(), 0.5);
+        score.add(RecordResult::BlackStalemates, Color::Black);
+        assert!((score.score() - 1.75 / 3.0).abs() < 1e-9);
+        assert_eq!(score.to_string().split(',').next(), Some("+0 =0 -0 (stalemates +2 -1)"));
+    }
+
+    #[test]
+    fn test_sprt() {
+        let sprt = Sprt::new(0.0, 10.0);
+        let (lower, upper) = sprt.bounds();
+        assert!((lower + 2.944).abs() < 0.001 && (upper - 2.944).abs() < 0.001);
+
+        assert_eq!(sprt.result(&MatchScore { wins: 10, draws: 10, losses: 10, ..MatchScore::default() }), None);
+        assert_eq!(
+            sprt.result(&MatchScore { wins: 1200, draws: 1000, losses: 800, ..MatchScore::default() }),
+            Some(SprtResult::Pass)
+        );
+        assert_eq!(
+            sprt.result(&MatchScore { wins: 800, draws: 1000, losses: 1200, ..MatchScore::default() }),
+            Some(SprtResult::Fail)
+        );
+    }
+}
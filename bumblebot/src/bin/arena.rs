@@ -0,0 +1,126 @@
+//! Plays a match between two engines and reports which is stronger.
+//!
+//!     arena <engine> <engine> [--games N] [--move-ms MS] [--allowance-ms MS]
+//!           [--book FILE] [--records FILE] [--sprt ELO0 ELO1]
+//!
+//! An engine is one of bumblebot's by name, with any options after a colon,
+//! like `alphabeta:weights=tuned.json,hash=64`. Anything else is run as the
+//! command for an engine that speaks the engine protocol, like
+//! `"../old/bumblebot protocol"`. The results are given for the first
+//! engine.
+//!
+//! Pairs of games start from the same opening, picked at random from the
+//! book if there is one, with the engines taking each colour once. A move
+//! that takes longer than its time, plus the allowance, loses the game. With
+//! `--sprt`, the match stops early once it's clear whether the first engine
+//! is `ELO1` stronger rather than only `ELO0`.
+
+use std::{env, process, time::Duration};
+
+use bumblebot::{
+    arena::{append_record, play_game, read_book, MatchOptions, MatchScore, Opening, Sprt},
+    engine::{engine_by_name, Engine, ENGINE_NAMES},
+    external::ExternalEngine,
+};
+use hexchesscore::Color;
+use rand::seq::SliceRandom;
+
+fn usage() -> ! {
+    eprintln!("usage:");
+    eprintln!("    arena <engine> <engine> [--games N] [--move-ms MS] [--allowance-ms MS]");
+    eprintln!("          [--book FILE] [--records FILE] [--sprt ELO0 ELO1]");
+    eprintln!("engines are one of {ENGINE_NAMES:?}, with options like alphabeta:hash=64,");
+    eprintln!("or the command for an engine protocol engine");
+    process::exit(1);
+}
+
+fn number<T: std::str::FromStr>(arg: Option<&String>) -> T {
+    arg.and_then(|arg| arg.parse().ok()).unwrap_or_else(|| usage())
+}
+
+fn start_engine(spec: &str) -> Box<dyn Engine> {
+    let (name, options) = spec.split_once(':').unwrap_or((spec, ""));
+    if let Some(mut engine) = engine_by_name(name) {
+        for option in options.split(',').filter(|option| !option.is_empty()) {
+            let (option, value) = option.split_once('=').unwrap_or_else(|| usage());
+            engine
+                .set_option(option, value)
+                .unwrap_or_else(|e| panic!("couldn't set {option} for {name}: {e}"));
+        }
+        return engine;
+    }
+    let engine = ExternalEngine::start(spec).unwrap_or_else(|e| panic!("couldn't start {spec}: {e}"));
+    Box::new(engine)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (Some(first), Some(second)) = (args.first(), args.get(1)) else { usage() };
+
+    let mut games: u32 = 100;
+    let mut options = MatchOptions::default();
+    let mut book = vec![Opening::standard()];
+    let mut records = None;
+    let mut sprt = None;
+    let mut rest = args[2..].iter();
+    while let Some(flag) = rest.next() {
+        match flag.as_str() {
+            "--games" => games = number(rest.next()),
+            "--move-ms" => options.move_time = Duration::from_millis(number(rest.next())),
+            "--allowance-ms" => options.allowance = Duration::from_millis(number(rest.next())),
+            "--book" => {
+                let path = rest.next().unwrap_or_else(|| usage());
+                book = read_book(path).unwrap_or_else(|e| panic!("couldn't read the book {path}: {e}"));
+                if book.is_empty() {
+                    eprintln!("{path} has no openings in it");
+                    process::exit(1);
+                }
+            }
+            "--records" => records = Some(rest.next().unwrap_or_else(|| usage()).clone()),
+            "--sprt" => sprt = Some(Sprt::new(number(rest.next()), number(rest.next()))),
+            _ => usage(),
+        }
+    }
+
+    let mut engines = [start_engine(first), start_engine(second)];
+    let mut score = MatchScore::default();
+    let mut rng = rand::thread_rng();
+    let mut opening = book[0].clone();
+    for game in 0..games {
+        if game % 2 == 0 {
+            opening = book.choose(&mut rng).expect("the book has openings").clone();
+        }
+        // the first engine plays white in the first game of each pair
+        let first_color = if game % 2 == 0 { Color::White } else { Color::Black };
+        let [first_engine, second_engine] = &mut engines;
+        let (white, black, names) = match first_color {
+            Color::White => (first_engine, second_engine, [first, second]),
+            Color::Black => (second_engine, first_engine, [second, first]),
+        };
+        let mut record = play_game(white.as_mut(), black.as_mut(), &opening, &options);
+        record.set_tag("Event", "Arena");
+        record.set_tag("Round", &(game + 1).to_string());
+        record.set_tag("White", names[0]);
+        record.set_tag("Black", names[1]);
+        // the usual tags go first, and how the game ended after them
+        record.tags.sort_by_key(|(name, _)| name == "Termination");
+        if let Some(path) = &records {
+            append_record(path, &record).unwrap_or_else(|e| panic!("couldn't write to {path}: {e}"));
+        }
+
+        score.add(record.result, first_color);
+        let termination = record.tag("Termination").map_or(String::new(), |reason| format!(" ({reason})"));
+        eprint!("game {}/{games}: {}{termination}, {score}", game + 1, record.result);
+        if let Some(sprt) = &sprt {
+            let (lower, upper) = sprt.bounds();
+            eprint!(", LLR {:.2} [{lower:.2}, {upper:.2}]", sprt.log_likelihood_ratio(&score));
+        }
+        eprintln!();
+
+        if let Some(result) = sprt.and_then(|sprt| sprt.result(&score)) {
+            println!("SPRT {result:?} after {} games", score.games());
+            break;
+        }
+    }
+    println!("{first} against {second}: {score}");
+}
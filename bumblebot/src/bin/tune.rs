@@ -0,0 +1,91 @@
+//! Fits bumblebot's evaluation weights to game results.
+//!
+//!     tune generate <positions.jsonl> [games] [ms per move]
+//!     tune fit <positions.jsonl> <weights.json> [iterations] [starting weights.json]
+//!
+//! `generate` plays the bot against itself and adds the positions from each
+//! game to the file as it finishes. `fit` tunes the weights to the positions
+//! in the file, and writes them out where `BUMBLEBOT_WEIGHTS` can point to.
+
+use std::{env, process};
+
+use bumblebot::{
+    bot_mind::SearchState,
+    evaluation::EvaluationWeights,
+    tuning::{append_positions, read_positions, self_play_game, SelfPlayOptions, TrainingSet},
+};
+
+/// The table for self-play is kept small, as the moves are quick
+const SELF_PLAY_TABLE_MEGABYTES: usize = 16;
+const LEARNING_RATE: f32 = 0.01;
+
+fn usage() -> ! {
+    eprintln!("usage:");
+    eprintln!("    tune generate <positions.jsonl> [games] [ms per move]");
+    eprintln!("    tune fit <positions.jsonl> <weights.json> [iterations] [starting weights.json]");
+    process::exit(1);
+}
+
+fn number<T: std::str::FromStr>(arg: Option<&String>, default: T) -> T {
+    match arg {
+        Some(arg) => arg.parse().unwrap_or_else(|_| usage()),
+        None => default,
+    }
+}
+
+fn generate(args: &[String]) {
+    let Some(path) = args.first() else { usage() };
+    let games: usize = number(args.get(1), 100);
+    let options = SelfPlayOptions {
+        move_ms: number(args.get(2), SelfPlayOptions::default().move_ms),
+        ..SelfPlayOptions::default()
+    };
+
+    let mut state = SearchState::new(SELF_PLAY_TABLE_MEGABYTES);
+    let mut rng = rand::thread_rng();
+    for game in 1..=games {
+        let positions = self_play_game(&mut state, &options, &mut rng);
+        append_positions(path, &positions).unwrap_or_else(|e| panic!("couldn't write to {path}: {e}"));
+        let result = positions.first().map_or(0.5, |position| position.result);
+        eprintln!("game {game}/{games}: {result} for white, {} positions", positions.len());
+    }
+}
+
+fn fit(args: &[String]) {
+    let (Some(positions_path), Some(weights_path)) = (args.first(), args.get(1)) else { usage() };
+    let iterations: usize = number(args.get(2), 1000);
+    let start = match args.get(3) {
+        Some(path) => EvaluationWeights::load(path)
+            .unwrap_or_else(|e| panic!("couldn't read evaluation weights from {path}: {e}")),
+        None => EvaluationWeights::default(),
+    };
+
+    let positions =
+        read_positions(positions_path).unwrap_or_else(|e| panic!("couldn't read positions from {positions_path}: {e}"));
+    let set = TrainingSet::new(&positions);
+    if set.is_empty() {
+        eprintln!("{positions_path} has no positions in it");
+        process::exit(1);
+    }
+
+    let scale = set.fit_scale(&start.to_vec());
+    eprintln!("{} positions, scale {scale:.4}, error {:.6}", set.len(), set.error(&start.to_vec(), scale));
+    let tuned = set.tune(&start, scale, iterations, LEARNING_RATE, |iteration, error| {
+        if iteration % 100 == 0 {
+            eprintln!("iteration {iteration}: error {error:.6}");
+        }
+    });
+    eprintln!("final error {:.6}", set.error(&tuned.to_vec(), scale));
+    tuned
+        .save(weights_path)
+        .unwrap_or_else(|e| panic!("couldn't write evaluation weights to {weights_path}: {e}"));
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("generate") => generate(&args[1..]),
+        Some("fit") => fit(&args[1..]),
+        _ => usage(),
+    }
+}
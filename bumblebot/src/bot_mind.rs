@@ -1,49 +1,98 @@
 use api::OutgoingMessage;
-use std::{
-    collections::{VecDeque},
-    time::Duration,
-};
+use std::time::Duration;
 use tokio::{self, sync::mpsc};
 use std::time::Instant;
 use warp::ws::Message;
 
-use hexchesscore::{get_all_valid_moves, Board, Color, Move, PieceType, apply_move, revert_move};
-
-pub fn evaluate_board(board: &Board) -> f32 {
-    // count each player's pieces
-    let mut black_count = 0.0;
-    let mut white_count = 0.0;
-    for (_hex, piece) in &board.occupied_squares {
-        let value = match piece.piece_type {
-            PieceType::Pawn => 1.0,
-            PieceType::Rook => 4.0,
-            PieceType::Bishop => 3.0,
-            PieceType::Knight => 3.0,
-            PieceType::Queen => 9.0,
-            PieceType::King => 0.0,
-        };
-        match piece.color {
-            Color::White => white_count += value,
-            Color::Black => black_count += value,
+use hexchesscore::{Board, Move, PieceType, apply_move, king_is_in_check, revert_move};
+
+use crate::engine::{parse_option, Engine, OptionError, Score, SearchLimits, SearchResult, StopSignal};
+use crate::evaluation::{evaluate, EvaluationWeights};
+use crate::move_ordering::{is_tactical, order_tactical_moves, MoveOrdering};
+use crate::transposition::{Bound, TranspositionTable};
+
+/// The size of the transposition table the bot plays with
+pub const TABLE_MEGABYTES: usize = 64;
+
+/// The deepest a search goes when it's only limited by time
+pub const MAX_DEPTH: i8 = 20;
+
+/// A day, long enough to stand for no time limit at all
+const NO_TIME_LIMIT_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// How many nodes are searched between looks at the clock
+const CLOCK_CHECK_NODES: u64 = 256;
+
+/// What the search remembers from one position to the next. Keeping the
+/// same state for every move of a game lets each search start from what
+/// the last one found.
+#[derive(Debug, Clone)]
+pub struct SearchState {
+    pub ordering: MoveOrdering,
+    pub table: TranspositionTable,
+    pub weights: EvaluationWeights,
+    /// Checked along with the timeout, to end a search early
+    pub stop: StopSignal,
+    /// How many positions the current search has visited, not counting the
+    /// quiescence search
+    pub nodes: u64,
+}
+
+impl SearchState {
+    pub fn new(table_megabytes: usize) -> SearchState {
+        SearchState {
+            ordering: MoveOrdering::new(),
+            table: TranspositionTable::new(table_megabytes),
+            weights: EvaluationWeights::default(),
+            stop: StopSignal::new(),
+            nodes: 0,
         }
     }
-    (if board.current_player == Color::White {
-        1.0
-    } else {
-        -1.0
-    }) * (white_count - black_count)
+
+    /// Search with different evaluation weights. The table is cleared, as
+    /// the scores in it were worked out with the old ones.
+    pub fn set_weights(&mut self, weights: EvaluationWeights) {
+        self.weights = weights;
+        self.table.clear();
+    }
+
+    /// Forget everything learnt from the last game.
+    pub fn new_game(&mut self) {
+        self.ordering = MoveOrdering::new();
+        self.table.clear();
+    }
+}
+
+/// Rough piece values, for putting captures in order before they're searched
+pub fn piece_value(piece_type: PieceType) -> f32 {
+    match piece_type {
+        PieceType::Pawn => 1.0,
+        PieceType::Rook => 4.0,
+        PieceType::Bishop => 3.0,
+        PieceType::Knight => 3.0,
+        PieceType::Queen => 9.0,
+        PieceType::King => 0.0,
+    }
+}
+
+/// How good the position is for the player to move, in pawns. See
+/// [`crate::evaluation`] for what's taken into account.
+pub fn evaluate_board(board: &Board, weights: &EvaluationWeights) -> f32 {
+    evaluate(board, weights)
 }
 
-pub fn negamax(board: &mut Board, depth: i8) -> f32 {
+/// Plain minimax with no pruning, for checking the faster searches against.
+/// Like them, it finishes with a quiescence search.
+pub fn negamax(board: &mut Board, depth: i8, weights: &EvaluationWeights) -> f32 {
     if depth == 0 {
-        evaluate_board(board)
+        quiescence(board, f32::NEG_INFINITY, f32::INFINITY, weights)
     } else {
-        let moves = get_all_valid_moves(board);
+        let moves = board.legal_moves();
         let mut rating = f32::NEG_INFINITY;
         for movement in moves {
-            let (new_board, taken_piece) = apply_move(board, movement);
-            rating = f32::max(rating, -negamax(new_board, depth - 1));
-            revert_move(new_board, movement, taken_piece);
+            let undo = apply_move(board, movement);
+            rating = f32::max(rating, -negamax(board, depth - 1, weights));
+            revert_move(board, movement, undo);
         }
         rating
     }
@@ -51,40 +100,98 @@ pub fn negamax(board: &mut Board, depth: i8) -> f32 {
 
 pub fn send_board(transmitter: &mpsc::UnboundedSender<Message>, board: Board) {
     let _result = transmitter.send(Message::text(
-        serde_json::to_string(&OutgoingMessage::BoardState { board: board }).unwrap(),
+        serde_json::to_string(&OutgoingMessage::BoardState { board, last_move: None }).unwrap(),
     ));
 }
 
+/// Search captures and promotions until the position is quiet, so the
+/// search never stops halfway through an exchange. The side to move can
+/// always "stand pat" and keep the static evaluation instead, unless they're
+/// in check, in which case every way out of check is searched.
+pub fn quiescence(board: &mut Board, mut alpha: f32, beta: f32, weights: &EvaluationWeights) -> f32 {
+    let in_check = king_is_in_check(board.current_player, board);
+    let mut rating = f32::NEG_INFINITY;
+    if !in_check {
+        rating = evaluate_board(board, weights);
+        if rating >= beta {
+            return rating;
+        }
+        alpha = f32::max(alpha, rating);
+    }
+    let mut moves = board.legal_moves();
+    if !in_check {
+        moves.retain(|movement| is_tactical(board, movement));
+    }
+    order_tactical_moves(board, &mut moves);
+
+    for valid_move in moves {
+        let undo = apply_move(board, valid_move);
+        let eval = quiescence(board, -beta, -alpha, weights);
+        revert_move(board, valid_move, undo);
+
+        rating = f32::max(rating, -eval);
+        if rating >= beta {
+            break;
+        }
+        alpha = f32::max(alpha, rating);
+    }
+    rating
+}
+
 pub fn alpha_beta_prune(
     board: &mut Board,
     depth: i8,
     mut alpha: f32,
     beta: f32,
+    ply: usize,
+    state: &mut SearchState,
     timeout: Instant
     // tx: &mpsc::UnboundedSender<Message>
 ) -> Option<f32> {
+    state.nodes += 1;
+    // deep searches can take a long time to come back up to a node that
+    // looks at the clock, so every so often look at it on the way down too
+    if state.nodes.is_multiple_of(CLOCK_CHECK_NODES) && (Instant::now() > timeout || state.stop.is_stopped()) {
+        return None;
+    }
+    let key = board.hash();
+    let mut hash_move = None;
+    if let Some(entry) = state.table.probe(key) {
+        if let Some(score) = entry.cutoff(depth, alpha, beta) {
+            return Some(score);
+        }
+        hash_move = entry.best_move;
+    }
+
+    let original_alpha = alpha;
     let mut rating;
+    let mut best_move = None;
     if depth == 0 {
         // send_board(tx, board.clone());
-        rating = evaluate_board(board);
+        rating = quiescence(board, alpha, beta, &state.weights);
         // thread::sleep(Duration::from_millis(100));
     } else {
-        let moves = get_all_valid_moves(board);
+        let mut moves = board.legal_moves();
+        state.ordering.order(board, &mut moves, ply, hash_move);
 
         rating = f32::NEG_INFINITY;
 
         for valid_move in moves {
-            let (new_board, taken_piece) = apply_move(board, valid_move);
+            let quiet = !is_tactical(board, &valid_move);
+            let undo = apply_move(board, valid_move);
 
-            let eval = alpha_beta_prune(new_board, depth - 1, -beta, -alpha, timeout);
-            revert_move(board, valid_move, taken_piece);
+            let eval = alpha_beta_prune(board, depth - 1, -beta, -alpha, ply + 1, state, timeout);
+            revert_move(board, valid_move, undo);
 
             if let Some(eval) = eval {
-                rating = f32::max(
-                    rating,
-                    -eval,
-                );
-                if rating > beta {
+                if -eval > rating {
+                    rating = -eval;
+                    best_move = Some(valid_move);
+                }
+                if rating >= beta {
+                    if quiet {
+                        state.ordering.record_cutoff(valid_move, ply, depth);
+                    }
                     break;
                 }
             } else {
@@ -98,45 +205,63 @@ pub fn alpha_beta_prune(
     if depth > 2 {
         // if our depth is > 2 from the bottom level of the search, we will only visit this
         // level relatively rarely. We should poll the remaining time and figure out if we've
-        // already taken too long.
-        if Instant::now() > timeout {
+        // already taken too long, or been told to stop.
+        if Instant::now() > timeout || state.stop.is_stopped() {
             return None;
         }
     }
+    state.table.store(key, depth, bound_for(rating, original_alpha, beta), rating, best_move);
     Some(rating)
 }
 
+/// What a fail-soft search's result says about the position's true score.
+fn bound_for(rating: f32, alpha: f32, beta: f32) -> Bound {
+    if rating >= beta {
+        Bound::Lower
+    } else if rating <= alpha {
+        Bound::Upper
+    } else {
+        Bound::Exact
+    }
+}
+
 pub fn alpha_beta_prune_with_best_move(
     board: &mut Board,
     depth: i8,
     mut best_move: Move,
     mut alpha: f32,
     beta: f32,
+    state: &mut SearchState,
     timeout: Instant,
 ) -> Option<(f32, Move)> {
+    let key = board.hash();
+    let original_alpha = alpha;
     let mut rating;
     if depth == 0 {
-        rating = evaluate_board(board)
+        rating = quiescence(board, alpha, beta, &state.weights)
     } else {
-        let mut moves: VecDeque<Move> = get_all_valid_moves(board).into();
-        // move the best move to the front of the list
-        moves.retain(|&x| x != best_move);
-        moves.push_front(best_move);
+        let mut moves = board.legal_moves();
+        // the best move from the last iteration goes first
+        state.ordering.order(board, &mut moves, 0, Some(best_move));
 
         rating = f32::NEG_INFINITY;
 
         for valid_move in moves {
-            let (new_board, taken_piece) = apply_move(board, valid_move);
+            let quiet = !is_tactical(board, &valid_move);
+            let undo = apply_move(board, valid_move);
 
-            let eval = alpha_beta_prune(new_board, depth - 1, -beta, -alpha, timeout);
-            revert_move(board, valid_move, taken_piece);
+            let eval = alpha_beta_prune(board, depth - 1, -beta, -alpha, 1, state, timeout);
+            revert_move(board, valid_move, undo);
 
             if let Some(eval) = eval {
                 rating = f32::max(
                     -eval,
                     rating,
                 );
-                if rating > beta {
+                if rating >= beta {
+                    if quiet {
+                        state.ordering.record_cutoff(valid_move, 0, depth);
+                    }
                     break;
                 }
                 if rating > alpha {
@@ -148,39 +273,104 @@ pub fn alpha_beta_prune_with_best_move(
             }
         }
     }
+    let bound = bound_for(rating, original_alpha, beta);
+    state.table.store(key, depth, bound, rating, Some(best_move));
     Some((rating, best_move))
 }
 
 // pub fn iterative_deepening(board: &mut Board, max_depth: i8, tx: &mpsc::UnboundedSender<Message>) -> Move {
-pub fn iterative_deepening(board: &mut Board, max_depth: i8, timeout_ms: u64) -> Move {
-    let moves = get_all_valid_moves(board);
-    let mut best_move = moves[0];
+pub fn iterative_deepening(board: &mut Board, max_depth: i8, timeout_ms: u64, state: &mut SearchState) -> Move {
+    iterative_deepening_search(board, max_depth, timeout_ms, state)
+        .best_move
+        .expect("there are no moves to search")
+}
+
+/// Search one ply deeper at a time until `max_depth` or the timeout, and
+/// report what the deepest finished search found.
+pub fn iterative_deepening_search(
+    board: &mut Board,
+    max_depth: i8,
+    timeout_ms: u64,
+    state: &mut SearchState,
+) -> SearchResult {
+    let moves = board.legal_moves();
+    if moves.is_empty() {
+        return SearchResult::game_over();
+    }
+    // start from what the table remembers of this position, if anything
+    let mut best_move = state
+        .table
+        .probe(board.hash())
+        .and_then(|entry| entry.best_move)
+        .filter(|movement| moves.contains(movement))
+        .unwrap_or(moves[0]);
+    let mut score = None;
+    let mut finished_depth = 0;
+    // the table, killers and history all carry over from one depth to the
+    // next, and the table from one move to the next
+    state.ordering.new_search();
+    state.nodes = 0;
 
     // setup timer
     let end: Instant = Instant::now() + Duration::from_millis(timeout_ms);
 
 
     for depth in 0..(max_depth + 1) {
-        dbg!(depth);
-        if let Some((_new_rating, new_best_move)) = alpha_beta_prune_with_best_move(
+        if let Some((new_rating, new_best_move)) = alpha_beta_prune_with_best_move(
             board,
             depth,
             best_move,
             f32::NEG_INFINITY,
             f32::INFINITY,
+            state,
             end
         ) {
             best_move = new_best_move;
+            score = Some(new_rating);
+            finished_depth = depth;
         } else {
             // we've timed out, and need to pass whatever has been calculated
             // already
-            return best_move
+            break;
         }
     }
-    best_move
+    SearchResult {
+        best_move: Some(best_move),
+        score: score.map(Score::Pawns),
+        principal_variation: principal_variation(board, best_move, state, finished_depth.max(1) as usize),
+        nodes: state.nodes,
+        depth: Some(finished_depth as u32),
+    }
 }
 
-pub fn make_a_move(board: &mut Board, timeout_ms: u64) -> Move {
+/// The moves the table expects both players to make from `board`, starting
+/// with `best_move`, up to `length` of them.
+fn principal_variation(board: &Board, best_move: Move, state: &SearchState, length: usize) -> Vec<Move> {
+    let mut board = board.clone();
+    let mut line = vec![best_move];
+    apply_move(&mut board, best_move);
+    let mut seen = vec![board.hash()];
+    while line.len() < length {
+        let Some(movement) = state.table.probe(board.hash()).and_then(|entry| entry.best_move) else {
+            break;
+        };
+        // the entry could belong to another position with the same slot
+        // and key, so make sure the move can be played
+        if !board.legal_moves().contains(&movement) {
+            break;
+        }
+        apply_move(&mut board, movement);
+        // stop before going round a repetition forever
+        if seen.contains(&board.hash()) {
+            break;
+        }
+        seen.push(board.hash());
+        line.push(movement);
+    }
+    line
+}
+
+pub fn make_a_move(board: &mut Board, timeout_ms: u64, state: &mut SearchState) -> Move {
     // let move_options = get_all_valid_moves(board);
     // let mut best_move = move_options[0];
     // let mut best_move_rating = evaluate_move(board, best_move, bot_color);
@@ -200,5 +390,70 @@ pub fn make_a_move(board: &mut Board, timeout_ms: u64) -> Move {
     //         best_move = player_move
     //     }
     // }
-    iterative_deepening(board, 20, timeout_ms)
+    iterative_deepening(board, MAX_DEPTH, timeout_ms, state)
+}
+
+/// The alpha-beta search as an [`Engine`]. It takes the options `weights`,
+/// a path to evaluation weights to load, and `hash`, the size of the
+/// transposition table in megabytes.
+#[derive(Debug, Clone)]
+pub struct AlphaBetaEngine {
+    board: Board,
+    state: SearchState,
+}
+
+impl AlphaBetaEngine {
+    pub fn new() -> AlphaBetaEngine {
+        AlphaBetaEngine {
+            board: Board::setup_default_board(),
+            state: SearchState::new(TABLE_MEGABYTES),
+        }
+    }
+}
+
+impl Default for AlphaBetaEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine for AlphaBetaEngine {
+    fn name(&self) -> &'static str {
+        "alphabeta"
+    }
+
+    fn new_game(&mut self) {
+        self.state.new_game();
+    }
+
+    fn set_position(&mut self, board: &Board) {
+        // the table is keyed by position, so it carries over by itself
+        self.board = board.clone();
+    }
+
+    fn search(&mut self, limits: &SearchLimits) -> SearchResult {
+        self.state.stop.reset();
+        let max_depth = limits.depth.map_or(MAX_DEPTH, |depth| depth.min(i8::MAX as u32) as i8);
+        let timeout_ms = limits.time.map_or(NO_TIME_LIMIT_MS, |time| time.as_millis() as u64);
+        iterative_deepening_search(&mut self.board, max_depth, timeout_ms, &mut self.state)
+    }
+
+    fn stop_signal(&self) -> StopSignal {
+        self.state.stop.clone()
+    }
+
+    fn set_option(&mut self, name: &str, value: &str) -> Result<(), OptionError> {
+        match name {
+            "weights" => {
+                let weights = EvaluationWeights::load(value).map_err(|e| OptionError::InvalidValue {
+                    name: name.to_string(),
+                    reason: e.to_string(),
+                })?;
+                self.state.set_weights(weights);
+            }
+            "hash" => self.state.table = TranspositionTable::new(parse_option(name, value)?),
+            _ => return Err(OptionError::Unknown(name.to_string())),
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,270 @@
+//! One interface over bumblebot's search strategies.
+//!
+//! Each strategy is wrapped in an [`Engine`], which is told about the game
+//! and the position, and then asked to search it within some
+//! [`SearchLimits`]. Anything that plays the bot - the live server, the
+//! engine protocol, the tuner or a test - can pick an engine by name with
+//! [`engine_by_name`] and drive it the same way.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use hexchesscore::{Board, Move};
+
+use crate::bot_mind::AlphaBetaEngine;
+use crate::random_bot::MonteCarloEngine;
+use crate::random_bot2::SimpleMonteCarloEngine;
+
+/// The names [`engine_by_name`] knows, the default first.
+pub const ENGINE_NAMES: [&str; 3] = ["alphabeta", "mcts", "simple-mcts"];
+
+/// A new engine of the named kind, with its default settings.
+pub fn engine_by_name(name: &str) -> Option<Box<dyn Engine>> {
+    match name {
+        "alphabeta" => Some(Box::new(AlphaBetaEngine::new())),
+        "mcts" => Some(Box::new(MonteCarloEngine::new())),
+        "simple-mcts" => Some(Box::new(SimpleMonteCarloEngine::new())),
+        _ => None,
+    }
+}
+
+/// When a search should finish. With neither limit set, it carries on until
+/// it's stopped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SearchLimits {
+    pub time: Option<Duration>,
+    /// How many plies to search. The tree searches have no plies to count,
+    /// so they take it as a number of rounds instead: batches of games for
+    /// `mcts`, single games for `simple-mcts`.
+    pub depth: Option<u32>,
+}
+
+impl SearchLimits {
+    pub fn time_ms(ms: u64) -> SearchLimits {
+        SearchLimits {
+            time: Some(Duration::from_millis(ms)),
+            depth: None,
+        }
+    }
+
+    pub fn depth(depth: u32) -> SearchLimits {
+        SearchLimits {
+            time: None,
+            depth: Some(depth),
+        }
+    }
+}
+
+/// How good an engine thinks the position is for the player to move.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Score {
+    /// An evaluation in pawns. Mates are infinite.
+    Pawns(f32),
+    /// The share of the points the player expects to get, from 0 to 1
+    Expectation(f32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchResult {
+    /// None if the game is over and there's nothing to play
+    pub best_move: Option<Move>,
+    pub score: Option<Score>,
+    /// The moves the engine expects both players to make, starting with
+    /// `best_move`
+    pub principal_variation: Vec<Move>,
+    /// How many positions, or for the tree searches how many games, the
+    /// search looked at
+    pub nodes: u64,
+    /// The deepest search that finished, for the engines that search to a
+    /// depth
+    pub depth: Option<u32>,
+}
+
+impl SearchResult {
+    /// The result of searching a position with no legal moves.
+    pub fn game_over() -> SearchResult {
+        SearchResult {
+            best_move: None,
+            score: None,
+            principal_variation: Vec::new(),
+            nodes: 0,
+            depth: None,
+        }
+    }
+}
+
+/// Tells a running search to finish as soon as it can. Clones share the
+/// same signal, so one can be kept to stop a search running on another
+/// thread.
+#[derive(Debug, Clone, Default)]
+pub struct StopSignal(Arc<AtomicBool>);
+
+impl StopSignal {
+    pub fn new() -> StopSignal {
+        StopSignal::default()
+    }
+
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Ready the signal for the next search.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Why [`Engine::set_option`] refused an option.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionError {
+    /// The engine has no option by this name
+    Unknown(String),
+    /// The value couldn't be used for the option
+    InvalidValue { name: String, reason: String },
+}
+
+impl fmt::Display for OptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptionError::Unknown(name) => write!(f, "unknown option {name}"),
+            OptionError::InvalidValue { name, reason } => write!(f, "invalid value for {name}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for OptionError {}
+
+/// Parse an option's value, or say why it couldn't be.
+pub fn parse_option<T>(name: &str, value: &str) -> Result<T, OptionError>
+where
+    T: std::str::FromStr,
+    T::Err: fmt::Display,
+{
+    value.parse().map_err(|e: T::Err| OptionError::InvalidValue {
+        name: name.to_string(),
+        reason: e.to_string(),
+    })
+}
+
+/// A search strategy that can play a game.
+pub trait Engine: Send {
+    /// The name [`engine_by_name`] knows the engine by
+    fn name(&self) -> &'static str;
+
+    /// Forget everything learnt from the last game.
+    fn new_game(&mut self);
+
+    /// The position to search next. Engines that keep what they've learnt
+    /// between moves carry it over when this follows on from the last one.
+    fn set_position(&mut self, board: &Board);
+
+    /// Search the position until a limit is reached or the search is
+    /// stopped, and say which move to play.
+    fn search(&mut self, limits: &SearchLimits) -> SearchResult;
+
+    /// The signal [`Engine::stop`] sends. Keep a copy of it to stop a search
+    /// that's running on another thread.
+    fn stop_signal(&self) -> StopSignal;
+
+    /// Finish the current search as soon as possible.
+    fn stop(&self) {
+        self.stop_signal().stop();
+    }
+
+    /// Change one of the engine's settings. None are supported unless the
+    /// engine says otherwise.
+    fn set_option(&mut self, name: &str, _value: &str) -> Result<(), OptionError> {
+        Err(OptionError::Unknown(name.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hexchesscore::{Color, Hexagon, Piece, PieceType};
+
+    #[test]
+    fn test_every_engine_plays_a_legal_move() {
+        let board = Board::setup_default_board();
+        for name in ENGINE_NAMES {
+            let mut engine = engine_by_name(name).unwrap();
+            assert_eq!(engine.name(), name);
+            engine.new_game();
+            engine.set_position(&board);
+            let result = engine.search(&SearchLimits::time_ms(50));
+            let best_move = result.best_move.unwrap();
+            assert!(board.legal_moves().contains(&best_move), "{name} played {best_move:?}");
+            assert_eq!(result.principal_variation.first(), Some(&best_move));
+            assert!(result.nodes > 0);
+        }
+        assert!(engine_by_name("deep thought").is_none());
+    }
+
+    #[test]
+    fn test_every_engine_finishes_a_depth_search() {
+        let board = Board::setup_default_board();
+        for name in ENGINE_NAMES {
+            let mut engine = engine_by_name(name).unwrap();
+            engine.set_position(&board);
+            // with no time limit, this only returns if the engine heeds the depth
+            let result = engine.search(&SearchLimits::depth(1));
+            assert!(result.best_move.is_some(), "{name} didn't find a move");
+        }
+    }
+
+    #[test]
+    fn test_a_stopped_search_still_has_a_move() {
+        let board = Board::setup_default_board();
+        for name in ENGINE_NAMES {
+            let mut engine = engine_by_name(name).unwrap();
+            engine.set_position(&board);
+            let stop = engine.stop_signal();
+            let searching = std::thread::spawn(move || engine.search(&SearchLimits::default()));
+            std::thread::sleep(Duration::from_millis(50));
+            stop.stop();
+            assert!(searching.join().unwrap().best_move.is_some(), "{name} didn't stop with a move");
+        }
+    }
+
+    #[test]
+    fn test_finished_games_have_no_move() {
+        // the black queen, guarded by the rook, has the white king mated
+        let mut board = Board::new();
+        for (hex, piece_type, color) in [
+            ("A1", PieceType::King, Color::White),
+            ("F11", PieceType::King, Color::Black),
+            ("A2", PieceType::Queen, Color::Black),
+            ("A3", PieceType::Rook, Color::Black),
+        ] {
+            board.occupied_squares.insert(Hexagon::new(hex).unwrap(), Piece { piece_type, color });
+        }
+        board.refresh_hash();
+        assert!(board.legal_moves().is_empty());
+        for name in ENGINE_NAMES {
+            let mut engine = engine_by_name(name).unwrap();
+            engine.set_position(&board);
+            assert_eq!(engine.search(&SearchLimits::depth(2)), SearchResult::game_over());
+        }
+    }
+
+    #[test]
+    fn test_options() {
+        let mut engine = engine_by_name("mcts").unwrap();
+        assert!(engine.set_option("exploration", "0.5").is_ok());
+        assert!(matches!(engine.set_option("exploration", "lots"), Err(OptionError::InvalidValue { .. })));
+        for value in ["0", "-1", "NaN", "inf"] {
+            assert!(matches!(engine.set_option("exploration", value), Err(OptionError::InvalidValue { .. })));
+        }
+        assert_eq!(engine.set_option("colour", "blue"), Err(OptionError::Unknown("colour".to_string())));
+    }
+}
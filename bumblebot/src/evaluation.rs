@@ -0,0 +1,603 @@
+//! Scoring positions for the search.
+//!
+//! A position's score is a weighted sum of its features: material, where
+//! the pieces stand, pawn structure, king safety, mobility and which colours
+//! the bishops are on. Every feature is counted for White minus Black, so
+//! the score is linear in the weights. The weights live in
+//! [`EvaluationWeights`], which can be read from a JSON file, so they can be
+//! tuned without touching the search.
+//!
+//! Pawns move along the hexagons that share a letter, so that's what a
+//! pawn's file means here - doubled pawns share a letter, and an isolated
+//! pawn has no friendly pawns on the letters either side.
+
+use std::{fs, io, path::Path, sync::OnceLock};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use hexchesscore::geometry::{self, NUMBER_OF_HEXES};
+use hexchesscore::moves::get_rank_length;
+use hexchesscore::{attacks_to, Board, Color, Hexagon, PieceType};
+
+/// A number for each type of piece.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct PieceWeights {
+    pub pawn: f32,
+    pub knight: f32,
+    pub bishop: f32,
+    pub rook: f32,
+    pub queen: f32,
+    pub king: f32,
+}
+
+impl PieceWeights {
+    pub fn get(&self, piece_type: PieceType) -> f32 {
+        match piece_type {
+            PieceType::Pawn => self.pawn,
+            PieceType::Knight => self.knight,
+            PieceType::Bishop => self.bishop,
+            PieceType::Rook => self.rook,
+            PieceType::Queen => self.queen,
+            PieceType::King => self.king,
+        }
+    }
+
+    pub fn get_mut(&mut self, piece_type: PieceType) -> &mut f32 {
+        match piece_type {
+            PieceType::Pawn => &mut self.pawn,
+            PieceType::Knight => &mut self.knight,
+            PieceType::Bishop => &mut self.bishop,
+            PieceType::Rook => &mut self.rook,
+            PieceType::Queen => &mut self.queen,
+            PieceType::King => &mut self.king,
+        }
+    }
+
+    fn to_array(self) -> [f32; 6] {
+        [self.pawn, self.knight, self.bishop, self.rook, self.queen, self.king]
+    }
+
+    fn from_array([pawn, knight, bishop, rook, queen, king]: [f32; 6]) -> PieceWeights {
+        PieceWeights { pawn, knight, bishop, rook, queen, king }
+    }
+
+    fn dot(&self, other: &PieceWeights) -> f32 {
+        self.pawn * other.pawn
+            + self.knight * other.knight
+            + self.bishop * other.bishop
+            + self.rook * other.rook
+            + self.queen * other.queen
+            + self.king * other.king
+    }
+}
+
+/// A number for each hexagon, in the order of [`geometry::hexagons`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SquareTable(pub [f32; NUMBER_OF_HEXES]);
+
+impl Default for SquareTable {
+    fn default() -> Self {
+        SquareTable([0.0; NUMBER_OF_HEXES])
+    }
+}
+
+impl SquareTable {
+    fn dot(&self, other: &SquareTable) -> f32 {
+        self.0.iter().zip(&other.0).map(|(a, b)| a * b).sum()
+    }
+}
+
+// serde only handles arrays of up to 32 values, so the table is written as
+// a list and checked for length when it's read back
+impl Serialize for SquareTable {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for SquareTable {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let values = Vec::<f32>::deserialize(deserializer)?;
+        let found = values.len();
+        let values = values
+            .try_into()
+            .map_err(|_| de::Error::custom(format!("expected {NUMBER_OF_HEXES} values, found {found}")))?;
+        Ok(SquareTable(values))
+    }
+}
+
+/// A [`SquareTable`] for each type of piece - the piece-square tables.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct PieceSquareTables {
+    pub pawn: SquareTable,
+    pub knight: SquareTable,
+    pub bishop: SquareTable,
+    pub rook: SquareTable,
+    pub queen: SquareTable,
+    pub king: SquareTable,
+}
+
+impl PieceSquareTables {
+    pub fn get(&self, piece_type: PieceType) -> &SquareTable {
+        match piece_type {
+            PieceType::Pawn => &self.pawn,
+            PieceType::Knight => &self.knight,
+            PieceType::Bishop => &self.bishop,
+            PieceType::Rook => &self.rook,
+            PieceType::Queen => &self.queen,
+            PieceType::King => &self.king,
+        }
+    }
+
+    pub fn get_mut(&mut self, piece_type: PieceType) -> &mut SquareTable {
+        match piece_type {
+            PieceType::Pawn => &mut self.pawn,
+            PieceType::Knight => &mut self.knight,
+            PieceType::Bishop => &mut self.bishop,
+            PieceType::Rook => &mut self.rook,
+            PieceType::Queen => &mut self.queen,
+            PieceType::King => &mut self.king,
+        }
+    }
+
+    fn tables(&self) -> [&SquareTable; 6] {
+        [&self.pawn, &self.knight, &self.bishop, &self.rook, &self.queen, &self.king]
+    }
+
+    fn dot(&self, other: &PieceSquareTables) -> f32 {
+        self.tables().iter().zip(other.tables()).map(|(a, b)| a.dot(b)).sum()
+    }
+}
+
+/// How much each feature of a position is worth, in pawns. Penalties have
+/// negative weights.
+///
+/// The same struct holds a position's features, as counted by [`features`],
+/// so that a position's score is `weights.dot(&features(board))`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EvaluationWeights {
+    pub material: PieceWeights,
+    /// For a piece standing on each hexagon, as seen from White's side.
+    /// Black's pieces are looked up on the mirror image of their hexagon,
+    /// the same height from the other end of its file.
+    pub piece_squares: PieceSquareTables,
+    /// For a pawn no enemy pawn can stop or capture on its way to promotion
+    pub passed_pawn: f32,
+    /// For each pawn on a file after the first
+    pub doubled_pawn: f32,
+    /// For a pawn with no friendly pawns on the files either side
+    pub isolated_pawn: f32,
+    /// For each friendly pawn next to the king. Like `king_attacks`, this
+    /// counts for less as the opponent runs out of pieces to attack with.
+    pub king_shelter: f32,
+    /// For each hexagon next to the king, or under it, that the opponent
+    /// attacks
+    pub king_attacks: f32,
+    /// For each hexagon a piece could move to, ignoring pins
+    pub mobility: PieceWeights,
+    /// For each colour a player has a bishop on, after the first
+    pub bishop_colors: f32,
+}
+
+impl Default for EvaluationWeights {
+    fn default() -> Self {
+        EvaluationWeights {
+            material: PieceWeights {
+                pawn: 1.0,
+                knight: 3.0,
+                bishop: 3.0,
+                rook: 4.0,
+                queen: 9.0,
+                king: 0.0,
+            },
+            piece_squares: default_piece_squares(),
+            passed_pawn: 0.4,
+            doubled_pawn: -0.25,
+            isolated_pawn: -0.15,
+            king_shelter: 0.1,
+            king_attacks: -0.1,
+            mobility: PieceWeights {
+                pawn: 0.0,
+                knight: 0.04,
+                bishop: 0.03,
+                rook: 0.02,
+                queen: 0.01,
+                king: 0.0,
+            },
+            bishop_colors: 0.15,
+        }
+    }
+}
+
+impl EvaluationWeights {
+    /// How many numbers [`EvaluationWeights::to_vec`] gives
+    pub const LENGTH: usize = 6 + 6 * NUMBER_OF_HEXES + 5 + 6 + 1;
+
+    /// Read weights from a JSON file. Any left out keep their default.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<EvaluationWeights> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    /// The sum of each weight times the matching weight in `other`.
+    pub fn dot(&self, other: &EvaluationWeights) -> f32 {
+        self.material.dot(&other.material)
+            + self.piece_squares.dot(&other.piece_squares)
+            + self.passed_pawn * other.passed_pawn
+            + self.doubled_pawn * other.doubled_pawn
+            + self.isolated_pawn * other.isolated_pawn
+            + self.king_shelter * other.king_shelter
+            + self.king_attacks * other.king_attacks
+            + self.mobility.dot(&other.mobility)
+            + self.bishop_colors * other.bishop_colors
+    }
+
+    /// Every weight in a fixed order, for code that treats them all alike,
+    /// like the tuner.
+    pub fn to_vec(&self) -> Vec<f32> {
+        let mut values = Vec::with_capacity(Self::LENGTH);
+        values.extend(self.material.to_array());
+        for table in self.piece_squares.tables() {
+            values.extend(table.0);
+        }
+        values.extend([
+            self.passed_pawn,
+            self.doubled_pawn,
+            self.isolated_pawn,
+            self.king_shelter,
+            self.king_attacks,
+        ]);
+        values.extend(self.mobility.to_array());
+        values.push(self.bishop_colors);
+        values
+    }
+
+    /// The inverse of [`EvaluationWeights::to_vec`]. Panics unless there are
+    /// exactly [`EvaluationWeights::LENGTH`] values.
+    pub fn from_slice(values: &[f32]) -> EvaluationWeights {
+        assert_eq!(values.len(), Self::LENGTH, "wrong number of evaluation weights");
+        let pieces = |start: usize| PieceWeights::from_array(values[start..start + 6].try_into().unwrap());
+        let table = |number: usize| {
+            let start = 6 + number * NUMBER_OF_HEXES;
+            SquareTable(values[start..start + NUMBER_OF_HEXES].try_into().unwrap())
+        };
+        let rest = 6 + 6 * NUMBER_OF_HEXES;
+        EvaluationWeights {
+            material: pieces(0),
+            piece_squares: PieceSquareTables {
+                pawn: table(0),
+                knight: table(1),
+                bishop: table(2),
+                rook: table(3),
+                queen: table(4),
+                king: table(5),
+            },
+            passed_pawn: values[rest],
+            doubled_pawn: values[rest + 1],
+            isolated_pawn: values[rest + 2],
+            king_shelter: values[rest + 3],
+            king_attacks: values[rest + 4],
+            mobility: pieces(rest + 5),
+            bishop_colors: values[rest + 11],
+        }
+    }
+
+    /// Every weight set to zero, for counting features into.
+    pub fn zero() -> EvaluationWeights {
+        EvaluationWeights {
+            material: PieceWeights::default(),
+            piece_squares: PieceSquareTables::default(),
+            passed_pawn: 0.0,
+            doubled_pawn: 0.0,
+            isolated_pawn: 0.0,
+            king_shelter: 0.0,
+            king_attacks: 0.0,
+            mobility: PieceWeights::default(),
+            bishop_colors: 0.0,
+        }
+    }
+}
+
+/// The position's score for the player to move, in pawns.
+pub fn evaluate(board: &Board, weights: &EvaluationWeights) -> f32 {
+    let score = weights.dot(&features(board));
+    match board.current_player {
+        Color::White => score,
+        Color::Black => -score,
+    }
+}
+
+/// The tables to start tuning from: pieces are worth more in the centre
+/// than at the edge, the king less, and pawns more the further along their
+/// file they are.
+fn default_piece_squares() -> PieceSquareTables {
+    let centralisation = PieceWeights {
+        pawn: 0.1,
+        knight: 0.3,
+        bishop: 0.15,
+        rook: 0.1,
+        queen: 0.1,
+        king: -0.2,
+    };
+    let pawn_advance = 0.3;
+
+    let centre = Hexagon::new("F6").unwrap();
+    let mut tables = PieceSquareTables::default();
+    for (index, hex) in geometry::hexagons().iter().enumerate() {
+        // 1 on the centre hexagon, down to 0 at the edge
+        let centrality = 1.0 - geometry::distance(hex, &centre) as f32 / 5.0;
+        for piece_type in PIECE_TYPES {
+            tables.get_mut(piece_type).0[index] = centralisation.get(piece_type) * centrality;
+        }
+        // direction 0 is up the file, and 3 is down it
+        let up = geometry::ray(hex, 0).len() as f32;
+        let down = geometry::ray(hex, 3).len() as f32;
+        tables.pawn.0[index] += pawn_advance * down / (up + down).max(1.0);
+    }
+    tables
+}
+
+const PIECE_TYPES: [PieceType; 6] = [
+    PieceType::Pawn,
+    PieceType::Knight,
+    PieceType::Bishop,
+    PieceType::Rook,
+    PieceType::Queen,
+    PieceType::King,
+];
+
+/// The index of the hexagon a piece is looked up on in the piece-square
+/// tables: its own for White, and its mirror image for Black.
+fn table_index(hex: &Hexagon, color: Color) -> usize {
+    static MIRRORED: OnceLock<[usize; NUMBER_OF_HEXES]> = OnceLock::new();
+    let index = geometry::index(hex);
+    match color {
+        Color::White => index,
+        Color::Black => MIRRORED.get_or_init(|| {
+            std::array::from_fn(|index| {
+                let hex = geometry::hexagons()[index];
+                let length = get_rank_length(hex.rank).expect("the hexagon is on the board");
+                geometry::index(&Hexagon {
+                    rank: hex.rank,
+                    file: length - 1 - hex.file,
+                })
+            })
+        })[index],
+    }
+}
+
+fn color_index(color: Color) -> usize {
+    match color {
+        Color::White => 0,
+        Color::Black => 1,
+    }
+}
+
+/// Every feature of the position, counted for White minus Black.
+pub fn features(board: &Board) -> EvaluationWeights {
+    let mut features = EvaluationWeights::zero();
+
+    // lay the board out by index, so the pawn and mobility terms can look
+    // hexagons up without hashing
+    let mut occupant = [None; NUMBER_OF_HEXES];
+    let mut pawns_on_file = [[0u8; 11]; 2];
+    let mut bishop_colors = [[false; 3]; 2];
+    let mut non_pawns = [0u8; 2];
+    for (hex, piece) in &board.occupied_squares {
+        occupant[geometry::index(hex)] = Some(*piece);
+        let side = color_index(piece.color);
+        match piece.piece_type {
+            PieceType::Pawn => pawns_on_file[side][hex.rank as usize] += 1,
+            PieceType::King => {}
+            piece_type => {
+                non_pawns[side] += 1;
+                if piece_type == PieceType::Bishop {
+                    bishop_colors[side][geometry::hex_color(hex)] = true;
+                }
+            }
+        }
+    }
+    let is_pawn = |hex: &Hexagon, color: Color| {
+        occupant[geometry::index(hex)].is_some_and(|piece| piece.piece_type == PieceType::Pawn && piece.color == color)
+    };
+
+    for (hex, piece) in &board.occupied_squares {
+        let sign = match piece.color {
+            Color::White => 1.0,
+            Color::Black => -1.0,
+        };
+        let side = color_index(piece.color);
+        *features.material.get_mut(piece.piece_type) += sign;
+        features.piece_squares.get_mut(piece.piece_type).0[table_index(hex, piece.color)] += sign;
+
+        match piece.piece_type {
+            PieceType::Pawn => {
+                let file = hex.rank as usize;
+                let neighbours = [file.wrapping_sub(1), file + 1]
+                    .iter()
+                    .filter_map(|file| pawns_on_file[side].get(*file))
+                    .sum::<u8>();
+                if neighbours == 0 {
+                    features.isolated_pawn += sign;
+                }
+                if is_passed(hex, piece.color, &is_pawn) {
+                    features.passed_pawn += sign;
+                }
+            }
+            PieceType::King => {
+                // the king matters less as the opponent's attackers come off
+                let danger = (non_pawns[1 - side] as f32 / 8.0).min(1.0);
+                let shelter = geometry::king_moves(hex)
+                    .iter()
+                    .filter(|next| is_pawn(next, piece.color))
+                    .count();
+                features.king_shelter += sign * danger * shelter as f32;
+                let attacked = std::iter::once(*hex)
+                    .chain((0..6).filter_map(|direction| geometry::neighbour(hex, direction)))
+                    .filter(|zone| !attacks_to(zone, piece.color.invert(), board).is_empty())
+                    .count();
+                features.king_attacks += sign * danger * attacked as f32;
+            }
+            _ => {}
+        }
+
+        let reachable =
+            |next: &Hexagon| !matches!(occupant[geometry::index(next)], Some(other) if other.color == piece.color);
+        let moves = match piece.piece_type {
+            PieceType::Pawn => 0,
+            PieceType::Knight => geometry::knight_jumps(hex).iter().filter(|next| reachable(next)).count(),
+            PieceType::King => geometry::king_moves(hex).iter().filter(|next| reachable(next)).count(),
+            PieceType::Rook => sliding_moves(hex, 0..6, &occupant, &reachable),
+            PieceType::Bishop => sliding_moves(hex, 6..12, &occupant, &reachable),
+            PieceType::Queen => sliding_moves(hex, 0..12, &occupant, &reachable),
+        };
+        *features.mobility.get_mut(piece.piece_type) += sign * moves as f32;
+    }
+
+    for color in [Color::White, Color::Black] {
+        let side = color_index(color);
+        let sign = if side == 0 { 1.0 } else { -1.0 };
+        let doubled = pawns_on_file[side].iter().map(|count| count.saturating_sub(1)).sum::<u8>();
+        features.doubled_pawn += sign * doubled as f32;
+        let colors = bishop_colors[side].iter().filter(|has_bishop| **has_bishop).count();
+        features.bishop_colors += sign * colors.saturating_sub(1) as f32;
+    }
+    features
+}
+
+/// Whether nothing but pieces can stop the pawn - there are no enemy pawns
+/// in its way, or next to its path ready to capture it.
+fn is_passed(hex: &Hexagon, color: Color, is_pawn: &dyn Fn(&Hexagon, Color) -> bool) -> bool {
+    let enemy = color.invert();
+    let mut next = geometry::pawn_push(hex, color);
+    while let Some(ahead) = next {
+        // an enemy pawn attacks the hexagon from wherever a pawn of ours
+        // standing on it would capture
+        if is_pawn(&ahead, enemy) || geometry::pawn_captures(&ahead, color).iter().any(|from| is_pawn(from, enemy)) {
+            return false;
+        }
+        next = geometry::pawn_push(&ahead, color);
+    }
+    true
+}
+
+fn sliding_moves(
+    hex: &Hexagon,
+    directions: std::ops::Range<usize>,
+    occupant: &[Option<hexchesscore::Piece>; NUMBER_OF_HEXES],
+    reachable: &dyn Fn(&Hexagon) -> bool,
+) -> usize {
+    let mut count = 0;
+    for direction in directions {
+        for next in geometry::ray(hex, direction) {
+            if reachable(next) {
+                count += 1;
+            }
+            if occupant[geometry::index(next)].is_some() {
+                break;
+            }
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hexchesscore::Piece;
+
+    fn hex(name: &str) -> Hexagon {
+        Hexagon::new(name).unwrap()
+    }
+
+    fn place(board: &mut Board, name: &str, piece_type: PieceType, color: Color) {
+        board.occupied_squares.insert(hex(name), Piece { piece_type, color });
+    }
+
+    fn kings() -> Board {
+        let mut board = Board::new();
+        place(&mut board, "F1", PieceType::King, Color::White);
+        place(&mut board, "F11", PieceType::King, Color::Black);
+        board
+    }
+
+    #[test]
+    fn test_starting_position_is_level() {
+        let board = Board::setup_default_board();
+        let features = features(&board);
+        assert_eq!(features.material, PieceWeights::default());
+        assert_eq!(features.bishop_colors, 0.0);
+        assert!(evaluate(&board, &EvaluationWeights::default()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_pawn_structure() {
+        let mut board = kings();
+        // a doubled pair on B, and a pawn on D with no neighbours
+        place(&mut board, "B1", PieceType::Pawn, Color::White);
+        place(&mut board, "B2", PieceType::Pawn, Color::White);
+        place(&mut board, "D3", PieceType::Pawn, Color::White);
+        place(&mut board, "H7", PieceType::Pawn, Color::Black);
+        let features = features(&board);
+        assert_eq!(features.doubled_pawn, 1.0);
+        assert_eq!(features.isolated_pawn, 3.0 - 1.0);
+        // nothing stands in the way of any of them
+        assert_eq!(features.passed_pawn, 3.0 - 1.0);
+
+        // a black pawn on C7 guards B6 and D7, in the way of all three white
+        // pawns, and can't get past the pawn on B2 itself
+        place(&mut board, "C7", PieceType::Pawn, Color::Black);
+        assert_eq!(super::features(&board).passed_pawn, -1.0);
+    }
+
+    #[test]
+    fn test_bishops_on_every_color() {
+        let mut board = kings();
+        place(&mut board, "F3", PieceType::Bishop, Color::White);
+        place(&mut board, "F2", PieceType::Bishop, Color::White);
+        place(&mut board, "F9", PieceType::Bishop, Color::Black);
+        place(&mut board, "F6", PieceType::Bishop, Color::Black);
+        assert_eq!(geometry::hex_color(&hex("F9")), geometry::hex_color(&hex("F6")));
+        assert_eq!(features(&board).bishop_colors, 1.0);
+    }
+
+    #[test]
+    fn test_piece_square_tables_are_mirrored_for_black() {
+        let mut board = kings();
+        place(&mut board, "C3", PieceType::Knight, Color::White);
+        place(&mut board, "C6", PieceType::Knight, Color::Black);
+        place(&mut board, "H4", PieceType::Pawn, Color::White);
+        place(&mut board, "H6", PieceType::Pawn, Color::Black);
+        let features = features(&board);
+        assert_eq!(features.piece_squares, PieceSquareTables::default());
+
+        // each hexagon has its own weight, so moving the knight along the
+        // rank is seen
+        let mut weights = EvaluationWeights::zero();
+        weights.piece_squares.knight.0[geometry::index(&hex("C3"))] = 0.5;
+        assert_eq!(evaluate(&board, &weights), 0.0);
+        board.occupied_squares.remove(&hex("C3"));
+        place(&mut board, "C4", PieceType::Knight, Color::White);
+        assert_eq!(evaluate(&board, &weights), -0.5);
+    }
+
+    #[test]
+    fn test_weights_round_trip_and_fill_in_defaults() {
+        let weights: EvaluationWeights = serde_json::from_str(r#"{"passed_pawn": 1.5}"#).unwrap();
+        assert_eq!(weights.passed_pawn, 1.5);
+        assert_eq!(weights.material, EvaluationWeights::default().material);
+        let json = serde_json::to_string(&weights).unwrap();
+        assert_eq!(serde_json::from_str::<EvaluationWeights>(&json).unwrap(), weights);
+
+        let values = weights.to_vec();
+        assert_eq!(values.len(), EvaluationWeights::LENGTH);
+        assert_eq!(EvaluationWeights::from_slice(&values), weights);
+
+        let short_table = serde_json::from_str::<EvaluationWeights>(r#"{"piece_squares": {"pawn": [1.0]}}"#);
+        assert!(short_table.is_err());
+    }
+}
@@ -0,0 +1,236 @@
+//! An [`Engine`] that's another program, run as a subprocess and talked to
+//! over [`hexchesscore::engine_protocol`]. It lets an older build of
+//! bumblebot, or someone else's engine, play wherever ours can.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use hexchesscore::{
+    engine_protocol::{self, CoordinateMove, GoLimits, Info, InfoScore, Response},
+    Board, Move,
+};
+
+use crate::engine::{Engine, OptionError, Score, SearchLimits, SearchResult, StopSignal};
+
+/// How long the engine gets to introduce itself, to answer once it's been
+/// told to stop, and to quit when it's dropped, before it's given up on
+const PATIENCE: Duration = Duration::from_secs(5);
+/// How often a search checks whether it's been told to stop
+const POLL: Duration = Duration::from_millis(5);
+
+#[derive(Debug)]
+pub struct ExternalEngine {
+    /// What the engine called itself
+    pub id_name: Option<String>,
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+    board: Board,
+    stop: StopSignal,
+    /// Searches that were given up on before the engine answered. Their
+    /// `bestmove`s may still turn up, and aren't answers to the next search.
+    abandoned: usize,
+}
+
+impl ExternalEngine {
+    /// Run `command`, a program followed by its arguments, and wait for it
+    /// to say it speaks the protocol.
+    pub fn start(command: &str) -> io::Result<ExternalEngine> {
+        let mut words = command.split_whitespace();
+        let program = words
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "the engine's command is empty"))?;
+        let mut child = Command::new(program)
+            .args(words)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("the engine's stdin is piped");
+        let stdout = child.stdout.take().expect("the engine's stdout is piped");
+
+        // lines are read on their own thread, so a search can wait for them
+        // and watch for a stop at the same time
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut engine = ExternalEngine {
+            id_name: None,
+            child,
+            stdin,
+            lines,
+            board: Board::setup_default_board(),
+            stop: StopSignal::new(),
+            abandoned: 0,
+        };
+        engine.send(&engine_protocol::Command::Uci)?;
+        let deadline = Instant::now() + PATIENCE;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let line = engine.lines.recv_timeout(timeout).map_err(|e| {
+                io::Error::new(io::ErrorKind::TimedOut, format!("the engine didn't say uciok: {e}"))
+            })?;
+            match Response::parse(&line) {
+                Ok(Response::UciOk) => return Ok(engine),
+                Ok(Response::IdName(name)) => engine.id_name = Some(name),
+                _ => {}
+            }
+        }
+    }
+
+    fn send(&mut self, command: &engine_protocol::Command) -> io::Result<()> {
+        writeln!(self.stdin, "{command}")?;
+        self.stdin.flush()
+    }
+
+    /// Read the moves the engine wrote, playing them out from the position
+    /// being searched as far as they're legal.
+    fn read_line(&self, moves: &[CoordinateMove]) -> Vec<Move> {
+        let mut board = self.board.clone();
+        let mut line = Vec::new();
+        for movement in moves {
+            let movement = movement.to_move(&board);
+            if board.play(movement).is_err() {
+                break;
+            }
+            line.push(movement);
+        }
+        line
+    }
+}
+
+fn score(score: InfoScore) -> Score {
+    match score {
+        InfoScore::Centipawns(centipawns) => Score::Pawns(centipawns as f32 / 100.0),
+        InfoScore::Mate(moves) if moves < 0 => Score::Pawns(f32::NEG_INFINITY),
+        InfoScore::Mate(_) => Score::Pawns(f32::INFINITY),
+    }
+}
+
+impl Engine for ExternalEngine {
+    fn name(&self) -> &'static str {
+        "external"
+    }
+
+    fn new_game(&mut self) {
+        // if the engine has gone, the next search will find out
+        let _ = self.send(&engine_protocol::Command::NewGame);
+    }
+
+    /// The engine is only sent the position, so it can't see the moves that
+    /// led to it, or repetitions coming.
+    fn set_position(&mut self, board: &Board) {
+        self.board = board.clone();
+    }
+
+    fn search(&mut self, limits: &SearchLimits) -> SearchResult {
+        self.stop.reset();
+        let go = GoLimits {
+            movetime: limits.time,
+            depth: limits.depth,
+            infinite: *limits == SearchLimits::default(),
+            ..GoLimits::default()
+        };
+        let position = engine_protocol::Command::Position {
+            start: self.board.clone(),
+            moves: Vec::new(),
+        };
+        if self.send(&position).and_then(|_| self.send(&engine_protocol::Command::Go(go))).is_err() {
+            return SearchResult::game_over();
+        }
+
+        let mut info = Info::default();
+        let mut stopped_at = None;
+        loop {
+            match self.lines.recv_timeout(POLL) {
+                Ok(line) => match Response::parse(&line) {
+                    // later lines are from deeper searches, but may not say
+                    // everything the earlier ones did
+                    Ok(Response::Info(latest)) => {
+                        info = Info {
+                            depth: latest.depth.or(info.depth),
+                            score: latest.score.or(info.score),
+                            nodes: latest.nodes.or(info.nodes),
+                            time: latest.time.or(info.time),
+                            pv: if latest.pv.is_empty() { info.pv } else { latest.pv },
+                            string: None,
+                        };
+                    }
+                    Ok(Response::BestMove(_)) if self.abandoned > 0 => self.abandoned -= 1,
+                    Ok(Response::BestMove(best_move)) => {
+                        let best_move = best_move.map(|movement| movement.to_move(&self.board));
+                        let mut principal_variation = self.read_line(&info.pv);
+                        if principal_variation.first() != best_move.as_ref() {
+                            principal_variation = best_move.into_iter().collect();
+                        }
+                        return SearchResult {
+                            best_move,
+                            score: info.score.map(score),
+                            principal_variation,
+                            nodes: info.nodes.unwrap_or(0),
+                            depth: info.depth,
+                        };
+                    }
+                    _ => {}
+                },
+                Err(RecvTimeoutError::Timeout) => match stopped_at {
+                    None if self.stop.is_stopped() => {
+                        stopped_at = Some(Instant::now());
+                        let _ = self.send(&engine_protocol::Command::Stop);
+                    }
+                    // the engine isn't answering, so don't wait on it forever
+                    Some(stopped_at) if stopped_at.elapsed() > PATIENCE => {
+                        self.abandoned += 1;
+                        return SearchResult::game_over();
+                    }
+                    _ => {}
+                },
+                // the engine has gone without answering
+                Err(RecvTimeoutError::Disconnected) => return SearchResult::game_over(),
+            }
+        }
+    }
+
+    fn stop_signal(&self) -> StopSignal {
+        self.stop.clone()
+    }
+
+    /// Options are passed straight on. The protocol has no way for the
+    /// engine to refuse one, so they're always accepted.
+    fn set_option(&mut self, name: &str, value: &str) -> Result<(), OptionError> {
+        let command = engine_protocol::Command::SetOption {
+            name: name.to_string(),
+            value: value.to_string(),
+        };
+        self.send(&command).map_err(|e| OptionError::InvalidValue {
+            name: name.to_string(),
+            reason: e.to_string(),
+        })
+    }
+}
+
+impl Drop for ExternalEngine {
+    fn drop(&mut self) {
+        let _ = self.send(&engine_protocol::Command::Quit);
+        let deadline = Instant::now() + PATIENCE;
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(POLL);
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -1,9 +1,17 @@
 
 
 
+pub mod arena;
 pub mod bot_mind;
+pub mod engine;
+pub mod evaluation;
+pub mod external;
+pub mod move_ordering;
+pub mod protocol;
 pub mod random_bot;
 pub mod random_bot2;
+pub mod transposition;
+pub mod tuning;
 
 
 use hexchesscore::{Board, Color, Hexagon, Piece};
@@ -58,17 +66,28 @@ pub fn setup_test_boards() -> (Board, Board) {
 
 #[cfg(test)]
 mod tests {
-    use std::{time::Instant, fs::File, io::Write};
+    use std::{collections::HashMap, time::{Duration, Instant}, fs::File, io::Write};
 
-    use hexchesscore::{get_all_valid_moves, Board, Color, Hexagon, Piece};
-    use random_bot::get_samples;
+    use hexchesscore::{Board, Color, Hexagon, Move, Piece};
+    use bot_mind::{alpha_beta_prune, evaluate_board, negamax, quiescence, SearchState};
+    use evaluation::EvaluationWeights;
+    use random_bot::{get_samples, MonteCarloOptions, MonteCarloTree};
 
     use crate::random_bot2::SearchTree;
 
     use super::*;
 
+    /// Weights that only count material
+    fn material_only() -> EvaluationWeights {
+        EvaluationWeights {
+            material: EvaluationWeights::default().material,
+            ..EvaluationWeights::zero()
+        }
+    }
+
     #[test]
     fn test_board_evaluation() {
+        let weights = material_only();
         let mut board = Board::setup_default_board();
 
         // clear the board
@@ -82,7 +101,7 @@ mod tests {
                 color: Color::White,
             },
         );
-        assert!(evaluate_board(&board) == 9.0);
+        assert!(evaluate_board(&board, &weights) == 9.0);
 
         // add a second white queen
         board.occupied_squares.insert(
@@ -92,7 +111,7 @@ mod tests {
                 color: Color::White,
             },
         );
-        assert!(evaluate_board(&board) == 18.0);
+        assert!(evaluate_board(&board, &weights) == 18.0);
 
         // now add a black queen
         board.occupied_squares.insert(
@@ -102,17 +121,32 @@ mod tests {
                 color: Color::Black,
             },
         );
-        assert!(evaluate_board(&board) == 9.0);
+        assert!(evaluate_board(&board, &weights) == 9.0);
     }
 
     #[test]
     fn test_alpha_beta_pruning_matches_minimax() {
+        check_alpha_beta_pruning_matches_minimax(&[0, 1, 2]);
+    }
+
+    // both searches finish with a quiescence search, which minimax can't
+    // prune, so going a ply deeper takes minutes
+    #[test]
+    #[ignore = "slow - run with --release -- --ignored"]
+    fn test_alpha_beta_pruning_matches_minimax_deeper() {
+        check_alpha_beta_pruning_matches_minimax(&[3]);
+    }
+
+    fn check_alpha_beta_pruning_matches_minimax(depths: &[i8]) {
         let (board, board2) = setup_test_boards();
 
         for mut board in [board, board2] {
-            for depth in [0, 1, 2, 3] {
+            // one table for every search, so they can reuse each other's results
+            let mut state = SearchState::new(1);
+            for &depth in depths {
                 for color in [Color::Black, Color::White] {
                     board.current_player = color;
+                    board.refresh_hash();
                     dbg!(color);
                     dbg!(board.current_player);
                     let a_eval = alpha_beta_prune(
@@ -120,11 +154,13 @@ mod tests {
                         depth,
                         f32::NEG_INFINITY,
                         f32::INFINITY,
+                        0,
+                        &mut state,
                         Instant::now() + Duration::from_millis(100000),
                     )
                     .expect("timed out!");
                     dbg!(board.current_player);
-                    let nega_eval = negamax(&mut board, depth);
+                    let nega_eval = negamax(&mut board, depth, &state.weights);
                     dbg!(depth);
                     dbg!(a_eval);
                     dbg!(nega_eval);
@@ -135,33 +171,140 @@ mod tests {
             }
         }
     }
+    #[test]
+    fn test_quiescence_sees_the_recapture() {
+        let mut board = Board::new();
+        for (hex, piece_type, color) in [
+            ("F1", hexchesscore::PieceType::King, Color::White),
+            ("F11", hexchesscore::PieceType::King, Color::Black),
+            ("F5", hexchesscore::PieceType::Queen, Color::White),
+            ("F7", hexchesscore::PieceType::Pawn, Color::Black),
+            ("G7", hexchesscore::PieceType::Pawn, Color::Black),
+        ] {
+            board.occupied_squares.insert(Hexagon::new(hex).unwrap(), Piece { piece_type, color });
+        }
+        board.refresh_hash();
+
+        // the pawn on F7 is defended, so taking it loses the queen
+        let greedy = Move {
+            start_hex: Hexagon::new("F5").unwrap(),
+            final_hex: Hexagon::new("F7").unwrap(),
+            final_piece: hexchesscore::PieceType::Queen,
+        };
+        let mut after = board.clone();
+        after.play(greedy).unwrap();
+        let weights = material_only();
+        assert_eq!(evaluate_board(&after, &weights), -8.0);
+        assert_eq!(quiescence(&mut after, f32::NEG_INFINITY, f32::INFINITY, &weights), 1.0);
+
+        assert_ne!(bot_mind::iterative_deepening(&mut board, 1, 100000, &mut SearchState::new(1)), greedy);
+    }
+
+    #[test]
+    fn test_search_remembers_positions_between_moves() {
+        let mut board = Board::setup_default_board();
+        let mut state = SearchState::new(16);
+        let chosen = bot_mind::iterative_deepening(&mut board, 3, 100000, &mut state);
+        let entry = *state.table.probe(board.hash()).expect("the root wasn't stored");
+        assert_eq!(entry.best_move, Some(chosen));
+        assert_eq!(entry.depth, 3);
+
+        // after both sides move, the table still knows the new position
+        board.play(chosen).unwrap();
+        let reply = board.legal_moves()[0];
+        board.play(reply).unwrap();
+        assert!(state.table.probe(board.hash()).is_some());
+        let next = bot_mind::iterative_deepening(&mut board, 3, 100000, &mut state);
+        assert!(board.legal_moves().contains(&next));
+    }
+
     #[test]
     fn test_random_sampling() {
         let n = 1000;
         let num_moves = 20;
         let num_samples = 50;
-        let samples: Vec<Vec<u16>> = (0..n)
-            .map(|x| {
+        let moves: Vec<Move> = Board::setup_default_board().legal_moves()[..num_moves].to_vec();
+        let samples: Vec<HashMap<Move, u64>> = (0..n)
+            .map(|_| {
                 get_samples(
-                    num_samples as f32,
-                    (0..num_moves).map(|x| x as f32).collect(),
+                    num_samples,
+                    moves.iter().enumerate().map(|(x, m)| (*m, x as f32)).collect(),
                 )
             })
             .collect();
         for sample in &samples {
-            let total: u16 = sample.iter().sum();
-            assert!(total == num_samples as u16);
+            let total: u64 = sample.values().sum();
+            assert!(total == num_samples);
         }
-        let stats = samples.iter().fold(vec![0; num_moves], |acc, s| {
-            acc.iter().zip(s).map(|(x, y)| x + y).collect()
-        });
+        let stats: Vec<u64> = moves
+            .iter()
+            .map(|m| samples.iter().map(|s| s.get(m).copied().unwrap_or(0)).sum())
+            .collect();
         dbg!(&stats);
     }
 
+    #[test]
+    fn test_random_sampling_past_u16() {
+        let moves = Board::setup_default_board().legal_moves();
+        let bias = moves.iter().map(|m| (*m, 1.0)).collect();
+        let sample = get_samples(100_000, bias);
+        assert_eq!(sample.values().sum::<u64>(), 100_000);
+    }
+
+    #[test]
+    fn test_random_sampling_without_any_bias() {
+        // a fresh node's children all have a bias of 0
+        let moves = Board::setup_default_board().legal_moves();
+        let bias = moves.iter().map(|m| (*m, 0.0)).collect();
+        let sample = get_samples(1000, bias);
+        assert_eq!(sample.len(), moves.len());
+        assert_eq!(sample.values().sum::<u64>(), 1000);
+    }
+
+    #[test]
+    fn test_tree_search_keeps_its_tree_between_moves() {
+        let options = MonteCarloOptions {
+            batch_size: 200,
+            expand_cutoff: 2,
+            rollout_plies: 10,
+            ..MonteCarloOptions::default()
+        };
+        let mut tree = MonteCarloTree::new(options);
+        let mut board = Board::setup_default_board();
+        // no time at all still plays one batch
+        let chosen = random_bot::make_a_move(&mut board, 0, &mut tree);
+        {
+            let root = tree.root().read().unwrap();
+            assert_eq!(root.tally, 200);
+            // every game hands out four points
+            assert_eq!(root.player_score + root.opponent_score, 4 * root.tally);
+        }
+
+        // the bot's move was played often enough to get its own node, so
+        // after any reply that was tried, those games are kept
+        let child = tree.root().read().unwrap().children[&chosen].clone();
+        let (reply, grandchild) = child
+            .read()
+            .unwrap()
+            .children
+            .iter()
+            .find(|(_, node)| node.read().unwrap().tally > 0)
+            .map(|(reply, node)| (*reply, node.clone()))
+            .expect("no replies were tried");
+        board.play(chosen).unwrap();
+        board.play(reply).unwrap();
+        tree.reroot(&board);
+        assert!(std::sync::Arc::ptr_eq(tree.root(), &grandchild));
+
+        // an unrelated position starts again
+        tree.reroot(&Board::new());
+        assert_eq!(tree.root().read().unwrap().tally, 0);
+    }
+
     #[test]
     fn test_tree_search() {
         let mut board = Board::setup_default_board();
-        random_bot::make_a_move(&mut board, 100000);
+        random_bot::make_a_move(&mut board, 100000, &mut MonteCarloTree::new(MonteCarloOptions::default()));
     }
 
     #[test]
@@ -201,7 +344,7 @@ mod tests {
         board.current_player = Color::Black;
         output_board_representation(&board);
         
-        dbg!(random_bot::make_a_move(&mut board, 1000));
+        dbg!(random_bot::make_a_move(&mut board, 1000, &mut MonteCarloTree::new(MonteCarloOptions::default())));
     }
     
     fn output_board_representation(board: &Board) {
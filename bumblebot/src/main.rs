@@ -1,5 +1,5 @@
 use std::{
-    env,
+    env, io,
     net::TcpStream,
     thread::{self},
 };
@@ -20,6 +20,9 @@ use api::{IncomingMessage, OutgoingMessage, PlayerColor};
 use bumblebot::{
     bot_mind::iterative_deepening,
     bot_mind::make_a_move,
+    bot_mind::{SearchState, TABLE_MEGABYTES},
+    engine::{engine_by_name, Engine, SearchLimits, ENGINE_NAMES},
+    protocol,
     setup_test_boards,
 };
 
@@ -33,13 +36,34 @@ fn match_player_color(color: PlayerColor) -> Color {
     }
 }
 
+/// Read a message from the server. A board in it is checked before anything
+/// searches from it - and one with hexagons off the board can't be read at
+/// all.
+fn read_message(text: &str) -> Result<OutgoingMessage, String> {
+    let decoded: OutgoingMessage =
+        serde_json::from_str(text).map_err(|e| format!("couldn't read the message: {e}"))?;
+    if let OutgoingMessage::BoardState { board, .. } = &decoded {
+        board
+            .validate()
+            .map_err(|problems| format!("received an invalid board: {problems:?}"))?;
+    }
+    Ok(decoded)
+}
+
 async fn handle_message(
     message: tungstenite::Message,
     user_id: Uuid,
     current_color: &mut Color,
     socket: &mut WebSocket<MaybeTlsStream<TcpStream>>,
+    engine: &mut dyn Engine,
 ) {
-    let decoded: OutgoingMessage = serde_json::from_str(&message.into_text().unwrap()).unwrap();
+    let decoded = match message.into_text().map_err(|e| e.to_string()).and_then(|text| read_message(&text)) {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            eprintln!("{e}");
+            return;
+        }
+    };
     match decoded {
         OutgoingMessage::JoinGameSuccess { color, session: _ } => {
             *current_color = match_player_color(color);
@@ -52,20 +76,24 @@ async fn handle_message(
                 .unwrap(),
             ));
         }
-        OutgoingMessage::BoardState { mut board } => {
-            if board.current_player == *current_color {
-                let intended_move = make_a_move(&mut board, 2000);
-                let _ = socket.send(tungstenite::Message::Text(
-                    serde_json::to_string(&IncomingMessage::RegisterMove {
-                        user_id: user_id.to_string(),
-                        start_hexagon: intended_move.start_hex,
-                        final_hexagon: intended_move.final_hex,
-                        // TODO fix the behaviour around promotions
-                        promotion_choice: None,
-                    })
-                    .unwrap(),
-                ));
-            }
+        OutgoingMessage::BoardState { board, .. } if board.current_player == *current_color => {
+            engine.set_position(&board);
+            let Some(intended_move) = engine.search(&SearchLimits::time_ms(2000)).best_move else {
+                // the game is over
+                return;
+            };
+            let _ = socket.send(tungstenite::Message::Text(
+                serde_json::to_string(&IncomingMessage::RegisterMove {
+                    user_id: user_id.to_string(),
+                    start_hexagon: intended_move.start_hex,
+                    final_hexagon: intended_move.final_hex,
+                    promotion_choice: Some(intended_move.final_piece),
+                })
+                .unwrap(),
+            ));
+        }
+        OutgoingMessage::MoveRejected { reason } => {
+            eprintln!("move rejected: {reason}");
         }
         _ => {}
     }
@@ -74,7 +102,7 @@ async fn handle_message(
 pub fn spawn_bot(_tx: &mpsc::UnboundedSender<Message>) {
     let (mut board, mut board2) = setup_test_boards();
     board.current_player = board.current_player.invert();
-    iterative_deepening(&mut board2, 3, 1000);
+    iterative_deepening(&mut board2, 3, 1000, &mut SearchState::new(TABLE_MEGABYTES));
     // iterative_deepening(&mut board2, 3, tx);
     // loop {
     //     send_board(tx, board.clone());
@@ -136,7 +164,16 @@ async fn handle_websocket_async(socket: warp::ws::WebSocket) {
 #[tokio::main]
 async fn main() {
     let args: Vec<String> = env::args().collect();
-    dbg!(&args);
+
+    // `bumblebot protocol [engine name]` plays through the engine protocol on
+    // stdin and stdout instead of joining a game on the server
+    if args.get(1).map(String::as_str) == Some("protocol") {
+        let name = args.get(2).map_or(ENGINE_NAMES[0], String::as_str);
+        let engine = engine_by_name(name)
+            .unwrap_or_else(|| panic!("unknown engine {name}, expected one of {ENGINE_NAMES:?}"));
+        protocol::run(engine, io::stdin().lock(), io::stdout()).expect("couldn't talk over stdin and stdout");
+        return;
+    }
 
     if args.len() > 1 {
         let (mut socket, _response) =
@@ -151,6 +188,20 @@ async fn main() {
 
         // initialize the session_id with something useless
         let mut current_color = Color::Black;
+        // kept for the whole game, so each search can reuse the last one's work
+        let name = args.get(2).map_or(ENGINE_NAMES[0], String::as_str);
+        let mut engine = engine_by_name(name)
+            .unwrap_or_else(|| panic!("unknown engine {name}, expected one of {ENGINE_NAMES:?}"));
+        engine.new_game();
+        // settings can be tried out without rebuilding the bot, like tuned
+        // evaluation weights or a different exploration constant
+        for (variable, option) in [("BUMBLEBOT_WEIGHTS", "weights"), ("BUMBLEBOT_EXPLORATION", "exploration")] {
+            if let Ok(value) = env::var(variable) {
+                engine
+                    .set_option(option, &value)
+                    .unwrap_or_else(|e| panic!("couldn't use {variable} with the {name} engine: {e}"));
+            }
+        }
 
         socket.send(tungstenite::Message::Text(
             serde_json::to_string(&message).expect("Couldn't serialize message"),
@@ -158,10 +209,10 @@ async fn main() {
 
         loop {
             let msg = socket.read().expect("Error reading WS message");
-            handle_message(msg, user_id, &mut current_color, &mut socket).await;
+            handle_message(msg, user_id, &mut current_color, &mut socket, engine.as_mut()).await;
         }
     } else {
-        make_a_move(&mut Board::setup_default_board(), 100000);
+        make_a_move(&mut Board::setup_default_board(), 100000, &mut SearchState::new(TABLE_MEGABYTES));
     }
     let websocket =
         warp::path("ws")
@@ -174,3 +225,27 @@ async fn main() {
         .run(([127, 0, 0, 1], 7878))
         .await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boards_from_the_server_are_checked() {
+        let message = OutgoingMessage::BoardState {
+            board: Board::setup_default_board(),
+            last_move: None,
+        };
+        assert!(read_message(&serde_json::to_string(&message).unwrap()).is_ok());
+
+        let off_board = r#"{"op": "BoardState", "last_move": null, "board": {"occupied_squares":
+            {"l11": {"piece_type": "King", "color": "White"}}, "en_passant": null, "current_player": "White"}}"#;
+        let error = read_message(off_board).unwrap_err();
+        assert!(error.contains("l11 is not on the board"), "{error}");
+
+        let no_kings = r#"{"op": "BoardState", "last_move": null, "board": {"occupied_squares":
+            {"f5": {"piece_type": "Pawn", "color": "White"}}, "en_passant": null, "current_player": "White"}}"#;
+        let error = read_message(no_kings).unwrap_err();
+        assert!(error.starts_with("received an invalid board"), "{error}");
+    }
+}
@@ -0,0 +1,220 @@
+//! Deciding which moves the search should try first.
+//!
+//! Alpha-beta pruning cuts off the most when the best move is searched
+//! first. Captures are tried in most valuable victim / least valuable
+//! attacker order, then the killer moves that caused cutoffs at the same
+//! ply, then the remaining quiet moves by how often they've caused cutoffs
+//! anywhere in the tree.
+
+use std::cmp::Reverse;
+
+use hexchesscore::geometry::{self, NUMBER_OF_HEXES};
+use hexchesscore::{convert_en_passant_to_virtual_pawn, Board, Move, PieceType};
+
+use crate::bot_mind::piece_value;
+
+// ordering scores for each kind of move, highest first. History scores are
+// capped below the killer moves.
+const BEST_MOVE_SCORE: u32 = u32::MAX;
+const TACTICAL_SCORE: u32 = 1 << 30;
+const KILLER_SCORES: [u32; 2] = [1 << 29, (1 << 29) - 1];
+const HISTORY_LIMIT: u32 = (1 << 29) - 2;
+
+/// The piece taken by `movement`, including pawns taken en passant.
+pub fn captured_piece(board: &Board, movement: &Move) -> Option<PieceType> {
+    if let Some(piece) = board.occupied_squares.get(&movement.final_hex) {
+        return Some(piece.piece_type);
+    }
+    let piece = board.occupied_squares.get(&movement.start_hex)?;
+    // pawns only change rank when capturing
+    let en_passant = piece.piece_type == PieceType::Pawn
+        && movement.start_hex.rank != movement.final_hex.rank
+        && board.en_passant == Some(convert_en_passant_to_virtual_pawn(&movement.final_hex, piece.color));
+    en_passant.then_some(PieceType::Pawn)
+}
+
+/// Whether `movement` changes the material on the board - a capture or a
+/// promotion. These are the moves the quiescence search looks at.
+pub fn is_tactical(board: &Board, movement: &Move) -> bool {
+    captured_piece(board, movement).is_some() || is_promotion(board, movement)
+}
+
+fn is_promotion(board: &Board, movement: &Move) -> bool {
+    board
+        .occupied_squares
+        .get(&movement.start_hex)
+        .is_some_and(|piece| piece.piece_type != movement.final_piece)
+}
+
+/// Most valuable victim, least valuable attacker. Taking a queen with a
+/// pawn scores higher than taking a pawn with a queen, and promoting counts
+/// as capturing the piece the pawn turns into.
+pub fn mvv_lva(board: &Board, movement: &Move) -> u32 {
+    let Some(attacker) = board.occupied_squares.get(&movement.start_hex) else {
+        return 0;
+    };
+    let mut gain = captured_piece(board, movement).map_or(0.0, piece_value);
+    if attacker.piece_type != movement.final_piece {
+        gain += piece_value(movement.final_piece) - piece_value(PieceType::Pawn);
+    }
+    // piece values are whole numbers below 10, so this never goes negative
+    (10.0 * (gain + 1.0) - piece_value(attacker.piece_type)) as u32
+}
+
+/// Put the captures and promotions in `moves` in most valuable victim,
+/// least valuable attacker order.
+pub fn order_tactical_moves(board: &Board, moves: &mut [Move]) {
+    moves.sort_by_cached_key(|movement| Reverse(mvv_lva(board, movement)));
+}
+
+/// What the search has learnt about quiet moves so far.
+#[derive(Debug, Clone)]
+pub struct MoveOrdering {
+    /// The last two quiet moves to cause a cutoff at each ply
+    killers: Vec<[Option<Move>; 2]>,
+    /// How much each quiet move has caused cutoffs, by start and final hexagon
+    history: Vec<u32>,
+}
+
+impl Default for MoveOrdering {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MoveOrdering {
+    pub fn new() -> MoveOrdering {
+        MoveOrdering {
+            killers: Vec::new(),
+            history: vec![0; NUMBER_OF_HEXES * NUMBER_OF_HEXES],
+        }
+    }
+
+    fn history_index(movement: &Move) -> usize {
+        geometry::index(&movement.start_hex) * NUMBER_OF_HEXES + geometry::index(&movement.final_hex)
+    }
+
+    pub fn killers(&self, ply: usize) -> [Option<Move>; 2] {
+        self.killers.get(ply).copied().unwrap_or_default()
+    }
+
+    pub fn history(&self, movement: &Move) -> u32 {
+        self.history[Self::history_index(movement)]
+    }
+
+    /// Sort `moves` so the likeliest to cause a cutoff come first, starting
+    /// with `best_move` if it's one of them.
+    pub fn order(&self, board: &Board, moves: &mut [Move], ply: usize, best_move: Option<Move>) {
+        let killers = self.killers(ply);
+        moves.sort_by_cached_key(|movement| {
+            let score = if Some(*movement) == best_move {
+                BEST_MOVE_SCORE
+            } else if is_tactical(board, movement) {
+                TACTICAL_SCORE + mvv_lva(board, movement)
+            } else if let Some(slot) = killers.iter().position(|killer| *killer == Some(*movement)) {
+                KILLER_SCORES[slot]
+            } else {
+                self.history(movement).min(HISTORY_LIMIT)
+            };
+            Reverse(score)
+        });
+    }
+
+    /// Remember that the quiet move `movement` caused a cutoff at `ply`,
+    /// with `depth` plies left to search.
+    pub fn record_cutoff(&mut self, movement: Move, ply: usize, depth: i8) {
+        if self.killers.len() <= ply {
+            self.killers.resize(ply + 1, [None; 2]);
+        }
+        let killers = &mut self.killers[ply];
+        if killers[0] != Some(movement) {
+            killers[1] = killers[0];
+            killers[0] = Some(movement);
+        }
+        // cutoffs near the root prune more of the tree, so count for more
+        let depth = depth.max(0) as u32;
+        let entry = &mut self.history[Self::history_index(&movement)];
+        *entry = entry.saturating_add(depth * depth);
+    }
+
+    /// Forget the killer moves and fade the history, ready to search a new
+    /// position. The history is still worth something after a move is
+    /// played, so it's halved rather than cleared.
+    pub fn new_search(&mut self) {
+        self.killers.clear();
+        for entry in self.history.iter_mut() {
+            *entry /= 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hexchesscore::{Color, Hexagon, Piece};
+
+    fn hex(name: &str) -> Hexagon {
+        Hexagon::new(name).unwrap()
+    }
+
+    fn place(board: &mut Board, name: &str, piece_type: PieceType, color: Color) {
+        board.occupied_squares.insert(hex(name), Piece { piece_type, color });
+    }
+
+    fn find_move(board: &Board, start: &str, end: &str) -> Move {
+        board
+            .legal_moves_from(&hex(start))
+            .into_iter()
+            .find(|movement| movement.final_hex == hex(end))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_captures_are_ordered_by_victim_then_attacker() {
+        let mut board = Board::new();
+        place(&mut board, "A1", PieceType::King, Color::White);
+        place(&mut board, "F11", PieceType::King, Color::Black);
+        place(&mut board, "F5", PieceType::Queen, Color::White);
+        place(&mut board, "E5", PieceType::Pawn, Color::White);
+        place(&mut board, "D5", PieceType::Pawn, Color::Black);
+        place(&mut board, "H3", PieceType::Pawn, Color::Black);
+        place(&mut board, "F8", PieceType::Rook, Color::Black);
+        board.refresh_hash();
+
+        let queen_takes_rook = find_move(&board, "F5", "F8");
+        let pawn_takes_pawn = find_move(&board, "E5", "D5");
+        let queen_takes_pawn = find_move(&board, "F5", "H3");
+        assert_eq!(captured_piece(&board, &queen_takes_rook), Some(PieceType::Rook));
+        assert!(mvv_lva(&board, &queen_takes_rook) > mvv_lva(&board, &pawn_takes_pawn));
+        assert!(mvv_lva(&board, &pawn_takes_pawn) > mvv_lva(&board, &queen_takes_pawn));
+
+        let mut moves = board.legal_moves();
+        MoveOrdering::new().order(&board, &mut moves, 0, None);
+        assert_eq!(&moves[..3], &[queen_takes_rook, pawn_takes_pawn, queen_takes_pawn]);
+        assert!(!is_tactical(&board, &moves[3]));
+    }
+
+    #[test]
+    fn test_killers_and_history_come_before_other_quiet_moves() {
+        let board = Board::setup_default_board();
+        let mut moves = board.legal_moves();
+        let killer = moves[20];
+        let old_killer = moves[30];
+        let historic = moves[40];
+        let best_move = moves[50];
+
+        let mut ordering = MoveOrdering::new();
+        ordering.record_cutoff(historic, 5, 4);
+        ordering.record_cutoff(old_killer, 2, 1);
+        ordering.record_cutoff(killer, 2, 1);
+        assert_eq!(ordering.killers(2), [Some(killer), Some(old_killer)]);
+
+        ordering.order(&board, &mut moves, 2, Some(best_move));
+        assert_eq!(&moves[..4], &[best_move, killer, old_killer, historic]);
+
+        // a new search keeps some of the history, but none of the killers
+        ordering.new_search();
+        assert_eq!(ordering.killers(2), [None, None]);
+        assert_eq!(ordering.history(&historic), 8);
+    }
+}
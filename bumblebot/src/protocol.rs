@@ -0,0 +1,313 @@
+//! Speaks the engine protocol from [`hexchesscore::engine_protocol`], so
+//! bumblebot can be run as a subprocess by anything that drives engines
+//! that way.
+//!
+//! Searches run on their own thread, so `stop` and `isready` are answered
+//! while one is going. Any other command stops the search first. When the
+//! input runs out, a search with a limit is left to finish, so commands can
+//! be piped in.
+
+use std::{
+    io::{self, BufRead, Write},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use hexchesscore::{
+    engine_protocol::{play_moves, Command, CoordinateMove, GoLimits, Info, InfoScore, Response},
+    Board, Color, Move,
+};
+
+use crate::engine::{Engine, Score, SearchLimits, SearchResult, StopSignal};
+
+/// How many more moves the engine plans for when it's only told the time
+/// left on its clock
+const MOVES_TO_GO: u32 = 30;
+
+type Output<W> = Arc<Mutex<W>>;
+
+fn send<W: Write>(output: &Output<W>, response: Response) -> io::Result<()> {
+    let mut output = output.lock().unwrap();
+    writeln!(output, "{response}")?;
+    output.flush()
+}
+
+/// How long to search for, given what `go` said and whose move it is.
+pub fn search_limits(limits: &GoLimits, to_move: Color) -> SearchLimits {
+    let (clock, increment) = match to_move {
+        Color::White => (limits.white_time, limits.white_increment),
+        Color::Black => (limits.black_time, limits.black_increment),
+    };
+    let time = if limits.infinite {
+        None
+    } else if limits.movetime.is_some() {
+        limits.movetime
+    } else {
+        // plan to use a share of the clock, plus most of what comes back,
+        // without ever running it down
+        clock.map(|clock| {
+            let planned = clock / MOVES_TO_GO + increment.unwrap_or_default() / 2;
+            planned.min(clock / 2)
+        })
+    };
+    SearchLimits {
+        time,
+        depth: limits.depth,
+    }
+}
+
+/// The score as the protocol writes it.
+pub fn info_score(score: Score, principal_variation: &[Move]) -> InfoScore {
+    match score {
+        Score::Pawns(pawns) if pawns.is_infinite() => {
+            // the variation ends with the mate, so count the winner's moves
+            let moves = (principal_variation.len() as i32 + 1) / 2;
+            InfoScore::Mate(moves.max(1) * pawns.signum() as i32)
+        }
+        Score::Pawns(pawns) => InfoScore::Centipawns((pawns * 100.0).round() as i32),
+        Score::Expectation(expectation) => {
+            // the usual logistic link between centipawns and expected points
+            let expectation = expectation.clamp(0.001, 0.999);
+            InfoScore::Centipawns((400.0 * (expectation / (1.0 - expectation)).log10()).round() as i32)
+        }
+    }
+}
+
+/// What the engine says once it has finished searching `board`.
+fn result_responses(board: &Board, result: &SearchResult, elapsed: Duration) -> [Response; 2] {
+    let mut pv = Vec::new();
+    let mut line = board.clone();
+    for movement in &result.principal_variation {
+        pv.push(CoordinateMove::from_move(&line, *movement));
+        if line.play(*movement).is_err() {
+            break;
+        }
+    }
+    let info = Info {
+        depth: result.depth,
+        score: result.score.map(|score| info_score(score, &result.principal_variation)),
+        nodes: Some(result.nodes),
+        time: Some(elapsed),
+        pv,
+        string: None,
+    };
+    let best_move = result.best_move.map(|movement| CoordinateMove::from_move(board, movement));
+    [Response::Info(info), Response::BestMove(best_move)]
+}
+
+/// The engine, either waiting for a command or off searching.
+enum State {
+    Idle(Box<dyn Engine>),
+    /// The search, how to stop it, and whether it will stop by itself
+    Searching(JoinHandle<Box<dyn Engine>>, StopSignal, bool),
+}
+
+impl State {
+    /// Stop any search, and wait for the engine to be free.
+    fn finish(self) -> Box<dyn Engine> {
+        match self {
+            State::Idle(engine) => engine,
+            State::Searching(search, stop, _) => {
+                // the engine clears the signal as its search starts, so keep
+                // sending it in case that hadn't happened yet
+                while !search.is_finished() {
+                    stop.stop();
+                    thread::sleep(Duration::from_millis(1));
+                }
+                search.join().expect("the search panicked")
+            }
+        }
+    }
+
+    /// Wait for a search with a limit to reach it, and stop any other.
+    fn wait(self) -> Box<dyn Engine> {
+        match self {
+            State::Searching(search, _, true) => search.join().expect("the search panicked"),
+            state => state.finish(),
+        }
+    }
+}
+
+/// Read commands from `input` and answer them on `output`, until told to
+/// quit or the input runs out.
+pub fn run<W>(engine: Box<dyn Engine>, input: impl BufRead, output: W) -> io::Result<()>
+where
+    W: Write + Send + 'static,
+{
+    let output = Arc::new(Mutex::new(output));
+    let mut state = State::Idle(engine);
+    let mut board = Board::setup_default_board();
+
+    let mut quit = false;
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let command = match Command::parse(&line) {
+            Ok(command) => command,
+            Err(e) => {
+                send(&output, Response::Info(Info { string: Some(e.to_string()), ..Info::default() }))?;
+                continue;
+            }
+        };
+        match command {
+            Command::IsReady => {
+                send(&output, Response::ReadyOk)?;
+                continue;
+            }
+            Command::Quit => {
+                quit = true;
+                break;
+            }
+            _ => {}
+        }
+
+        let mut engine = state.finish();
+        let mut problem = None;
+        match command {
+            Command::Uci => {
+                send(&output, Response::IdName(format!("bumblebot {}", engine.name())))?;
+                send(&output, Response::IdAuthor("the hexchess developers".to_string()))?;
+                send(&output, Response::UciOk)?;
+            }
+            Command::NewGame => engine.new_game(),
+            Command::SetOption { name, value } => {
+                problem = engine.set_option(&name, &value).err().map(|e| e.to_string());
+            }
+            Command::Position { start, moves } => match play_moves(&start, &moves) {
+                Ok(position) => board = position,
+                Err(e) => problem = Some(e.to_string()),
+            },
+            Command::Go(limits) => {
+                let limits = search_limits(&limits, board.current_player);
+                let bounded = limits != SearchLimits::default();
+                let stop = engine.stop_signal();
+                let output = output.clone();
+                let board = board.clone();
+                let search = thread::spawn(move || {
+                    let started = Instant::now();
+                    engine.set_position(&board);
+                    let result = engine.search(&limits);
+                    for response in result_responses(&board, &result, started.elapsed()) {
+                        // if the controller has gone there's no one to tell
+                        let _ = send(&output, response);
+                    }
+                    engine
+                });
+                state = State::Searching(search, stop, bounded);
+                continue;
+            }
+            // a search that's already finished has nothing more to say
+            Command::Stop | Command::IsReady | Command::Quit => {}
+        }
+        if let Some(problem) = problem {
+            send(&output, Response::Info(Info { string: Some(problem), ..Info::default() }))?;
+        }
+        state = State::Idle(engine);
+    }
+
+    if quit {
+        state.finish();
+    } else {
+        state.wait();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::engine_by_name;
+
+    /// Output that can still be read once `run` has taken it
+    #[derive(Clone, Default)]
+    struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn run_script(engine: &str, script: &str) -> Vec<Response> {
+        let output = SharedOutput::default();
+        run(engine_by_name(engine).unwrap(), script.as_bytes(), output.clone()).unwrap();
+        let text = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        text.lines().map(|line| Response::parse(line).unwrap()).collect()
+    }
+
+    fn best_move(responses: &[Response]) -> Option<CoordinateMove> {
+        match responses.last() {
+            Some(Response::BestMove(movement)) => *movement,
+            other => panic!("expected a best move, not {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_searching_to_a_depth() {
+        let responses = run_script("alphabeta", "uci\nisready\nucinewgame\nposition startpos moves e4e5\ngo depth 2\n");
+        assert_eq!(responses[..4], [
+            Response::IdName("bumblebot alphabeta".to_string()),
+            Response::IdAuthor("the hexchess developers".to_string()),
+            Response::UciOk,
+            Response::ReadyOk,
+        ]);
+        let Response::Info(info) = &responses[4] else {
+            panic!("expected the search's info, not {:?}", responses[4]);
+        };
+        assert_eq!(info.depth, Some(2));
+        assert!(matches!(info.score, Some(InfoScore::Centipawns(_))));
+
+        let board = play_moves(&Board::setup_default_board(), &["e4e5".parse().unwrap()]).unwrap();
+        let movement = best_move(&responses).unwrap().to_move(&board);
+        assert!(board.legal_moves().contains(&movement));
+        assert_eq!(info.pv.first().map(|first| first.to_move(&board)), Some(movement));
+    }
+
+    #[test]
+    fn test_stopping_an_infinite_search() {
+        for engine in ["alphabeta", "mcts"] {
+            let responses = run_script(engine, "position startpos\ngo infinite\nisready\nstop\n");
+            assert_eq!(responses[0], Response::ReadyOk);
+            assert!(best_move(&responses).is_some());
+        }
+    }
+
+    #[test]
+    fn test_problems_are_reported() {
+        let responses = run_script(
+            "mcts",
+            "castle kingside\nsetoption name colour value blue\nposition startpos moves e7e6\n",
+        );
+        assert_eq!(responses.len(), 3);
+        for response in responses {
+            assert!(matches!(response, Response::Info(Info { string: Some(_), .. })));
+        }
+    }
+
+    #[test]
+    fn test_clock_time() {
+        let limits = GoLimits {
+            white_time: Some(Duration::from_secs(60)),
+            black_time: Some(Duration::from_millis(900)),
+            white_increment: Some(Duration::from_secs(2)),
+            ..GoLimits::default()
+        };
+        assert_eq!(search_limits(&limits, Color::White).time, Some(Duration::from_secs(3)));
+        assert_eq!(search_limits(&limits, Color::Black).time, Some(Duration::from_millis(30)));
+        let infinite = GoLimits { infinite: true, ..limits };
+        assert_eq!(search_limits(&infinite, Color::White), SearchLimits::default());
+
+        assert_eq!(info_score(Score::Pawns(1.5), &[]), InfoScore::Centipawns(150));
+        assert_eq!(info_score(Score::Expectation(0.5), &[]), InfoScore::Centipawns(0));
+        let line = Board::setup_default_board().legal_moves()[..3].to_vec();
+        assert_eq!(info_score(Score::Pawns(f32::INFINITY), &line), InfoScore::Mate(2));
+        assert_eq!(info_score(Score::Pawns(f32::NEG_INFINITY), &line[..2]), InfoScore::Mate(-1));
+    }
+}
@@ -3,16 +3,15 @@ use rand::{thread_rng, Rng, seq::SliceRandom};
 use rand_distr::{Distribution, WeightedIndex};
 use rayon::prelude::*;
 use std::{
+    cell::Cell,
     collections::HashMap,
     sync::{Arc, RwLock}, time::{Instant, Duration}, fs::File, io::Write,
 };
 
-const EXPLORATION_PARAMETER: f32 = 1.414;
-
-const EXPAND_TREE_CUTOFF: u16 = 100;
+use crate::engine::{parse_option, Engine, OptionError, Score, SearchLimits, SearchResult, StopSignal};
 
 use hexchesscore::{
-    apply_move, check_for_mates, get_all_valid_moves, Board, Move,
+    apply_move, check_for_game_end, is_insufficient_material, Board, GameResult, Move,
 };
 
 // batched monte-carlo tree-search
@@ -30,66 +29,97 @@ use hexchesscore::{
 //      update the node's average score
 //      return the average score
 
-#[derive(Debug)]
+/// How the tree search spends its time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MonteCarloOptions {
+    /// The UCT exploration constant. Higher values spread the samples more
+    /// evenly, lower values pile them onto the moves doing best so far.
+    pub exploration: f32,
+    /// How many random games each batch plays
+    pub batch_size: u64,
+    /// Moves given at least this many samples in a batch get their own
+    /// node in the tree, rather than a random game each
+    pub expand_cutoff: u64,
+    /// Random games still going after this many plies are counted as draws
+    pub rollout_plies: u32,
+}
+
+impl Default for MonteCarloOptions {
+    fn default() -> Self {
+        MonteCarloOptions {
+            exploration: 1.414,
+            batch_size: 50,
+            expand_cutoff: 100,
+            rollout_plies: 800,
+        }
+    }
+}
+
+/// Points out of four for the player who made the last move, and for the
+/// player left to move, when a game has finished.
+fn final_points(result: GameResult) -> (u64, u64) {
+    match result {
+        GameResult::Checkmate => (4, 0),
+        // stalemate is 3/4 of a win
+        GameResult::Stalemate => (3, 1),
+        // every other draw is half a win for each player
+        _ => (2, 2),
+    }
+}
+
+#[derive(Debug, Default)]
 pub struct ScoreBoard {
     pub children: HashMap<Move, Arc<RwLock<ScoreBoard>>>,
-    pub player_score: u16,
-    pub opponent_score: u16,
-    pub tally: u16,
+    /// Points for the player who moved into this position, four per game
+    pub player_score: u64,
+    /// Points for the player to move in this position
+    pub opponent_score: u64,
+    /// How many games have been played through this position
+    pub tally: u64,
 }
 
 impl ScoreBoard {
     pub fn new() -> ScoreBoard {
-        ScoreBoard {
-            children: HashMap::<Move, Arc<RwLock<ScoreBoard>>>::new(),
-            player_score: 0,
-            opponent_score: 0,
-            tally: 1, // initialize to 1 to prevent div by 0 and log(0)
-        }
+        ScoreBoard::default()
     }
     // handle creating a new scoreboard for this node if one doesn't already exist,
     pub fn retrieve_scoreboard(&mut self, movement: &Move) -> Arc<RwLock<ScoreBoard>> {
-        if !self.children.contains_key(movement) {
-            self.children
-                .insert(*movement, Arc::new(RwLock::new(ScoreBoard::new())));
-        }
-        Arc::clone(
-            self.children
-                .get(&movement)
-                .expect("Scoreboard should exist, but doesn't"),
-        )
+        Arc::clone(self.children.entry(*movement).or_default())
     }
     pub fn update_scores(&mut self) {
-        self.tally = self.children.values().fold(0, |acc, e| acc + e.read().unwrap().tally);
+        self.tally = self.children.values().map(|e| e.read().unwrap().tally).sum();
         // opponent becomes player and vice versa, and their score is propagated
         // upwards from the sub simulations
         self.player_score = self
             .children
             .values()
-            .fold(0, |acc, e| acc + e.read().unwrap().opponent_score);
+            .map(|e| e.read().unwrap().opponent_score)
+            .sum();
         self.opponent_score = self
-        .children
-        .values()
-        .fold(0, |acc, e| acc + e.read().unwrap().player_score);
-
+            .children
+            .values()
+            .map(|e| e.read().unwrap().player_score)
+            .sum();
     }
-    pub fn calculate_bias(&self) -> HashMap<Move, f32> {
+    /// The UCT score of each move. Moves that haven't been tried yet are
+    /// treated as if they'd been tried once and lost.
+    pub fn calculate_bias(&self, exploration: f32) -> HashMap<Move, f32> {
+        let total = (self.tally.max(1) as f32).ln();
         self.children
             .iter()
             .map(|(m, x)| {
                 let y = x.read().unwrap();
-                (m.clone(), y.player_score as f32 / 4.0 / (y.tally as f32)
-                    + EXPLORATION_PARAMETER * ((self.tally as f32).log(2.71828) / y.tally as f32).sqrt())
+                let tally = y.tally.max(1) as f32;
+                (*m, y.player_score as f32 / 4.0 / tally + exploration * (total / tally).sqrt())
             })
             .collect()
     }
     pub fn pick_move(&self) -> Option<Move> {
-        let mut highest_bias = f32::MIN;
+        let mut highest_bias = 0;
         let mut best_move = None;
         for (movement, scoreboard) in self.children.iter() {
-            let b = scoreboard.read().unwrap();
-            let bias = b.tally as f32;
-            if bias > highest_bias {
+            let bias = scoreboard.read().unwrap().tally;
+            if best_move.is_none() || bias > highest_bias {
                 highest_bias = bias;
                 best_move = Some(*movement);
             }
@@ -100,33 +130,34 @@ impl ScoreBoard {
 
 // tuning parameter for the randomness - bigger divisor gives more smoothness at higher
 // computational cost.
-const DIVISOR: u16 = 10;
+const DIVISOR: u64 = 10;
 /// See the writeup in docs/sampling/notes.md
-/// Will return a vec of equal length to bias -
-/// i.e. bias must match num_moves
-pub fn get_samples(num_samples: f32, bias: HashMap<Move, f32>) -> HashMap<Move, u16> {
-    let b_sum: f32 = bias.values().sum();
+/// Will return a map with the same moves as bias
+pub fn get_samples(num_samples: u64, bias: HashMap<Move, f32>) -> HashMap<Move, u64> {
+    let mut b_sum: f32 = bias.values().sum();
+    // with no weight to share out (every move unscored, say) the shares below
+    // are meaningless, so spread the samples evenly instead
+    let bias = if b_sum > 0.0 && b_sum.is_finite() && bias.values().all(|b| *b >= 0.0) {
+        bias
+    } else {
+        b_sum = bias.len() as f32;
+        bias.into_keys().map(|m| (m, 1.0)).collect()
+    };
+    let share = |b: f32| num_samples as f32 * b / b_sum;
     // do a best-attempt at matching the bias distribution with integer number of samples
-    let mut choices: HashMap<Move, u16> = bias
-        .iter()
-        .map(|(m, b)| (m.clone(), (num_samples * b / b_sum) as u16))
-        .collect();
+    let mut choices: HashMap<Move, u64> = bias.iter().map(|(m, b)| (*m, share(*b) as u64)).collect();
 
     // build a distribution of where our best-attempt is furthest from our desired distribution
-    let remainder_bias: HashMap<Move, f32> = bias
-        .iter()
-        .map(|(m, b)| (m.clone(), num_samples * (b % b_sum)))
-        .collect();
-    // let debg: Vec<usize> = bias.iter().map(|b| (num_samples * (b % b_sum)).max(1e-4) as usize).collect();
-    let mut remainder = (num_samples as u16) - choices.values().sum::<u16>();
+    let remainder_bias: HashMap<Move, f32> = bias.iter().map(|(m, b)| (*m, share(*b).fract())).collect();
+    let mut remainder = num_samples.saturating_sub(choices.values().sum::<u64>());
 
     let mut rng = thread_rng();
 
     // now, divide up the remainder by the bias
-    if remainder > 0 {
+    if remainder > 0 && !bias.is_empty() {
         if remainder_bias.len() == 1 {
-            for (m, _choice) in choices.clone() {
-                *choices.get_mut(&m).unwrap() += remainder;
+            for choice in choices.values_mut() {
+                *choice += remainder;
             }
         }
         else {
@@ -141,128 +172,341 @@ pub fn get_samples(num_samples: f32, bias: HashMap<Move, f32>) -> HashMap<Move,
                 biases.push(val);
             }
 
-            let index =
-                WeightedIndex::new(biases).expect("Failed to initialize biased sampler");
-    
+            // rounding can leave samples over even when every share was a
+            // whole number, so fall back to the bias itself
+            let index = WeightedIndex::new(&biases)
+                .or_else(|_| WeightedIndex::new(choosable_moves.iter().map(|m| bias[m])))
+                .expect("Failed to initialize biased sampler");
+
             while remainder > 0 {
-                let allocated = (thread_rng().gen_range(1..remainder + 1) + (DIVISOR - 1)) / DIVISOR;
+                let allocated = rng.gen_range(1..remainder + 1).div_ceil(DIVISOR);
 
                 let chosen_move: Move = choosable_moves[index.sample(&mut rng)];
                 *choices.get_mut(&chosen_move).unwrap() += allocated;
-    
+
                 remainder -= allocated;
             }
         }
-    }                                                                                                                                              
+    }
 
     choices
 }
 
+/// Play random moves until the game ends or `max_plies` have been played,
+/// and score the game like [`final_points`], for the player who moved into
+/// the starting position and for the player to move in it.
+fn random_game(board: &mut Board, max_plies: u32) -> (u64, u64) {
+    let player_color = board.current_player;
+    let mut rng = thread_rng();
+    for _ in 0..max_plies {
+        // the draws that are cheap to spot, so dead games stop early
+        if board.halfmove_clock >= 100 || is_insufficient_material(board) {
+            break;
+        }
+        let moves = board.legal_moves();
+        let Some(rand_move) = moves.choose(&mut rng) else {
+            break;
+        };
+        apply_move(board, *rand_move);
+    }
+    // games that haven't finished count as a draw
+    let (last_mover, to_move) = check_for_game_end(board).map_or((2, 2), final_points);
+    if board.current_player == player_color {
+        (last_mover, to_move)
+    } else {
+        (to_move, last_mover)
+    }
+}
+
 // We want to process all of the remaining moves
 // if a given move has a lot of samples, we would like to
 // give it its own thread.
 
-// Procedure:
-// fn process(list_of_tasks) {
-//      for task in tasks
-//}
-
+/// Play `num_searches` games from `board`, sharing them out between its
+/// moves by their UCT scores, and add the results to `scoreboard`.
 pub fn tree_search(
-    board: &mut Board,
-    num_searches: u16,
-    scoreboard: Arc<RwLock<ScoreBoard>>,
-    max_depth: u16,
+    board: &Board,
+    num_searches: u64,
+    scoreboard: &Arc<RwLock<ScoreBoard>>,
+    options: &MonteCarloOptions,
 ) {
-    let moves = get_all_valid_moves(board);
-
-    let player_color = board.current_player;
-    
-    // if we haven't yet traversed this nodes' children, our biases
-    // will all be 0
-    let bias: HashMap<Move, f32>;
-    // otherwise, calculate them
-    if scoreboard.read().unwrap().children.len() == 0 {
-        bias = moves.iter().map(|m| (m.clone(), 1.0)).collect();
-    } else {
-        bias = scoreboard.read().unwrap().calculate_bias();
+    if num_searches == 0 {
+        return;
+    }
+    if let Some(end_type) = check_for_game_end(board) {
+        // every game through here ends straight away
+        let (last_mover, to_move) = final_points(end_type);
+        let mut scoreboard = scoreboard.write().unwrap();
+        scoreboard.player_score += last_mover * num_searches;
+        scoreboard.opponent_score += to_move * num_searches;
+        scoreboard.tally += num_searches;
+        return;
     }
-    let samples: HashMap<Move, u16> = get_samples(num_searches as f32, bias);
-
-    let _: Vec<()> = moves
-        .par_iter()
-        .map(|movement| {
-            let sample = samples.get(movement).unwrap();
-            let mut board = board.clone();
-            let (board, _) = apply_move(&mut board, *movement);
-
-            let sub_scoreboard = scoreboard.write().unwrap().retrieve_scoreboard(movement);
-
-            if *sample >= EXPAND_TREE_CUTOFF {
-                // expand the tree to this level
-                tree_search(board, *sample, sub_scoreboard.clone(), max_depth - 1);
-
-
-            } else {
-                let _: Vec<()> = (0..*sample).into_par_iter().map(| _ | {
-                    {
-                        let mut board = &mut (board.clone());
-                        let mut depth = max_depth;
-                        
-                        while depth > 0 {
-                            let moves = get_all_valid_moves(board);
-                            if moves.len() == 0 {
-                                break;
-                            }
-                            let rand_move = moves.choose(&mut rand::thread_rng()).unwrap();
-                            let (a, _b) = apply_move(board, *rand_move);
-                            board = a;
-                            depth -= 1;
-                        };
-                        // we have a game ending position
-                        let end_type = check_for_mates(& mut board);
-        
-                        if let Some(end_type) = end_type {
-                            let score: u16 = match end_type {
-                                hexchesscore::Mate::Checkmate => 4,
-                                hexchesscore::Mate::Stalemate => 3, // stalemate is 3/4 of a win
-                            };
-                            if board.current_player == player_color {
-                                sub_scoreboard.write().unwrap().opponent_score += score;
-                            } else {
-                                sub_scoreboard.write().unwrap().player_score += score;
-                            }
-                        }
-                    }
-                }).collect();
+
+    let moves = board.legal_moves();
+
+    // if we haven't yet traversed this nodes' children, every move is
+    // sampled evenly, otherwise by its UCT score
+    let bias: HashMap<Move, f32> = {
+        let mut scoreboard = scoreboard.write().unwrap();
+        if scoreboard.children.is_empty() {
+            for movement in &moves {
+                scoreboard.retrieve_scoreboard(movement);
             }
+            moves.iter().map(|m| (*m, 1.0)).collect()
+        } else {
+            scoreboard.calculate_bias(options.exploration)
+        }
+    };
+    let samples: HashMap<Move, u64> = get_samples(num_searches, bias);
 
-            sub_scoreboard.write().unwrap().tally += sample;
-        })
-        .collect();
+    moves.par_iter().for_each(|movement| {
+        let sample = samples.get(movement).copied().unwrap_or(0);
+        if sample == 0 {
+            return;
+        }
+        let board = &mut board.clone();
+        apply_move(board, *movement);
+
+        let sub_scoreboard = scoreboard.write().unwrap().retrieve_scoreboard(movement);
+
+        if sample >= options.expand_cutoff {
+            // expand the tree to this level
+            tree_search(board, sample, &sub_scoreboard, options);
+        } else {
+            let (player_score, opponent_score) = (0..sample)
+                .into_par_iter()
+                .map(|_| random_game(&mut board.clone(), options.rollout_plies))
+                .reduce(|| (0, 0), |a, b| (a.0 + b.0, a.1 + b.1));
+            let mut sub_scoreboard = sub_scoreboard.write().unwrap();
+            sub_scoreboard.player_score += player_score;
+            sub_scoreboard.opponent_score += opponent_score;
+            sub_scoreboard.tally += sample;
+        }
+    });
 
     // we've got the results back from all of our samples, so lets start
     // updating the scoreboard
+    scoreboard.write().unwrap().update_scores();
+}
 
-    // for some reason this call screws everything up!
+/// The tree search's results, kept from one move to the next.
+#[derive(Debug)]
+pub struct MonteCarloTree {
+    pub options: MonteCarloOptions,
+    root: Arc<RwLock<ScoreBoard>>,
+    /// The position at the root of the tree, once there's been a search
+    root_board: Option<Board>,
+}
 
-    scoreboard.write().unwrap().update_scores();
+impl MonteCarloTree {
+    pub fn new(options: MonteCarloOptions) -> MonteCarloTree {
+        MonteCarloTree {
+            options,
+            root: Arc::new(RwLock::new(ScoreBoard::new())),
+            root_board: None,
+        }
+    }
+
+    pub fn root(&self) -> &Arc<RwLock<ScoreBoard>> {
+        &self.root
+    }
+
+    /// Forget everything learnt from the last game.
+    pub fn new_game(&mut self) {
+        self.root = Arc::new(RwLock::new(ScoreBoard::new()));
+        self.root_board = None;
+    }
+
+    /// Move the root of the tree to `board`. If it's one or two plies below
+    /// the old root - usually the bot's move and the opponent's reply - the
+    /// games already played from it are kept, and the rest of the tree is
+    /// thrown away.
+    pub fn reroot(&mut self, board: &Board) {
+        let key = board.hash();
+        let found = match &self.root_board {
+            Some(root_board) if root_board.hash() == key => return,
+            Some(root_board) => find_descendant(root_board, &self.root, key),
+            None => None,
+        };
+        self.root = found.unwrap_or_default();
+        self.root_board = Some(board.clone());
+    }
 }
 
+/// The node for the position with the hash `key`, among the children and
+/// grandchildren of `scoreboard`.
+fn find_descendant(board: &Board, scoreboard: &Arc<RwLock<ScoreBoard>>, key: u64) -> Option<Arc<RwLock<ScoreBoard>>> {
+    for (movement, child) in &scoreboard.read().unwrap().children {
+        let mut after = board.clone();
+        apply_move(&mut after, *movement);
+        if after.hash() == key {
+            return Some(Arc::clone(child));
+        }
+        for (reply, grandchild) in &child.read().unwrap().children {
+            let mut after_reply = after.clone();
+            apply_move(&mut after_reply, *reply);
+            if after_reply.hash() == key {
+                return Some(Arc::clone(grandchild));
+            }
+        }
+    }
+    None
+}
 
-// TODO: keep the scoreboard between moves and continously run search until asked to make a move
-pub fn make_a_move(board: &mut Board, timeout_ms: u64) -> Move {
+/// The most played moves from the root of `tree` down, as long as they've
+/// been played at all.
+pub fn principal_variation(tree: &MonteCarloTree) -> Vec<Move> {
+    let mut line = Vec::new();
+    let mut node = Arc::clone(&tree.root);
+    loop {
+        let next = {
+            let scoreboard = node.read().unwrap();
+            match scoreboard.pick_move() {
+                Some(movement) if scoreboard.children[&movement].read().unwrap().tally > 0 => {
+                    (movement, Arc::clone(&scoreboard.children[&movement]))
+                }
+                _ => break,
+            }
+        };
+        line.push(next.0);
+        node = next.1;
+    }
+    line
+}
+
+/// Play batches of games from `board` for as long as `keep_going` says to,
+/// carrying on from what `tree` already knows about it. There's always at
+/// least one batch, so there's a move to pick.
+pub fn search_while(board: &Board, tree: &mut MonteCarloTree, keep_going: impl Fn() -> bool) {
+    tree.reroot(board);
+    loop {
+        tree_search(board, tree.options.batch_size, &tree.root, &tree.options);
+        if !keep_going() {
+            break;
+        }
+    }
+}
+
+/// Search from `board` in batches until the time is up, and pick the most
+/// played move.
+pub fn make_a_move(board: &mut Board, timeout_ms: u64, tree: &mut MonteCarloTree) -> Move {
     let end: Instant = Instant::now() + Duration::from_millis(timeout_ms);
+    search_while(board, tree, || Instant::now() < end);
+    tree.root.read().unwrap().pick_move().expect("didn't pick a move")
+}
+
+/// The batched tree search as an [`Engine`]. Each of the fields of
+/// [`MonteCarloOptions`] can be set as an option of the same name.
+#[derive(Debug)]
+pub struct MonteCarloEngine {
+    board: Board,
+    tree: MonteCarloTree,
+    stop: StopSignal,
+}
+
+impl MonteCarloEngine {
+    pub fn new() -> MonteCarloEngine {
+        MonteCarloEngine {
+            board: Board::setup_default_board(),
+            tree: MonteCarloTree::new(MonteCarloOptions::default()),
+            stop: StopSignal::new(),
+        }
+    }
+}
+
+impl Default for MonteCarloEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine for MonteCarloEngine {
+    fn name(&self) -> &'static str {
+        "mcts"
+    }
+
+    fn new_game(&mut self) {
+        self.tree.new_game();
+    }
 
-    let scoreboard = Arc::new(RwLock::new(ScoreBoard::new()));
+    fn set_position(&mut self, board: &Board) {
+        self.board = board.clone();
+        self.tree.reroot(board);
+    }
+
+    fn search(&mut self, limits: &SearchLimits) -> SearchResult {
+        if self.board.legal_moves().is_empty() {
+            return SearchResult::game_over();
+        }
+        self.stop.reset();
+        let games_before = self.tree.root.read().unwrap().tally;
+        let end = limits.time.map(|time| Instant::now() + time);
+        let stop = &self.stop;
+        // a depth is taken as a number of batches
+        let batches = Cell::new(0);
+        search_while(&self.board, &mut self.tree, || {
+            batches.set(batches.get() + 1);
+            !stop.is_stopped()
+                && end.is_none_or(|end| Instant::now() < end)
+                && limits.depth.is_none_or(|depth| batches.get() < depth)
+        });
+
+        let principal_variation = principal_variation(&self.tree);
+        let root = self.tree.root.read().unwrap();
+        let score = principal_variation.first().map(|movement| {
+            let child = root.children[movement].read().unwrap();
+            Score::Expectation(child.player_score as f32 / 4.0 / child.tally as f32)
+        });
+        SearchResult {
+            best_move: principal_variation.first().copied(),
+            score,
+            principal_variation: principal_variation.clone(),
+            nodes: root.tally - games_before,
+            depth: None,
+        }
+    }
+
+    fn stop_signal(&self) -> StopSignal {
+        self.stop.clone()
+    }
+
+    fn set_option(&mut self, name: &str, value: &str) -> Result<(), OptionError> {
+        let options = &mut self.tree.options;
+        match name {
+            "exploration" => options.exploration = positive(name, value)?,
+            "batch_size" => options.batch_size = at_least(name, value, 1)?,
+            // a single game given its own node would be given its own node
+            // again at the next level, forever
+            "expand_cutoff" => options.expand_cutoff = at_least(name, value, 2)?,
+            "rollout_plies" => options.rollout_plies = parse_option(name, value)?,
+            _ => return Err(OptionError::Unknown(name.to_string())),
+        }
+        Ok(())
+    }
+}
+
+fn at_least(name: &str, value: &str, minimum: u64) -> Result<u64, OptionError> {
+    let number = parse_option(name, value)?;
+    if number < minimum {
+        return Err(OptionError::InvalidValue {
+            name: name.to_string(),
+            reason: format!("must be at least {minimum}"),
+        });
+    }
+    Ok(number)
+}
 
-    while Instant::now() < end {
-        tree_search(board, 50, scoreboard.clone(), 800);
-        dbg!(&scoreboard.read().unwrap().player_score);
-        dbg!(board.current_player);
-        dbg!(&scoreboard.read().unwrap().tally);
-        dbg!(&scoreboard.read().unwrap().pick_move());
+fn positive(name: &str, value: &str) -> Result<f32, OptionError> {
+    let number: f32 = parse_option(name, value)?;
+    // also turns away NaN, which would poison every bias
+    if !(number > 0.0 && number.is_finite()) {
+        return Err(OptionError::InvalidValue {
+            name: name.to_string(),
+            reason: "must be a number above 0".to_string(),
+        });
     }
-    scoreboard.clone().read().unwrap().pick_move().expect("didn't pick a move").clone()
+    Ok(number)
 }
 
 fn output_board_representation(board: &Board) {
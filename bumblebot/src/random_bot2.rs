@@ -1,8 +1,11 @@
 use hexchesscore::{
-    apply_move, check_for_mates, get_all_valid_moves, revert_move, Board, Color, Move,
+    apply_move, check_for_game_end, revert_move, Board, Color, Move,
 };
 use rand::{prelude::SliceRandom, thread_rng, Rng};
 use rand_distr::{Distribution, WeightedIndex};
+use std::time::Instant;
+
+use crate::engine::{Engine, SearchLimits, SearchResult, StopSignal};
 
 /// basic monte carlo tree search with no (pre-mature) optimizations
 ///
@@ -40,14 +43,16 @@ impl SearchTree {
     }
 }
 
-fn evaluate_endgame(board: &mut Board) -> Option<i32> {
+fn evaluate_endgame(board: &Board) -> Option<i32> {
     // we have a game ending position
-    let end_type = check_for_mates(board);
+    let end_type = check_for_game_end(board);
 
     if let Some(end_type) = end_type {
         let score: i32 = match end_type {
-            hexchesscore::Mate::Checkmate => 4,
-            hexchesscore::Mate::Stalemate => 3, // stalemate is 3/4 of a win
+            hexchesscore::GameResult::Checkmate => 4,
+            hexchesscore::GameResult::Stalemate => 3, // stalemate is 3/4 of a win
+            // every other draw is worth the same to both players
+            _ => 0,
         };
         Some(
             score
@@ -64,7 +69,7 @@ fn evaluate_endgame(board: &mut Board) -> Option<i32> {
 /// Play a game until there is a win, draw, or loss.
 /// This function does not expand the tree.
 /// Assumes the next move has been pre-applied to the board
-fn finish_playout(board: &mut Board) -> Option<i32> {
+fn finish_playout(board: &Board) -> Option<i32> {
     // clone the board, since we will be applying lots of moves
     // and don't want to have to revert them all
     let board = &mut board.clone();
@@ -72,7 +77,7 @@ fn finish_playout(board: &mut Board) -> Option<i32> {
     let mut rng = thread_rng();
 
     for _ in 0..MAX_ITERATIONS {
-        let new_moves = get_all_valid_moves(board);
+        let new_moves = board.legal_moves();
 
         if new_moves.len() == 0 {
             return evaluate_endgame(board);
@@ -81,7 +86,7 @@ fn finish_playout(board: &mut Board) -> Option<i32> {
             // loop to repeat
             let movement = new_moves.choose(&mut rng).unwrap();
             // assume that we're modifying the board in-place; this needs to be verified
-            let _ = apply_move(board, *movement);
+            apply_move(board, *movement);
         }
     }
 
@@ -116,7 +121,8 @@ fn choose_move(tree: &mut SearchTree) -> Option<&mut (Move, SearchTree)> {
     let bias = calculate_bias(children.iter().map(|(_x, y)| y).collect());
 
     let mut rng = thread_rng();
-    let index = WeightedIndex::new(bias).unwrap();
+    // before any playout has been won every weight is zero, so pick evenly
+    let index = WeightedIndex::new(&bias).unwrap_or_else(|_| WeightedIndex::new(vec![1.0; bias.len()]).unwrap());
 
     Some(&mut children[index.sample(&mut rng)])
 }
@@ -136,9 +142,9 @@ pub fn tree_search(board: &mut Board, tree: &mut SearchTree) -> Option<i32> {
             // play all the nodes at this level at least once before
             // starting the random search
             if child_tree.playouts == 0 {
-                let (board, taken_piece) = apply_move(board, *movement);
+                let undo = apply_move(board, *movement);
                 let res = finish_playout(board);
-                revert_move(board, *movement, taken_piece);
+                revert_move(board, *movement, undo);
                 if let Some(score) = res {
                     tally_results(child_tree, score);
                 }
@@ -150,15 +156,15 @@ pub fn tree_search(board: &mut Board, tree: &mut SearchTree) -> Option<i32> {
         // at this point, we've populated all the nodes at this level,
         // so pick one of the nodes randomly and recurse
         let (random_move, child_tree) = choose_move(tree).unwrap();
-        let (board, taken_piece) = apply_move(board, *random_move);
+        let undo = apply_move(board, *random_move);
         let res = tree_search(board, child_tree);
-        revert_move(board, *random_move, taken_piece);
+        revert_move(board, *random_move, undo);
         return res;
     }
     // otherwise, populate this layer, and then call the function again
     else {
         tree.children = Some(
-            get_all_valid_moves(board)
+            board.legal_moves()
                 .into_iter()
                 .map(|movement| (movement, SearchTree::new()))
                 .collect(),
@@ -172,3 +178,98 @@ pub fn tree_search(board: &mut Board, tree: &mut SearchTree) -> Option<i32> {
     // otherwise, when we hit an unsearched node, do a full playout
     //
 }
+
+/// The most played moves from the root of `tree` down, as long as they've
+/// been played at all.
+fn most_played_line(tree: &SearchTree) -> Vec<Move> {
+    let mut line = Vec::new();
+    let mut node = tree;
+    while let Some((movement, child)) = node
+        .children
+        .iter()
+        .flatten()
+        .filter(|(_, child)| child.playouts > 0)
+        .max_by_key(|(_, child)| child.playouts)
+    {
+        line.push(*movement);
+        node = child;
+    }
+    line
+}
+
+/// The simple tree search as an [`Engine`]. Its tree can't be moved to a
+/// new root, so it starts again whenever the position changes.
+#[derive(Debug)]
+pub struct SimpleMonteCarloEngine {
+    board: Board,
+    tree: SearchTree,
+    stop: StopSignal,
+}
+
+impl SimpleMonteCarloEngine {
+    pub fn new() -> SimpleMonteCarloEngine {
+        SimpleMonteCarloEngine {
+            board: Board::setup_default_board(),
+            tree: SearchTree::new(),
+            stop: StopSignal::new(),
+        }
+    }
+}
+
+impl Default for SimpleMonteCarloEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine for SimpleMonteCarloEngine {
+    fn name(&self) -> &'static str {
+        "simple-mcts"
+    }
+
+    fn new_game(&mut self) {
+        self.tree = SearchTree::new();
+    }
+
+    fn set_position(&mut self, board: &Board) {
+        if board.hash() != self.board.hash() {
+            self.tree = SearchTree::new();
+        }
+        self.board = board.clone();
+    }
+
+    fn search(&mut self, limits: &SearchLimits) -> SearchResult {
+        if self.board.legal_moves().is_empty() {
+            return SearchResult::game_over();
+        }
+        self.stop.reset();
+        let end = limits.time.map(|time| Instant::now() + time);
+        let mut searches = 0;
+        // a depth is taken as a number of games
+        let played_enough = |searches| limits.depth.is_some_and(|depth| searches >= u64::from(depth));
+        while searches == 0
+            || !self.stop.is_stopped() && end.is_none_or(|end| Instant::now() < end) && !played_enough(searches)
+        {
+            tree_search(&mut self.board, &mut self.tree);
+            searches += 1;
+        }
+
+        let mut principal_variation = most_played_line(&self.tree);
+        if principal_variation.is_empty() {
+            // no playout has finished yet, so any move is as good as another
+            let children = self.tree.children.iter().flatten();
+            principal_variation.extend(children.map(|(movement, _)| *movement).take(1));
+        }
+        SearchResult {
+            best_move: principal_variation.first().copied(),
+            score: None,
+            principal_variation,
+            nodes: searches,
+            depth: None,
+        }
+    }
+
+    fn stop_signal(&self) -> StopSignal {
+        self.stop.clone()
+    }
+}
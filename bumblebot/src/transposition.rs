@@ -0,0 +1,141 @@
+//! A transposition table, remembering what the search found out about each
+//! position it has visited.
+//!
+//! Positions are looked up by their Zobrist hash. The same position is often
+//! reached by different move orders, and every iteration of iterative
+//! deepening searches the positions the last one did, so a stored score can
+//! cut a search short, and a stored best move can be tried first.
+
+use std::mem;
+
+use hexchesscore::Move;
+
+/// How a stored score relates to the position's true score.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    /// Every move was searched and the score is exact
+    Exact,
+    /// A move was good enough for a cutoff, so the true score is at least this
+    Lower,
+    /// No move raised alpha, so the true score is at most this
+    Upper,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Entry {
+    /// The full hash of the position, to tell it apart from others sharing
+    /// its slot
+    pub key: u64,
+    /// How many plies were searched below the position
+    pub depth: i8,
+    pub bound: Bound,
+    pub score: f32,
+    pub best_move: Option<Move>,
+}
+
+impl Entry {
+    /// The stored score, if it's enough to settle a search of the position
+    /// to `depth` plies between `alpha` and `beta` without searching it.
+    pub fn cutoff(&self, depth: i8, alpha: f32, beta: f32) -> Option<f32> {
+        if self.depth < depth {
+            return None;
+        }
+        match self.bound {
+            Bound::Exact => Some(self.score),
+            Bound::Lower if self.score >= beta => Some(self.score),
+            Bound::Upper if self.score <= alpha => Some(self.score),
+            _ => None,
+        }
+    }
+}
+
+/// A fixed-size table of search results. Each position hashes to one slot,
+/// and a new result always replaces an old one for a different position.
+/// For the same position, the deeper search is kept.
+#[derive(Debug, Clone)]
+pub struct TranspositionTable {
+    entries: Vec<Option<Entry>>,
+}
+
+impl TranspositionTable {
+    /// A table taking up roughly `megabytes` of memory.
+    pub fn new(megabytes: usize) -> TranspositionTable {
+        let slots = megabytes * 1024 * 1024 / mem::size_of::<Option<Entry>>();
+        // a power of two, so the hash can be masked rather than divided
+        let slots = match slots {
+            0 => 1,
+            slots => 1 << slots.ilog2(),
+        };
+        TranspositionTable {
+            entries: vec![None; slots],
+        }
+    }
+
+    fn slot(&self, key: u64) -> usize {
+        key as usize & (self.entries.len() - 1)
+    }
+
+    /// The number of positions the table can hold.
+    pub fn capacity(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn probe(&self, key: u64) -> Option<&Entry> {
+        self.entries[self.slot(key)]
+            .as_ref()
+            .filter(|entry| entry.key == key)
+    }
+
+    pub fn store(&mut self, key: u64, depth: i8, bound: Bound, score: f32, mut best_move: Option<Move>) {
+        let slot = self.slot(key);
+        let entry = &mut self.entries[slot];
+        if let Some(old) = entry.filter(|old| old.key == key) {
+            if old.depth > depth {
+                return;
+            }
+            // keep the old best move if this search didn't find one
+            best_move = best_move.or(old.best_move);
+        }
+        *entry = Some(Entry { key, depth, bound, score, best_move });
+    }
+
+    /// Forget every position, ready for a new game.
+    pub fn clear(&mut self) {
+        self.entries.fill(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hexchesscore::Board;
+
+    #[test]
+    fn test_table_keeps_the_deeper_search() {
+        let mut table = TranspositionTable::new(1);
+        assert!(table.capacity().is_power_of_two());
+
+        let board = Board::setup_default_board();
+        let movement = board.legal_moves()[0];
+        let key = board.hash();
+        table.store(key, 3, Bound::Exact, 1.0, Some(movement));
+        table.store(key, 2, Bound::Lower, 5.0, None);
+        assert_eq!(table.probe(key).unwrap().score, 1.0);
+
+        table.store(key, 4, Bound::Upper, -2.0, None);
+        let entry = *table.probe(key).unwrap();
+        assert_eq!(entry.best_move, Some(movement));
+        assert_eq!(entry.cutoff(4, -3.0, 1.0), None);
+        assert_eq!(entry.cutoff(4, -2.0, 1.0), Some(-2.0));
+        assert_eq!(entry.cutoff(5, -2.0, 1.0), None);
+
+        // a different position in the same slot replaces it
+        let other = key ^ (table.capacity() as u64);
+        table.store(other, 1, Bound::Exact, 0.0, None);
+        assert!(table.probe(key).is_none());
+        assert!(table.probe(other).is_some());
+
+        table.clear();
+        assert!(table.probe(other).is_none());
+    }
+}
@@ -0,0 +1,330 @@
+//! Fitting the evaluation weights to game results, Texel-style.
+//!
+//! Every training position is labelled with the result of the game it came
+//! from. A sigmoid turns the evaluation into an expected result, and the
+//! weights are moved to shrink the mean squared difference between the
+//! expected and actual results. The evaluation is linear in the weights, so
+//! each position's features only need counting once, and the gradient is
+//! exact.
+//!
+//! Positions are stored one JSON object per line, as
+//! `{"board": <board>, "result": <result>}`, with the result from White's
+//! side - 1 for a White win, 0.5 for a draw and 0 for a Black win.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+use hexchesscore::{king_is_in_check, Board, Color, GameResult};
+
+use crate::bot_mind::{make_a_move, quiescence, SearchState};
+use crate::evaluation::{evaluate, features, EvaluationWeights};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LabelledPosition {
+    pub board: Board,
+    /// How the game went for White
+    pub result: f32,
+}
+
+/// Read every position in a file written by [`append_positions`].
+pub fn read_positions(path: impl AsRef<Path>) -> io::Result<Vec<LabelledPosition>> {
+    let mut positions = Vec::new();
+    for (number, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let position = serde_json::from_str(&line).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {e}", number + 1))
+        })?;
+        positions.push(position);
+    }
+    Ok(positions)
+}
+
+/// Add positions to the end of a file, creating it if needed, so a long
+/// self-play run can save each game as it finishes.
+pub fn append_positions(path: impl AsRef<Path>, positions: &[LabelledPosition]) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut writer = BufWriter::new(file);
+    for position in positions {
+        serde_json::to_writer(&mut writer, position)?;
+        writeln!(writer)?;
+    }
+    writer.flush()
+}
+
+/// How a game finished, from White's side. `to_move` is the player whose
+/// turn it is in the final position, so the one who's been mated or
+/// stalemated.
+pub fn result_for_white(result: GameResult, to_move: Color) -> f32 {
+    let last_mover_points = result.last_mover_points();
+    match to_move {
+        Color::White => 1.0 - last_mover_points,
+        Color::Black => last_mover_points,
+    }
+}
+
+/// Whether the static evaluation can be trusted - the side to move isn't in
+/// check, and there are no captures or promotions worth making. Only these
+/// positions are worth training on, as the search never evaluates the
+/// others without a quiescence search first.
+pub fn is_quiet(board: &Board, weights: &EvaluationWeights) -> bool {
+    if king_is_in_check(board.current_player, board) {
+        return false;
+    }
+    let standing = evaluate(board, weights);
+    quiescence(&mut board.clone(), f32::NEG_INFINITY, f32::INFINITY, weights) == standing
+}
+
+/// How the self-play games are played.
+#[derive(Debug, Clone)]
+pub struct SelfPlayOptions {
+    /// How long the bot thinks about each move
+    pub move_ms: u64,
+    /// How many moves at the start of each game are picked at random, so
+    /// that the games aren't all the same
+    pub random_plies: u32,
+    /// Games still going after this many plies are counted as draws
+    pub max_plies: u32,
+}
+
+impl Default for SelfPlayOptions {
+    fn default() -> Self {
+        SelfPlayOptions {
+            move_ms: 100,
+            random_plies: 8,
+            max_plies: 300,
+        }
+    }
+}
+
+/// Play the bot against itself with [`make_a_move`], and label every quiet
+/// position after the random opening with how the game ended.
+pub fn self_play_game(
+    state: &mut SearchState,
+    options: &SelfPlayOptions,
+    rng: &mut impl Rng,
+) -> Vec<LabelledPosition> {
+    state.new_game();
+    let mut board = Board::setup_default_board();
+    let mut boards = Vec::new();
+    let mut result = 0.5;
+    for ply in 0..options.max_plies {
+        let movement = if ply < options.random_plies {
+            let moves = board.legal_moves();
+            *moves.choose(rng).expect("the game should have ended")
+        } else {
+            if is_quiet(&board, &state.weights) {
+                // the history isn't needed to evaluate the position
+                let mut stored = board.clone();
+                stored.position_history.clear();
+                boards.push(stored);
+            }
+            make_a_move(&mut board, options.move_ms, state)
+        };
+        let outcome = board.play(movement).expect("the bot made an illegal move");
+        if let Some(end) = outcome.result {
+            result = result_for_white(end, board.current_player);
+            break;
+        }
+    }
+    boards
+        .into_iter()
+        .map(|board| LabelledPosition { board, result })
+        .collect()
+}
+
+/// The features of a set of positions, counted once, along with their
+/// results.
+#[derive(Debug, Clone)]
+pub struct TrainingSet {
+    /// Each position's features that aren't zero, with their index in
+    /// [`EvaluationWeights::to_vec`]. Most of the piece-square tables are
+    /// empty in any one position, so this is far smaller than every feature.
+    features: Vec<Vec<(usize, f32)>>,
+    results: Vec<f32>,
+}
+
+impl TrainingSet {
+    pub fn new(positions: &[LabelledPosition]) -> TrainingSet {
+        TrainingSet {
+            features: positions
+                .iter()
+                .map(|position| {
+                    let features = features(&position.board).to_vec();
+                    features.into_iter().enumerate().filter(|(_, feature)| *feature != 0.0).collect()
+                })
+                .collect(),
+            results: positions.iter().map(|position| position.result).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.results.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.results.is_empty()
+    }
+
+    /// The mean squared difference between the results and the results the
+    /// weights predict, turning a score into an expected result with
+    /// `1 / (1 + e^(-scale * score))`.
+    pub fn error(&self, weights: &[f32], scale: f32) -> f32 {
+        self.error_and_gradient(weights, scale, false).0
+    }
+
+    fn error_and_gradient(&self, weights: &[f32], scale: f32, with_gradient: bool) -> (f32, Vec<f32>) {
+        let mut error = 0.0;
+        let mut gradient = vec![0.0; if with_gradient { weights.len() } else { 0 }];
+        for (features, result) in self.features.iter().zip(&self.results) {
+            let score: f32 = features.iter().map(|(index, feature)| feature * weights[*index]).sum();
+            let expected = sigmoid(scale * score);
+            let difference = expected - result;
+            error += difference * difference;
+            if with_gradient {
+                let slope = 2.0 * difference * expected * (1.0 - expected) * scale;
+                for (index, feature) in features {
+                    gradient[*index] += slope * feature;
+                }
+            }
+        }
+        let count = self.len().max(1) as f32;
+        gradient.iter_mut().for_each(|total| *total /= count);
+        (error / count, gradient)
+    }
+
+    /// The sigmoid scale that best fits the results to the scores the
+    /// weights give, found by golden section search. The weights are then
+    /// tuned with the scale fixed, so they stay in pawns.
+    pub fn fit_scale(&self, weights: &[f32]) -> f32 {
+        let ratio = (5f32.sqrt() - 1.0) / 2.0;
+        let (mut low, mut high) = (0.0f32, 10.0f32);
+        while high - low > 1e-4 {
+            let left = high - ratio * (high - low);
+            let right = low + ratio * (high - low);
+            if self.error(weights, left) < self.error(weights, right) {
+                high = right;
+            } else {
+                low = left;
+            }
+        }
+        (low + high) / 2.0
+    }
+
+    /// Tune `start` to fit the results, with the Adam optimiser, which
+    /// copes with features as different in size as material and mobility.
+    /// `report` is called with the error after each iteration.
+    pub fn tune(
+        &self,
+        start: &EvaluationWeights,
+        scale: f32,
+        iterations: usize,
+        learning_rate: f32,
+        mut report: impl FnMut(usize, f32),
+    ) -> EvaluationWeights {
+        const BETA1: f32 = 0.9;
+        const BETA2: f32 = 0.999;
+        const EPSILON: f32 = 1e-8;
+
+        let mut weights = start.to_vec();
+        let mut momentum = vec![0.0; weights.len()];
+        let mut velocity = vec![0.0; weights.len()];
+        for iteration in 1..=iterations {
+            let (error, gradient) = self.error_and_gradient(&weights, scale, true);
+            let correction1 = 1.0 - BETA1.powi(iteration as i32);
+            let correction2 = 1.0 - BETA2.powi(iteration as i32);
+            for (i, slope) in gradient.iter().enumerate() {
+                momentum[i] = BETA1 * momentum[i] + (1.0 - BETA1) * slope;
+                velocity[i] = BETA2 * velocity[i] + (1.0 - BETA2) * slope * slope;
+                let step = momentum[i] / correction1 / ((velocity[i] / correction2).sqrt() + EPSILON);
+                weights[i] -= learning_rate * step;
+            }
+            report(iteration, error);
+        }
+        EvaluationWeights::from_slice(&weights)
+    }
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hexchesscore::{Hexagon, Piece, PieceType};
+
+    fn position(pieces: &[(&str, PieceType, Color)], result: f32) -> LabelledPosition {
+        let mut board = Board::new();
+        for (hex, piece_type, color) in pieces {
+            board
+                .occupied_squares
+                .insert(Hexagon::new(hex).unwrap(), Piece { piece_type: *piece_type, color: *color });
+        }
+        board.refresh_hash();
+        LabelledPosition { board, result }
+    }
+
+    const WHITE_KING: (&str, PieceType, Color) = ("F1", PieceType::King, Color::White);
+    const BLACK_KING: (&str, PieceType, Color) = ("F11", PieceType::King, Color::Black);
+
+    fn training_positions() -> Vec<LabelledPosition> {
+        vec![
+            position(&[WHITE_KING, BLACK_KING, ("C3", PieceType::Rook, Color::White)], 1.0),
+            position(&[WHITE_KING, BLACK_KING, ("H8", PieceType::Rook, Color::Black)], 0.0),
+            position(&[WHITE_KING, BLACK_KING, ("E4", PieceType::Rook, Color::White)], 1.0),
+            position(&[WHITE_KING, BLACK_KING, ("D8", PieceType::Rook, Color::Black)], 0.0),
+            position(&[WHITE_KING, BLACK_KING], 0.5),
+        ]
+    }
+
+    #[test]
+    fn test_positions_round_trip_through_a_file() {
+        let path = std::env::temp_dir().join(format!("bumblebot-positions-{}.jsonl", std::process::id()));
+        let positions = training_positions();
+        append_positions(&path, &positions[..2]).unwrap();
+        append_positions(&path, &positions[2..]).unwrap();
+        let read = read_positions(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read.unwrap(), positions);
+    }
+
+    #[test]
+    fn test_tuning_learns_what_wins_games() {
+        let set = TrainingSet::new(&training_positions());
+        assert_eq!(set.len(), 5);
+        let start = EvaluationWeights::zero();
+        let before = set.error(&start.to_vec(), 1.0);
+
+        let mut errors = Vec::new();
+        let tuned = set.tune(&start, 1.0, 200, 0.05, |_, error| errors.push(error));
+        assert_eq!(errors.len(), 200);
+        let after = set.error(&tuned.to_vec(), 1.0);
+        assert!(after < before / 10.0, "error went from {before} to {after}");
+        // the side with the extra rook won, so a rook somewhere new is
+        // worth having
+        let unseen = position(&[WHITE_KING, BLACK_KING, ("B5", PieceType::Rook, Color::White)], 1.0);
+        assert!(evaluate(&unseen.board, &tuned) > 1.0);
+
+        // sharper scores fit the results better
+        assert!(set.fit_scale(&tuned.to_vec()) > 1.0);
+    }
+
+    #[test]
+    fn test_checkmate_results() {
+        assert_eq!(result_for_white(GameResult::Checkmate, Color::Black), 1.0);
+        assert_eq!(result_for_white(GameResult::Checkmate, Color::White), 0.0);
+        // stalemate is ¾ of a win for the player who gives it
+        assert_eq!(result_for_white(GameResult::Stalemate, Color::White), 0.25);
+        assert_eq!(result_for_white(GameResult::Stalemate, Color::Black), 0.75);
+        assert_eq!(result_for_white(GameResult::FiftyMoveRule, Color::White), 0.5);
+    }
+}
@@ -3,13 +3,17 @@ import { get_hexagon_position } from "./get_hexagon_position.js";
 import { char_to_file, type Hexagon, type Piece, type Rank, Color, HexagonPattern } from "./hexchess_logic";
 import { writable } from "svelte/store";
 
-const board_json = { "op": "BoardState", "board": { "occupied_squares": { }, "en_passant": null, "current_player": "White" } }
+const board_json = { "op": "BoardState", "board": { "occupied_squares": { }, "en_passant": null, "current_player": "White", "halfmove_clock": 0, "fullmove_number": 1, "position_history": [], "variant": "Glinski" } }
 
 export class Board {
     occupied_squares!: Record<Hexagon, Piece>;
     en_passant!: Hexagon | null // although this is property may be null,
     // it is always required in the message
     current_player!: Color
+    halfmove_clock!: number
+    fullmove_number!: number
+    position_history!: string[]
+    variant!: string
 }
 
 
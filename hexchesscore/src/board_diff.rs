@@ -0,0 +1,130 @@
+//! Working out which move turned one board into another.
+//!
+//! Clients that only see a stream of boards, like the web frontend or the
+//! debug server, can use [`infer_move`] to find the move played between two
+//! of them - captures, promotions and en passant included - rather than
+//! guessing from the hexagons that changed.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::{apply_move, Board, Hexagon, Move};
+
+/// Why no move could be found between two boards.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MoveInferenceError {
+    /// Both boards have the same pieces on the same hexagons
+    NothingChanged,
+    /// No single legal move turns the first board into the second. These
+    /// are the hexagons whose contents differ between them.
+    NoLegalMove { changed: Vec<Hexagon> },
+}
+
+impl fmt::Display for MoveInferenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoveInferenceError::NothingChanged => write!(f, "the boards have the same pieces"),
+            MoveInferenceError::NoLegalMove { changed } => {
+                let changed: Vec<String> = changed.iter().map(|hex| hex.to_string()).collect();
+                write!(f, "no legal move changes exactly {}", changed.join(", "))
+            }
+        }
+    }
+}
+
+impl Error for MoveInferenceError {}
+
+/// Every hexagon whose piece differs between the two boards, in order.
+pub fn changed_hexagons(before: &Board, after: &Board) -> Vec<Hexagon> {
+    let mut changed: Vec<Hexagon> = before
+        .occupied_squares
+        .keys()
+        .chain(after.occupied_squares.keys())
+        .filter(|hex| before.occupied_squares.get(hex) != after.occupied_squares.get(hex))
+        .copied()
+        .collect();
+    changed.sort();
+    changed.dedup();
+    changed
+}
+
+/// Find the legal move on `before` that leaves the pieces as they are on
+/// `after`, with the other player to move.
+pub fn infer_move(before: &Board, after: &Board) -> Result<Move, MoveInferenceError> {
+    let changed = changed_hexagons(before, after);
+    if changed.is_empty() {
+        return Err(MoveInferenceError::NothingChanged);
+    }
+
+    before
+        .legal_moves()
+        .into_iter()
+        // the moving piece always leaves its starting hexagon
+        .filter(|movement| changed.contains(&movement.start_hex))
+        .find(|movement| {
+            let mut board = before.clone();
+            apply_move(&mut board, *movement);
+            board.current_player == after.current_player
+                && board.occupied_squares == after.occupied_squares
+        })
+        .ok_or(MoveInferenceError::NoLegalMove { changed })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Color, Piece, PieceType};
+
+    fn hex(name: &str) -> Hexagon {
+        Hexagon::new(name).unwrap()
+    }
+
+    fn played(board: &Board, movement: Move) -> Board {
+        let mut after = board.clone();
+        after.play(movement).unwrap();
+        after
+    }
+
+    #[test]
+    fn test_infer_every_legal_move() {
+        let board = Board::setup_default_board();
+        for movement in board.legal_moves() {
+            assert_eq!(infer_move(&board, &played(&board, movement)), Ok(movement));
+        }
+    }
+
+    #[test]
+    fn test_infer_en_passant_and_promotion() {
+        let mut board = Board::new();
+        board.occupied_squares.insert(hex("F1"), Piece { piece_type: PieceType::King, color: Color::White });
+        board.occupied_squares.insert(hex("F11"), Piece { piece_type: PieceType::King, color: Color::Black });
+        board.occupied_squares.insert(hex("F6"), Piece { piece_type: PieceType::Pawn, color: Color::White });
+        board.occupied_squares.insert(hex("G7"), Piece { piece_type: PieceType::Pawn, color: Color::Black });
+        board.occupied_squares.insert(hex("A5"), Piece { piece_type: PieceType::Pawn, color: Color::White });
+        board.current_player = Color::Black;
+        board.refresh_hash();
+
+        let double_jump = Move { start_hex: hex("G7"), final_hex: hex("G5"), final_piece: PieceType::Pawn };
+        let board = played(&board, double_jump);
+        let en_passant = Move { start_hex: hex("F6"), final_hex: hex("G6"), final_piece: PieceType::Pawn };
+        let after = played(&board, en_passant);
+        assert_eq!(changed_hexagons(&board, &after), vec![hex("F6"), hex("G5"), hex("G6")]);
+        assert_eq!(infer_move(&board, &after), Ok(en_passant));
+
+        let promotion = Move { start_hex: hex("A5"), final_hex: hex("A6"), final_piece: PieceType::Knight };
+        assert_eq!(infer_move(&board, &played(&board, promotion)), Ok(promotion));
+    }
+
+    #[test]
+    fn test_boards_without_a_move_between_them() {
+        let board = Board::setup_default_board();
+        assert_eq!(infer_move(&board, &board), Err(MoveInferenceError::NothingChanged));
+
+        // two moves in a row
+        let first = board.legal_moves()[0];
+        let after = played(&board, first);
+        let second = after.legal_moves()[0];
+        let error = infer_move(&board, &played(&after, second)).unwrap_err();
+        assert!(matches!(error, MoveInferenceError::NoLegalMove { .. }), "{error:?}");
+    }
+}
@@ -1,14 +1,187 @@
 use std::collections::HashMap;
+use std::sync::OnceLock;
 
-use crate::{Board, Color, Hexagon, Piece, PieceType};
+use crate::geometry::{self, NUMBER_OF_HEXES};
+use crate::variants::VariantKind;
+use crate::zobrist;
+use crate::{Board, Color, Hexagon, Move, Piece, PieceType};
 use bitvec::prelude::*;
 
-const RANK_LENGTH: [u32; 11] = [6, 7, 8, 9, 10, 11, 10, 9, 8, 7, 6];
-const CUMULATIVE_HEXES: [u32; 11] = [0, 6, 13, 21, 30, 40, 51, 61, 70, 78, 85];
-
 type SubBoard = u128;
 
-#[derive(PartialEq, Debug)]
+const PIECE_TYPES: [PieceType; 6] = [
+    PieceType::King,
+    PieceType::Queen,
+    PieceType::Rook,
+    PieceType::Bishop,
+    PieceType::Knight,
+    PieceType::Pawn,
+];
+
+/// Where each piece can move from each hexagon on an otherwise empty board,
+/// indexed by bit position. Built once from the [`geometry`] tables, the
+/// first time it's needed. These cover all 91 hexagons - see
+/// [`VariantTables`] for what's in play.
+struct MoveTables {
+    knight: [SubBoard; NUMBER_OF_HEXES],
+    king: [SubBoard; NUMBER_OF_HEXES],
+    /// Every hexagon along each direction from each hexagon. The first six
+    /// directions are orthogonal and the last six diagonal.
+    rays: [[SubBoard; NUMBER_OF_HEXES]; 12],
+    /// Whether stepping along each direction always increases the bit position
+    ray_increases: [bool; 12],
+}
+
+/// The parts of the move tables that depend on the variant being played.
+struct VariantTables {
+    /// The hexagons that are part of the variant's board
+    on_board: SubBoard,
+    /// The hexagons a pawn of each color can capture on, indexed by [color][position]
+    pawn_attacks: [[SubBoard; NUMBER_OF_HEXES]; 2],
+    /// The pawns of each color that can capture on a hexagon, indexed by [color][target]
+    pawn_attackers: [[SubBoard; NUMBER_OF_HEXES]; 2],
+    pawn_pushes: [[SubBoard; NUMBER_OF_HEXES]; 2],
+    pawn_double_jumps: [[SubBoard; NUMBER_OF_HEXES]; 2],
+    promotion_hexes: [SubBoard; 2],
+}
+
+fn color_index(color: Color) -> usize {
+    match color {
+        Color::White => 0,
+        Color::Black => 1,
+    }
+}
+
+fn mask_of(hexes: impl IntoIterator<Item = Hexagon>) -> SubBoard {
+    hexes
+        .into_iter()
+        .fold(0, |mask, target| mask | 1 << geometry::index(&target))
+}
+
+fn build_move_tables() -> MoveTables {
+    let mut tables = MoveTables {
+        knight: [0; NUMBER_OF_HEXES],
+        king: [0; NUMBER_OF_HEXES],
+        rays: [[0; NUMBER_OF_HEXES]; 12],
+        ray_increases: [false; 12],
+    };
+
+    for (dir, (dq, dr)) in geometry::DIRECTIONS.iter().enumerate() {
+        tables.ray_increases[dir] = *dq > 0 || (*dq == 0 && *dr > 0);
+    }
+
+    for (index, hex) in geometry::hexagons().iter().enumerate() {
+        tables.knight[index] = mask_of(geometry::knight_jumps(hex).iter().copied());
+        tables.king[index] = mask_of(geometry::king_moves(hex).iter().copied());
+        for dir in 0..12 {
+            tables.rays[dir][index] = mask_of(geometry::ray(hex, dir).iter().copied());
+        }
+    }
+    tables
+}
+
+fn build_variant_tables(variant: VariantKind) -> VariantTables {
+    let rules = variant.rules();
+    let mut tables = VariantTables {
+        on_board: 0,
+        pawn_attacks: [[0; NUMBER_OF_HEXES]; 2],
+        pawn_attackers: [[0; NUMBER_OF_HEXES]; 2],
+        pawn_pushes: [[0; NUMBER_OF_HEXES]; 2],
+        pawn_double_jumps: [[0; NUMBER_OF_HEXES]; 2],
+        promotion_hexes: [0; 2],
+    };
+    for &hex in geometry::hexagons() {
+        if rules.is_on_board(&hex) {
+            tables.on_board |= 1 << geometry::index(&hex);
+        }
+    }
+
+    for &hex in geometry::hexagons() {
+        let index = geometry::index(&hex);
+        if tables.on_board & 1 << index == 0 {
+            continue;
+        }
+        for color in [Color::White, Color::Black] {
+            let c = color_index(color);
+            if rules.is_promotion_hex(&hex, color) {
+                tables.promotion_hexes[c] |= 1 << index;
+                // a pawn never stays on its last hexagon, so it has no moves from there
+                continue;
+            }
+            let captures = rules
+                .pawn_captures(color)
+                .into_iter()
+                .filter_map(|offset| geometry::offset(&hex, offset));
+            tables.pawn_attacks[c][index] = mask_of(captures) & tables.on_board;
+            let push = geometry::pawn_push(&hex, color);
+            tables.pawn_pushes[c][index] = mask_of(push) & tables.on_board;
+            if rules.can_double_step(&hex, color) {
+                let double_jump = push.and_then(|single| geometry::pawn_push(&single, color));
+                tables.pawn_double_jumps[c][index] = mask_of(double_jump) & tables.on_board;
+            }
+        }
+    }
+
+    for c in 0..2 {
+        for index in 0..NUMBER_OF_HEXES {
+            for_each_bit(tables.pawn_attacks[c][index], |target| {
+                tables.pawn_attackers[c][target] |= 1 << index;
+            });
+        }
+    }
+    tables
+}
+
+fn move_tables() -> &'static MoveTables {
+    static TABLES: OnceLock<MoveTables> = OnceLock::new();
+    TABLES.get_or_init(build_move_tables)
+}
+
+fn variant_tables(variant: VariantKind) -> &'static VariantTables {
+    static TABLES: [OnceLock<VariantTables>; VariantKind::ALL.len()] = [const { OnceLock::new() }; VariantKind::ALL.len()];
+    TABLES[variant as usize].get_or_init(|| build_variant_tables(variant))
+}
+
+fn for_each_bit(mut subboard: SubBoard, mut f: impl FnMut(usize)) {
+    while subboard != 0 {
+        f(subboard.trailing_zeros() as usize);
+        subboard &= subboard - 1;
+    }
+}
+
+/// The hexagons a sliding piece on `index` attacks along one direction,
+/// up to and including the first piece in the way
+fn ray_attacks(tables: &MoveTables, dir: usize, index: usize, occupied: SubBoard) -> SubBoard {
+    let ray = tables.rays[dir][index];
+    let blockers = ray & occupied;
+    if blockers == 0 {
+        return ray;
+    }
+    let first_blocker = if tables.ray_increases[dir] {
+        blockers.trailing_zeros()
+    } else {
+        127 - blockers.leading_zeros()
+    };
+    ray ^ tables.rays[dir][first_blocker as usize]
+}
+
+fn orthogonal_attacks(tables: &MoveTables, index: usize, occupied: SubBoard) -> SubBoard {
+    (0..6).fold(0, |attacks, dir| attacks | ray_attacks(tables, dir, index, occupied))
+}
+
+fn diagonal_attacks(tables: &MoveTables, index: usize, occupied: SubBoard) -> SubBoard {
+    (6..12).fold(0, |attacks, dir| attacks | ray_attacks(tables, dir, index, occupied))
+}
+
+/// A position as one bitmask per piece, with a legal move generator that
+/// works on the masks directly and agrees move for move with
+/// [`Board::legal_moves`].
+///
+/// bumblebot's search doesn't use it yet. It evaluates and keeps its tables
+/// on a [`Board`], and converting to a `BitBoard` at every node costs about
+/// what the faster move generation saves, so moving the search over is
+/// left for when the evaluation can read the masks too.
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub struct BitBoard {
     white_king: SubBoard,
     white_queen: SubBoard,
@@ -23,6 +196,11 @@ pub struct BitBoard {
     black_knight: SubBoard,
     black_pawn: SubBoard,
     current_player_is_white: bool,
+    /// The pawn that has just double jumped, and so can be taken en passant
+    en_passant: Option<Hexagon>,
+    variant: VariantKind,
+    /// The position's Zobrist hash, matching [`Board::hash`]
+    hash: u64,
 }
 
 impl BitBoard {
@@ -41,6 +219,9 @@ impl BitBoard {
             black_knight: 0,
             black_pawn: 0,
             current_player_is_white: true,
+            en_passant: None,
+            variant: VariantKind::default(),
+            hash: 0,
         }
     }
     pub fn get_subboard(&self, piece: Piece) -> &SubBoard {
@@ -88,47 +269,42 @@ impl BitBoard {
     /// bit matching the relevant position in all of the BitBoard
     /// types' bit boards.
     pub fn bit_mask_from_hexagon(hex: Hexagon) -> SubBoard {
-        let position = CUMULATIVE_HEXES[hex.rank as usize] + hex.file as u32;
-        (2 as SubBoard).pow(position)
+        1 << geometry::index(&hex)
     }
 
     pub fn hexagon_from_bit_position(position: u32) -> Option<Hexagon> {
-        for (i, offset) in CUMULATIVE_HEXES.iter().enumerate().rev() {
-            if position > *offset - 1 {
-                return Some(Hexagon {
-                    rank: i as u8,
-                    file: (position - offset) as u8,
-                });
-            }
+        if position as usize >= NUMBER_OF_HEXES {
+            return None;
         }
-        None
+        Some(geometry::hexagon(position as usize))
     }
 
     pub fn get_mask_at_hex(hex: Hexagon) -> SubBoard {
         BitBoard::bit_mask_from_hexagon(hex)
     }
 
-    // Implicitly assumes the piece is in fact at this position.
-    // If that assumption is wrong, it will mistakenly insert
-    // a piece at the specified position.
+    /// Put a piece on a hexagon, replacing whatever was there before.
     pub fn insert_piece(&mut self, piece: Piece, hex: Hexagon) {
+        self.clear_hex(hex);
         let sub_board = self.get_subboard(piece);
-        self.set_subboard(piece, sub_board ^ BitBoard::get_mask_at_hex(hex));
+        self.set_subboard(piece, sub_board | BitBoard::get_mask_at_hex(hex));
+    }
 
-        // remove any enemy pieces that might have been in this position
-        for piece_type in [
-            PieceType::King,
-            PieceType::Queen,
-            PieceType::Rook,
-            PieceType::Bishop,
-            PieceType::Knight,
-            PieceType::Pawn,
-        ] {
-            let color = piece.color.invert();
-            let subboard = self.get_subboard(Piece{piece_type, color});
-            let mask = BitBoard::get_mask_at_hex(hex);
-            self.set_subboard(piece, (!*subboard) & mask);
+    /// Remove whatever piece is on a hexagon, returning it.
+    fn clear_hex(&mut self, hex: Hexagon) -> Option<Piece> {
+        let mask = BitBoard::get_mask_at_hex(hex);
+        let mut removed = None;
+        for color in [Color::White, Color::Black] {
+            for piece_type in PIECE_TYPES {
+                let piece = Piece { piece_type, color };
+                let subboard = *self.get_subboard(piece);
+                if subboard & mask != 0 {
+                    self.set_subboard(piece, subboard & !mask);
+                    removed = Some(piece);
+                }
+            }
         }
+        removed
     }
 
     pub fn from_board(board: &Board) -> Self {
@@ -136,12 +312,14 @@ impl BitBoard {
         for (hex, piece) in &board.occupied_squares {
             bitboard.insert_piece(*piece, *hex);
         }
-        // TODO: transfer across the double-jumped pawn mappings
-        bitboard.current_player_is_white = if board.current_player == Color::White {
-            true
-        } else {
-            false
-        };
+        bitboard.en_passant = board.en_passant;
+        bitboard.variant = board.variant;
+        bitboard.current_player_is_white = board.current_player == Color::White;
+        bitboard.hash = zobrist::hash_position(
+            &board.occupied_squares,
+            board.current_player,
+            board.en_passant,
+        );
         bitboard
     }
 
@@ -162,12 +340,17 @@ impl BitBoard {
     pub fn to_board(&self) -> Board {
         let mut board = Board {
             occupied_squares: HashMap::<Hexagon, Piece>::new(),
-            en_passant: None,
+            en_passant: self.en_passant,
             current_player: if self.current_player_is_white {
                 Color::White
             } else {
                 Color::Black
             },
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            position_history: Vec::new(),
+            variant: self.variant,
+            hash: 0,
         };
         for color in [Color::Black, Color::White] {
             for piece_type in [
@@ -187,8 +370,211 @@ impl BitBoard {
                 }
             }
         }
+        board.refresh_hash();
         board
     }
+
+    pub fn current_player(&self) -> Color {
+        if self.current_player_is_white {
+            Color::White
+        } else {
+            Color::Black
+        }
+    }
+
+    /// All the pieces of one color
+    fn pieces(&self, color: Color) -> SubBoard {
+        PIECE_TYPES.iter().fold(0, |pieces, piece_type| {
+            pieces
+                | self.get_subboard(Piece {
+                    piece_type: *piece_type,
+                    color,
+                })
+        })
+    }
+
+    fn piece_at(&self, index: usize, color: Color) -> Option<PieceType> {
+        PIECE_TYPES.iter().copied().find(|piece_type| {
+            self.get_subboard(Piece {
+                piece_type: *piece_type,
+                color,
+            }) & 1 << index
+                != 0
+        })
+    }
+
+    /// Whether any piece of `attacker` attacks the hexagon at bit `index`
+    fn is_attacked(&self, index: usize, attacker: Color) -> bool {
+        let tables = move_tables();
+        let variant_tables = variant_tables(self.variant);
+        let occupied = self.pieces(Color::White) | self.pieces(Color::Black);
+        let piece = |piece_type| *self.get_subboard(Piece { piece_type, color: attacker });
+        let queens = piece(PieceType::Queen);
+
+        tables.knight[index] & piece(PieceType::Knight) != 0
+            || tables.king[index] & piece(PieceType::King) != 0
+            || variant_tables.pawn_attackers[color_index(attacker)][index] & piece(PieceType::Pawn) != 0
+            || orthogonal_attacks(tables, index, occupied) & (piece(PieceType::Rook) | queens) != 0
+            || diagonal_attacks(tables, index, occupied) & (piece(PieceType::Bishop) | queens) != 0
+    }
+
+    /// Whether the king of the given color is under attack. A board
+    /// without that king is never in check.
+    pub fn king_is_in_check(&self, color: Color) -> bool {
+        let king = *self.get_subboard(Piece {
+            piece_type: PieceType::King,
+            color,
+        });
+        king != 0 && self.is_attacked(king.trailing_zeros() as usize, color.invert())
+    }
+
+    /// Every move the current player could make if leaving their own king
+    /// in check were allowed.
+    fn pseudo_legal_moves(&self) -> Vec<Move> {
+        let tables = move_tables();
+        let variant_tables = variant_tables(self.variant);
+        let color = self.current_player();
+        let c = color_index(color);
+        let ours = self.pieces(color);
+        let theirs = self.pieces(color.invert());
+        let occupied = ours | theirs;
+
+        let mut moves = Vec::<Move>::with_capacity(64);
+        let mut push_moves = |start: usize, targets: SubBoard, piece_type: PieceType| {
+            for_each_bit(targets, |target| {
+                let movement = Move {
+                    start_hex: geometry::hexagon(start),
+                    final_hex: geometry::hexagon(target),
+                    final_piece: piece_type,
+                };
+                if piece_type == PieceType::Pawn && variant_tables.promotion_hexes[c] & 1 << target != 0 {
+                    for final_piece in [
+                        PieceType::Bishop,
+                        PieceType::Knight,
+                        PieceType::Rook,
+                        PieceType::Queen,
+                    ] {
+                        moves.push(Move {
+                            final_piece,
+                            ..movement
+                        });
+                    }
+                } else {
+                    moves.push(movement);
+                }
+            });
+        };
+
+        for piece_type in PIECE_TYPES {
+            let subboard = *self.get_subboard(Piece { piece_type, color });
+            for_each_bit(subboard, |start| {
+                let targets = match piece_type {
+                    PieceType::King => tables.king[start],
+                    PieceType::Knight => tables.knight[start],
+                    PieceType::Rook => orthogonal_attacks(tables, start, occupied),
+                    PieceType::Bishop => diagonal_attacks(tables, start, occupied),
+                    PieceType::Queen => {
+                        orthogonal_attacks(tables, start, occupied)
+                            | diagonal_attacks(tables, start, occupied)
+                    }
+                    PieceType::Pawn => {
+                        let mut targets = variant_tables.pawn_attacks[c][start] & theirs;
+                        let push = variant_tables.pawn_pushes[c][start] & !occupied;
+                        if push != 0 {
+                            targets |= push;
+                            targets |= variant_tables.pawn_double_jumps[c][start] & !occupied;
+                        }
+                        if let Some(target) = self.en_passant_target() {
+                            targets |= variant_tables.pawn_attacks[c][start] & 1 << target;
+                        }
+                        targets
+                    }
+                };
+                push_moves(start, targets & variant_tables.on_board & !ours, piece_type);
+            });
+        }
+        moves
+    }
+
+    /// The empty hexagon the current player's pawns can capture onto to
+    /// take the pawn that has just double jumped
+    fn en_passant_target(&self) -> Option<usize> {
+        let pawn = self.en_passant?;
+        let color = self.current_player();
+        let enemy_pawns = *self.get_subboard(Piece {
+            piece_type: PieceType::Pawn,
+            color: color.invert(),
+        });
+        if enemy_pawns & BitBoard::get_mask_at_hex(pawn) == 0 {
+            return None;
+        }
+        // the target is the hexagon the pawn jumped over
+        let target = geometry::pawn_push(&pawn, color)?;
+        Some(geometry::index(&target))
+    }
+
+    /// The position's Zobrist hash. See [`Board::hash`].
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// All the legal moves available to the current player.
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let color = self.current_player();
+        let mut moves = self.pseudo_legal_moves();
+        moves.retain(|movement| {
+            let mut board = *self;
+            board.apply_move(*movement);
+            !board.king_is_in_check(color)
+        });
+        moves
+    }
+
+    /// Play a move, assumed to be legal, and hand the turn to the other player.
+    /// There's no undo - copy the board beforehand to be able to go back.
+    pub fn apply_move(&mut self, movement: Move) {
+        let color = self.current_player();
+        let start = geometry::index(&movement.start_hex);
+        let piece_type = self
+            .piece_at(start, color)
+            .expect("Piece wasn't present at start hex");
+        self.clear_hex(movement.start_hex);
+        self.hash ^= zobrist::piece_key(Piece { piece_type, color }, movement.start_hex);
+
+        let captured = self.clear_hex(movement.final_hex);
+        if let Some(piece) = captured {
+            self.hash ^= zobrist::piece_key(piece, movement.final_hex);
+        }
+        // pawns only change rank when capturing, so a pawn landing on an empty
+        // hexagon in a different rank must be taking en passant
+        if piece_type == PieceType::Pawn
+            && captured.is_none()
+            && movement.start_hex.rank != movement.final_hex.rank
+            && self.en_passant_target() == Some(geometry::index(&movement.final_hex))
+        {
+            if let Some(pawn) = self.en_passant {
+                if let Some(piece) = self.clear_hex(pawn) {
+                    self.hash ^= zobrist::piece_key(piece, pawn);
+                }
+            }
+        }
+
+        let double_jump = piece_type == PieceType::Pawn
+            && movement.start_hex.rank == movement.final_hex.rank
+            && movement.start_hex.file.abs_diff(movement.final_hex.file) == 2;
+        self.hash ^= zobrist::en_passant_key(self.en_passant);
+        self.en_passant = double_jump.then_some(movement.final_hex);
+        self.hash ^= zobrist::en_passant_key(self.en_passant);
+
+        let final_piece = Piece {
+            piece_type: movement.final_piece,
+            color,
+        };
+        self.insert_piece(final_piece, movement.final_hex);
+        self.hash ^= zobrist::piece_key(final_piece, movement.final_hex);
+        self.current_player_is_white = !self.current_player_is_white;
+        self.hash ^= zobrist::side_to_move_key(Color::Black);
+    }
 }
 
 #[cfg(test)]
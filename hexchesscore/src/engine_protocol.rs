@@ -0,0 +1,633 @@
+//! A line based protocol for talking to engines, modelled on UCI. The
+//! controller - a GUI, the server or a tournament runner - writes commands
+//! to the engine's standard input, and the engine answers on its standard
+//! output.
+//!
+//! Commands to the engine:
+//!
+//! - `uci` - the engine says who it is with `id name` and `id author`, then
+//!   `uciok`
+//! - `isready` - the engine answers `readyok`, even while it's searching
+//! - `ucinewgame` - the next position is from a new game
+//! - `setoption name <name> value <value>`
+//! - `position startpos [moves <move>...]` or
+//!   `position fen <position> [moves <move>...]`, with the position in the
+//!   notation from [`crate::notation`]. `startpos` is Gliński's start; the
+//!   other variants are given as a `fen`, whose sixth field names the variant
+//! - `go` with any of `movetime <ms>`, `depth <plies>`,
+//!   `wtime <ms> btime <ms> winc <ms> binc <ms>`, or `infinite`
+//! - `stop` - finish the search now, and answer with the best move so far
+//! - `quit`
+//!
+//! Answers from the engine:
+//!
+//! - `id name <name>` and `id author <author>`
+//! - `uciok` and `readyok`
+//! - `info` with any of `depth <plies>`, `score cp <centipawns>`,
+//!   `score mate <moves>`, `nodes <count>`, `time <ms>`, `pv <move>...` and
+//!   `string <text>`, which takes up the rest of the line
+//! - `bestmove <move>`, or `bestmove (none)` when there's no legal move
+//!
+//! Moves are written as their start and final hexagons, e.g. `f5f6`, with
+//! the letter of the piece a pawn promotes to on the end, e.g. `f10f11q`.
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::notation::NotationError;
+use crate::{Board, HexChessError, Hexagon, Move, PieceType};
+
+/// Why a line of the protocol couldn't be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    /// The line was empty
+    Empty,
+    /// The line doesn't start with a command the protocol knows
+    UnknownCommand(String),
+    /// A keyword needs a value after it, but there wasn't one
+    MissingValue(String),
+    /// A keyword's value isn't one it can take
+    InvalidValue { keyword: String, value: String },
+    InvalidPosition(NotationError),
+    /// The text isn't shaped like a move
+    InvalidMove(String),
+    /// The move can't be played in the position it's meant for
+    IllegalMove { movement: String, reason: HexChessError },
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Empty => write!(f, "the line is empty"),
+            ProtocolError::UnknownCommand(command) => write!(f, "unknown command '{command}'"),
+            ProtocolError::MissingValue(keyword) => write!(f, "'{keyword}' needs a value"),
+            ProtocolError::InvalidValue { keyword, value } => {
+                write!(f, "'{value}' is not a valid value for '{keyword}'")
+            }
+            ProtocolError::InvalidPosition(error) => write!(f, "invalid position: {error}"),
+            ProtocolError::InvalidMove(movement) => write!(f, "'{movement}' is not a move"),
+            ProtocolError::IllegalMove { movement, reason } => {
+                write!(f, "'{movement}' can't be played: {reason}")
+            }
+        }
+    }
+}
+
+impl Error for ProtocolError {}
+
+/// A move as the protocol writes it. It only says which hexagons the move
+/// goes between, so it needs the position it's played in to become a
+/// [`Move`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoordinateMove {
+    pub start: Hexagon,
+    pub end: Hexagon,
+    /// The piece a pawn turns into, if the move is a promotion
+    pub promotion: Option<PieceType>,
+}
+
+impl CoordinateMove {
+    /// Write `movement`, played on `board`, as the protocol does.
+    pub fn from_move(board: &Board, movement: Move) -> CoordinateMove {
+        let promotes = board
+            .occupied_squares
+            .get(&movement.start_hex)
+            .is_some_and(|piece| piece.piece_type != movement.final_piece);
+        CoordinateMove {
+            start: movement.start_hex,
+            end: movement.final_hex,
+            promotion: promotes.then_some(movement.final_piece),
+        }
+    }
+
+    /// The move on `board` this stands for. It isn't checked for legality,
+    /// which [`Board::play`] will do.
+    pub fn to_move(&self, board: &Board) -> Move {
+        let final_piece = self.promotion.unwrap_or_else(|| {
+            board
+                .occupied_squares
+                .get(&self.start)
+                .map_or(PieceType::Pawn, |piece| piece.piece_type)
+        });
+        Move {
+            start_hex: self.start,
+            final_hex: self.end,
+            final_piece,
+        }
+    }
+}
+
+fn promotion_letter(piece_type: PieceType) -> char {
+    match piece_type {
+        PieceType::Queen => 'q',
+        PieceType::Rook => 'r',
+        PieceType::Bishop => 'b',
+        PieceType::Knight => 'n',
+        PieceType::Pawn => 'p',
+        PieceType::King => 'k',
+    }
+}
+
+fn promotion_piece(letter: char) -> Option<PieceType> {
+    match letter {
+        'q' => Some(PieceType::Queen),
+        'r' => Some(PieceType::Rook),
+        'b' => Some(PieceType::Bishop),
+        'n' => Some(PieceType::Knight),
+        _ => None,
+    }
+}
+
+impl fmt::Display for CoordinateMove {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.start, self.end)?;
+        if let Some(piece_type) = self.promotion {
+            write!(f, "{}", promotion_letter(piece_type))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for CoordinateMove {
+    type Err = ProtocolError;
+
+    fn from_str(text: &str) -> Result<CoordinateMove, ProtocolError> {
+        let invalid = || ProtocolError::InvalidMove(text.to_string());
+        // the final hexagon starts at the second letter
+        let split = text
+            .char_indices()
+            .skip(1)
+            .find(|(_, c)| c.is_ascii_alphabetic())
+            .map(|(index, _)| index)
+            .ok_or_else(invalid)?;
+        let (start, rest) = text.split_at(split);
+        let (end, promotion) = match rest.char_indices().last() {
+            Some((index, last)) if last.is_ascii_alphabetic() && index > 0 => {
+                (&rest[..index], Some(promotion_piece(last).ok_or_else(invalid)?))
+            }
+            _ => (rest, None),
+        };
+        Ok(CoordinateMove {
+            start: Hexagon::new(start).ok_or_else(invalid)?,
+            end: Hexagon::new(end).ok_or_else(invalid)?,
+            promotion,
+        })
+    }
+}
+
+/// Play `moves` one after another from `start`.
+pub fn play_moves(start: &Board, moves: &[CoordinateMove]) -> Result<Board, ProtocolError> {
+    let mut board = start.clone();
+    for movement in moves {
+        board
+            .play(movement.to_move(&board))
+            .map_err(|reason| ProtocolError::IllegalMove {
+                movement: movement.to_string(),
+                reason,
+            })?;
+    }
+    Ok(board)
+}
+
+/// When the engine should stop searching, as given to `go`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GoLimits {
+    pub movetime: Option<Duration>,
+    pub depth: Option<u32>,
+    pub white_time: Option<Duration>,
+    pub black_time: Option<Duration>,
+    pub white_increment: Option<Duration>,
+    pub black_increment: Option<Duration>,
+    /// Search until told to stop
+    pub infinite: bool,
+}
+
+/// A command from the controller to the engine.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Uci,
+    IsReady,
+    NewGame,
+    SetOption { name: String, value: String },
+    Position { start: Board, moves: Vec<CoordinateMove> },
+    Go(GoLimits),
+    Stop,
+    Quit,
+}
+
+fn value<'a>(keyword: &str, tokens: &mut impl Iterator<Item = &'a str>) -> Result<&'a str, ProtocolError> {
+    tokens
+        .next()
+        .ok_or_else(|| ProtocolError::MissingValue(keyword.to_string()))
+}
+
+fn number<'a, T: FromStr>(keyword: &str, tokens: &mut impl Iterator<Item = &'a str>) -> Result<T, ProtocolError> {
+    let text = value(keyword, tokens)?;
+    text.parse().map_err(|_| ProtocolError::InvalidValue {
+        keyword: keyword.to_string(),
+        value: text.to_string(),
+    })
+}
+
+fn milliseconds<'a>(keyword: &str, tokens: &mut impl Iterator<Item = &'a str>) -> Result<Duration, ProtocolError> {
+    number(keyword, tokens).map(Duration::from_millis)
+}
+
+fn parse_moves<'a>(tokens: impl Iterator<Item = &'a str>) -> Result<Vec<CoordinateMove>, ProtocolError> {
+    tokens.map(str::parse).collect()
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, ProtocolError> {
+        let mut tokens = line.split_whitespace();
+        let command = tokens.next().ok_or(ProtocolError::Empty)?;
+        match command {
+            "uci" => Ok(Command::Uci),
+            "isready" => Ok(Command::IsReady),
+            "ucinewgame" => Ok(Command::NewGame),
+            "stop" => Ok(Command::Stop),
+            "quit" => Ok(Command::Quit),
+            "setoption" => {
+                // names and values can both have spaces in them
+                let rest: Vec<&str> = tokens.collect();
+                let name_at = rest.iter().position(|token| *token == "name");
+                let value_at = rest.iter().position(|token| *token == "value");
+                let Some(name_at) = name_at else {
+                    return Err(ProtocolError::MissingValue("name".to_string()));
+                };
+                let name_end = value_at.filter(|at| *at > name_at).unwrap_or(rest.len());
+                let value = value_at.map_or(String::new(), |at| rest[at + 1..].join(" "));
+                Ok(Command::SetOption {
+                    name: rest[name_at + 1..name_end].join(" "),
+                    value,
+                })
+            }
+            "position" => {
+                let start = match value("position", &mut tokens)? {
+                    "startpos" => Board::setup_default_board(),
+                    "fen" => {
+                        let fields: Vec<&str> = tokens.by_ref().take_while(|token| *token != "moves").collect();
+                        let board = Board::from_notation(&fields.join(" ")).map_err(ProtocolError::InvalidPosition)?;
+                        return Ok(Command::Position {
+                            start: board,
+                            moves: parse_moves(tokens)?,
+                        });
+                    }
+                    other => {
+                        return Err(ProtocolError::InvalidValue {
+                            keyword: "position".to_string(),
+                            value: other.to_string(),
+                        })
+                    }
+                };
+                match tokens.next() {
+                    None => Ok(Command::Position { start, moves: Vec::new() }),
+                    Some("moves") => Ok(Command::Position {
+                        start,
+                        moves: parse_moves(tokens)?,
+                    }),
+                    Some(other) => Err(ProtocolError::InvalidValue {
+                        keyword: "startpos".to_string(),
+                        value: other.to_string(),
+                    }),
+                }
+            }
+            "go" => {
+                let mut limits = GoLimits::default();
+                while let Some(keyword) = tokens.next() {
+                    match keyword {
+                        "movetime" => limits.movetime = Some(milliseconds(keyword, &mut tokens)?),
+                        "depth" => limits.depth = Some(number(keyword, &mut tokens)?),
+                        "wtime" => limits.white_time = Some(milliseconds(keyword, &mut tokens)?),
+                        "btime" => limits.black_time = Some(milliseconds(keyword, &mut tokens)?),
+                        "winc" => limits.white_increment = Some(milliseconds(keyword, &mut tokens)?),
+                        "binc" => limits.black_increment = Some(milliseconds(keyword, &mut tokens)?),
+                        "infinite" => limits.infinite = true,
+                        other => {
+                            return Err(ProtocolError::InvalidValue {
+                                keyword: "go".to_string(),
+                                value: other.to_string(),
+                            })
+                        }
+                    }
+                }
+                Ok(Command::Go(limits))
+            }
+            other => Err(ProtocolError::UnknownCommand(other.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Uci => write!(f, "uci"),
+            Command::IsReady => write!(f, "isready"),
+            Command::NewGame => write!(f, "ucinewgame"),
+            Command::SetOption { name, value } => write!(f, "setoption name {name} value {value}"),
+            Command::Position { start, moves } => {
+                if start.to_notation() == Board::setup_default_board().to_notation() {
+                    write!(f, "position startpos")?;
+                } else {
+                    write!(f, "position fen {}", start.to_notation())?;
+                }
+                if !moves.is_empty() {
+                    write!(f, " moves")?;
+                    for movement in moves {
+                        write!(f, " {movement}")?;
+                    }
+                }
+                Ok(())
+            }
+            Command::Go(limits) => {
+                write!(f, "go")?;
+                let times = [
+                    ("movetime", limits.movetime),
+                    ("wtime", limits.white_time),
+                    ("btime", limits.black_time),
+                    ("winc", limits.white_increment),
+                    ("binc", limits.black_increment),
+                ];
+                for (keyword, time) in times {
+                    if let Some(time) = time {
+                        write!(f, " {keyword} {}", time.as_millis())?;
+                    }
+                }
+                if let Some(depth) = limits.depth {
+                    write!(f, " depth {depth}")?;
+                }
+                if limits.infinite {
+                    write!(f, " infinite")?;
+                }
+                Ok(())
+            }
+            Command::Stop => write!(f, "stop"),
+            Command::Quit => write!(f, "quit"),
+        }
+    }
+}
+
+/// How good the engine thinks the position is for the side to move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InfoScore {
+    /// In hundredths of a pawn
+    Centipawns(i32),
+    /// Mate in this many moves, negative if it's the side to move being
+    /// mated
+    Mate(i32),
+}
+
+/// What the engine says about its search. Every field is optional.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Info {
+    pub depth: Option<u32>,
+    pub score: Option<InfoScore>,
+    pub nodes: Option<u64>,
+    pub time: Option<Duration>,
+    pub pv: Vec<CoordinateMove>,
+    /// Free text, for anything else the engine wants to say
+    pub string: Option<String>,
+}
+
+/// An answer from the engine to the controller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    IdName(String),
+    IdAuthor(String),
+    UciOk,
+    ReadyOk,
+    Info(Info),
+    /// None when there's no legal move to play
+    BestMove(Option<CoordinateMove>),
+}
+
+impl Response {
+    pub fn parse(line: &str) -> Result<Response, ProtocolError> {
+        let mut tokens = line.split_whitespace();
+        let response = tokens.next().ok_or(ProtocolError::Empty)?;
+        match response {
+            "uciok" => Ok(Response::UciOk),
+            "readyok" => Ok(Response::ReadyOk),
+            "id" => {
+                let kind = value("id", &mut tokens)?;
+                let text = tokens.collect::<Vec<_>>().join(" ");
+                match kind {
+                    "name" => Ok(Response::IdName(text)),
+                    "author" => Ok(Response::IdAuthor(text)),
+                    other => Err(ProtocolError::InvalidValue {
+                        keyword: "id".to_string(),
+                        value: other.to_string(),
+                    }),
+                }
+            }
+            "bestmove" => match value("bestmove", &mut tokens)? {
+                "(none)" => Ok(Response::BestMove(None)),
+                movement => Ok(Response::BestMove(Some(movement.parse()?))),
+            },
+            "info" => {
+                let mut info = Info::default();
+                while let Some(keyword) = tokens.next() {
+                    match keyword {
+                        "depth" => info.depth = Some(number(keyword, &mut tokens)?),
+                        "nodes" => info.nodes = Some(number(keyword, &mut tokens)?),
+                        "time" => info.time = Some(milliseconds(keyword, &mut tokens)?),
+                        "score" => {
+                            info.score = Some(match value(keyword, &mut tokens)? {
+                                "cp" => InfoScore::Centipawns(number("cp", &mut tokens)?),
+                                "mate" => InfoScore::Mate(number("mate", &mut tokens)?),
+                                other => {
+                                    return Err(ProtocolError::InvalidValue {
+                                        keyword: "score".to_string(),
+                                        value: other.to_string(),
+                                    })
+                                }
+                            })
+                        }
+                        // the principal variation runs to the next keyword
+                        "pv" => {
+                            while let Some(movement) = tokens.clone().next().and_then(|token| token.parse().ok()) {
+                                info.pv.push(movement);
+                                tokens.next();
+                            }
+                        }
+                        "string" => {
+                            info.string = Some(tokens.by_ref().collect::<Vec<_>>().join(" "));
+                        }
+                        // other engines may say more than we understand
+                        _ => {}
+                    }
+                }
+                Ok(Response::Info(info))
+            }
+            other => Err(ProtocolError::UnknownCommand(other.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Response::IdName(name) => write!(f, "id name {name}"),
+            Response::IdAuthor(author) => write!(f, "id author {author}"),
+            Response::UciOk => write!(f, "uciok"),
+            Response::ReadyOk => write!(f, "readyok"),
+            Response::Info(info) => {
+                write!(f, "info")?;
+                if let Some(depth) = info.depth {
+                    write!(f, " depth {depth}")?;
+                }
+                match info.score {
+                    Some(InfoScore::Centipawns(centipawns)) => write!(f, " score cp {centipawns}")?,
+                    Some(InfoScore::Mate(moves)) => write!(f, " score mate {moves}")?,
+                    None => {}
+                }
+                if let Some(nodes) = info.nodes {
+                    write!(f, " nodes {nodes}")?;
+                }
+                if let Some(time) = info.time {
+                    write!(f, " time {}", time.as_millis())?;
+                }
+                if !info.pv.is_empty() {
+                    write!(f, " pv")?;
+                    for movement in &info.pv {
+                        write!(f, " {movement}")?;
+                    }
+                }
+                if let Some(string) = &info.string {
+                    write!(f, " string {string}")?;
+                }
+                Ok(())
+            }
+            Response::BestMove(Some(movement)) => write!(f, "bestmove {movement}"),
+            Response::BestMove(None) => write!(f, "bestmove (none)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variants::VariantKind;
+    use crate::{Color, Piece};
+
+    fn hex(name: &str) -> Hexagon {
+        Hexagon::new(name).unwrap()
+    }
+
+    #[test]
+    fn test_moves_round_trip() {
+        let board = Board::setup_default_board();
+        for movement in board.legal_moves() {
+            let written = CoordinateMove::from_move(&board, movement);
+            let read: CoordinateMove = written.to_string().parse().unwrap();
+            assert_eq!(read.to_move(&board), movement);
+        }
+
+        let promotion: CoordinateMove = "f10f11q".parse().unwrap();
+        assert_eq!(promotion.start, hex("f10"));
+        assert_eq!(promotion.end, hex("f11"));
+        assert_eq!(promotion.promotion, Some(PieceType::Queen));
+        assert_eq!(promotion.to_string(), "f10f11q");
+
+        let mut board = Board::new();
+        let pawn = Piece { piece_type: PieceType::Pawn, color: Color::White };
+        board.occupied_squares.insert(hex("f10"), pawn);
+        assert_eq!(CoordinateMove::from_move(&board, promotion.to_move(&board)), promotion);
+
+        for invalid in ["", "f5", "f5f", "j1f5", "f5f6x", "f12f13"] {
+            assert!(invalid.parse::<CoordinateMove>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_commands_round_trip() {
+        let after_e4: Board = play_moves(&Board::setup_default_board(), &["e4e5".parse().unwrap()]).unwrap();
+        let commands = [
+            "uci",
+            "isready",
+            "ucinewgame",
+            "setoption name hash value 16",
+            "position startpos",
+            "position startpos moves e4e5 e7e6",
+            &format!("position fen {} moves c7c6", after_e4.to_notation()),
+            "go movetime 1000",
+            "go wtime 60000 btime 55000 winc 1000 binc 1000 depth 4",
+            "go infinite",
+            "stop",
+            "quit",
+        ];
+        for line in commands {
+            let command = Command::parse(line).unwrap();
+            assert_eq!(command.to_string(), line);
+        }
+
+        assert_eq!(
+            Command::parse("setoption name Evaluation File value my weights.json"),
+            Ok(Command::SetOption {
+                name: "Evaluation File".to_string(),
+                value: "my weights.json".to_string()
+            })
+        );
+        assert_eq!(Command::parse("  "), Err(ProtocolError::Empty));
+        assert_eq!(Command::parse("go depth"), Err(ProtocolError::MissingValue("depth".to_string())));
+        assert!(matches!(Command::parse("position fen 6/P5p w"), Err(ProtocolError::InvalidPosition(_))));
+        assert_eq!(Command::parse("castle"), Err(ProtocolError::UnknownCommand("castle".to_string())));
+    }
+
+    #[test]
+    fn test_positions_keep_their_variant() {
+        for (variant, moves) in [(VariantKind::McCooey, 32), (VariantKind::Shafran, 38)] {
+            let command = Command::Position {
+                start: Board::setup_variant(variant),
+                moves: Vec::new(),
+            };
+            let Ok(Command::Position { start, .. }) = Command::parse(&command.to_string()) else {
+                panic!("couldn't read {command}");
+            };
+            assert_eq!(start.variant, variant);
+            assert_eq!(start.legal_moves().len(), moves, "{variant}");
+        }
+    }
+
+    #[test]
+    fn test_playing_the_moves_of_a_position() {
+        let Ok(Command::Position { start, moves }) = Command::parse("position startpos moves e4e5 e7e6") else {
+            panic!("couldn't read the position");
+        };
+        let board = play_moves(&start, &moves).unwrap();
+        assert_eq!(board.current_player, Color::White);
+        assert_eq!(board.position_history.len(), 2);
+
+        // black's pawn can't move twice in a row
+        let moves: Vec<CoordinateMove> = vec!["f7f6".parse().unwrap()];
+        assert!(matches!(
+            play_moves(&start, &moves),
+            Err(ProtocolError::IllegalMove { reason: HexChessError::WrongColor(_), .. })
+        ));
+    }
+
+    #[test]
+    fn test_responses_round_trip() {
+        let lines = [
+            "id name bumblebot",
+            "id author hexchess",
+            "uciok",
+            "readyok",
+            "info depth 5 score cp -35 nodes 12345 time 250 pv e4e5 e7e6",
+            "info score mate 2",
+            "info string searching with 4 threads",
+            "bestmove f10f11q",
+            "bestmove (none)",
+        ];
+        for line in lines {
+            assert_eq!(Response::parse(line).unwrap().to_string(), line);
+        }
+        let Ok(Response::Info(info)) = Response::parse("info depth 3 seldepth 7 pv e4e5 d7d6 nps 100") else {
+            panic!("couldn't read the info");
+        };
+        assert_eq!(info.depth, Some(3));
+        assert_eq!(info.pv.len(), 2);
+    }
+}
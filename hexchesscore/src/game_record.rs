@@ -0,0 +1,677 @@
+//! A record of a whole game, with a PGN-style text format for saving and
+//! loading it. For example
+//!
+//! ```text
+//! [Event "Casual game"]
+//! [White "alice"]
+//! [Black "bumblebot"]
+//! [Result "1-0"]
+//!
+//! 1. e6 {[%clk 0:04:58] bold} 1... Nc6 2. Qe2 Ni6 1-0
+//! ```
+//!
+//! Moves are written in algebraic notation (see [`crate::san`]) with the
+//! hexagonal coordinates used everywhere else. A game that doesn't start from
+//! the usual position stores its starting position in a `FEN` tag, written
+//! in the notation from [`crate::notation`], and one played by other rules
+//! than Gliński's names them in a `Variant` tag. Comments go in braces after the
+//! move they're about, and can hold the mover's remaining time as
+//! `[%clk h:mm:ss]`.
+//!
+//! Stalemate isn't a draw under Gliński's rules: the player who gives it
+//! takes ¾ of the point, so those games end `3/4-1/4` or `1/4-3/4`.
+
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+use crate::notation::NotationError;
+use crate::san::{parse_san, to_san, SanError};
+use crate::variants::VariantKind;
+use crate::{apply_move, Board, Color, GameResult, HexChessError, Move};
+
+/// How a recorded game finished, as written at the end of the moves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordResult {
+    WhiteWins,
+    BlackWins,
+    /// White stalemated Black, and takes ¾ of the point
+    WhiteStalemates,
+    /// Black stalemated White, and takes ¾ of the point
+    BlackStalemates,
+    Draw,
+    /// The game is unfinished, or its result is unknown
+    Ongoing,
+}
+
+impl RecordResult {
+    /// The record's result after `last_mover` made the move that ended the game.
+    pub fn from_game_result(result: GameResult, last_mover: Color) -> RecordResult {
+        match (result, last_mover) {
+            (GameResult::Checkmate, Color::White) => RecordResult::WhiteWins,
+            (GameResult::Checkmate, Color::Black) => RecordResult::BlackWins,
+            (GameResult::Stalemate, Color::White) => RecordResult::WhiteStalemates,
+            (GameResult::Stalemate, Color::Black) => RecordResult::BlackStalemates,
+            _ => RecordResult::Draw,
+        }
+    }
+
+    /// White's share of the point, scored like [`GameResult::last_mover_points`].
+    /// None if the game hasn't finished.
+    pub fn points_for_white(&self) -> Option<f32> {
+        match self {
+            RecordResult::WhiteWins => Some(1.0),
+            RecordResult::BlackWins => Some(0.0),
+            RecordResult::WhiteStalemates => Some(0.75),
+            RecordResult::BlackStalemates => Some(0.25),
+            RecordResult::Draw => Some(0.5),
+            RecordResult::Ongoing => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            RecordResult::WhiteWins => "1-0",
+            RecordResult::BlackWins => "0-1",
+            RecordResult::WhiteStalemates => "3/4-1/4",
+            RecordResult::BlackStalemates => "1/4-3/4",
+            RecordResult::Draw => "1/2-1/2",
+            RecordResult::Ongoing => "*",
+        }
+    }
+
+    fn from_token(token: &str) -> Option<RecordResult> {
+        match token {
+            "1-0" => Some(RecordResult::WhiteWins),
+            "0-1" => Some(RecordResult::BlackWins),
+            "3/4-1/4" => Some(RecordResult::WhiteStalemates),
+            "1/4-3/4" => Some(RecordResult::BlackStalemates),
+            "1/2-1/2" => Some(RecordResult::Draw),
+            "*" => Some(RecordResult::Ongoing),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RecordResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A move in a game record, with anything noted alongside it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedMove {
+    pub movement: Move,
+    pub comment: Option<String>,
+    /// How much time the player who made the move had left afterwards
+    pub clock: Option<Duration>,
+}
+
+/// A game from its starting position, with every move played since.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameRecord {
+    /// Metadata like the players and the date, as `(name, value)` pairs
+    /// in the order they'll be written. The result and starting position
+    /// are kept in their own fields rather than as tags.
+    pub tags: Vec<(String, String)>,
+    pub starting_position: Board,
+    pub moves: Vec<RecordedMove>,
+    pub result: RecordResult,
+}
+
+/// Why a game record couldn't be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GameRecordError {
+    /// A tag line wasn't of the form `[Name "value"]`
+    InvalidTag(String),
+    /// The starting position in the `FEN` tag couldn't be read
+    InvalidPosition(NotationError),
+    /// The `Variant` tag isn't one of the variants in [`VariantKind`]
+    InvalidVariant(String),
+    /// The move `color` made on this move number couldn't be read or isn't legal
+    InvalidMove { move_number: u32, color: Color, error: SanError },
+    /// The game had already ended by one of the draw rules, or checkmate,
+    /// before this move
+    MoveAfterGameEnd { move_number: u32, color: Color, result: GameResult },
+    /// A `[%clk]` time wasn't of the form `h:mm:ss`
+    InvalidClock(String),
+    /// A `{` comment was never closed
+    UnterminatedComment,
+    /// There was more after the result at the end of the moves
+    TextAfterResult(String),
+}
+
+impl fmt::Display for GameRecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GameRecordError::InvalidTag(line) => write!(f, "'{line}' is not a valid tag"),
+            GameRecordError::InvalidPosition(error) => {
+                write!(f, "invalid starting position: {error}")
+            }
+            GameRecordError::InvalidVariant(variant) => write!(f, "unknown variant '{variant}'"),
+            GameRecordError::InvalidMove { move_number, color, error } => {
+                write!(f, "move {move_number} for {}: {error}", color_name(*color))
+            }
+            GameRecordError::MoveAfterGameEnd { move_number, color, result } => {
+                write!(f, "move {move_number} for {}: the game was already over ({result:?})", color_name(*color))
+            }
+            GameRecordError::InvalidClock(clock) => {
+                write!(f, "'{clock}' is not a clock time like 0:05:00")
+            }
+            GameRecordError::UnterminatedComment => write!(f, "a comment is missing its closing '}}'"),
+            GameRecordError::TextAfterResult(text) => {
+                write!(f, "unexpected '{text}' after the game's result")
+            }
+        }
+    }
+}
+
+impl Error for GameRecordError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GameRecordError::InvalidPosition(error) => Some(error),
+            GameRecordError::InvalidMove { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+fn color_name(color: Color) -> &'static str {
+    match color {
+        Color::White => "white",
+        Color::Black => "black",
+    }
+}
+
+fn format_clock(clock: Duration) -> String {
+    let seconds = clock.as_secs();
+    let mut formatted = format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if clock.subsec_millis() > 0 {
+        formatted.push_str(&format!(".{:03}", clock.subsec_millis()));
+    }
+    formatted
+}
+
+fn parse_clock(clock: &str) -> Result<Duration, GameRecordError> {
+    let invalid = || GameRecordError::InvalidClock(clock.to_string());
+    let parts: Vec<&str> = clock.split(':').collect();
+    let [hours, minutes, seconds] = parts[..] else {
+        return Err(invalid());
+    };
+    let hours: u64 = hours.parse().map_err(|_| invalid())?;
+    let minutes: u64 = minutes.parse().map_err(|_| invalid())?;
+    let seconds: f64 = seconds.parse().map_err(|_| invalid())?;
+    if minutes >= 60 || !(0.0..60.0).contains(&seconds) {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(hours * 3600 + minutes * 60) + Duration::from_secs_f64(seconds))
+}
+
+/// Split a comment's text into its `[%clk]` time, if it has one, and the
+/// rest of the comment.
+fn parse_comment(text: &str) -> Result<(Option<String>, Option<Duration>), GameRecordError> {
+    let mut clock = None;
+    let mut comment = text.to_string();
+    if let Some(start) = text.find("[%clk") {
+        let end = text[start..]
+            .find(']')
+            .map(|end| start + end)
+            .ok_or_else(|| GameRecordError::InvalidClock(text[start..].to_string()))?;
+        clock = Some(parse_clock(text[start + "[%clk".len()..end].trim())?);
+        comment = format!("{}{}", &text[..start], &text[end + 1..]);
+    }
+    let comment = comment.trim();
+    Ok(((!comment.is_empty()).then(|| comment.to_string()), clock))
+}
+
+fn parse_tag(line: &str) -> Result<(String, String), GameRecordError> {
+    let invalid = || GameRecordError::InvalidTag(line.to_string());
+    let inner = line
+        .strip_prefix('[')
+        .and_then(|line| line.strip_suffix(']'))
+        .ok_or_else(invalid)?;
+    let (name, value) = inner.split_once(char::is_whitespace).ok_or_else(invalid)?;
+    let value = value
+        .trim()
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .ok_or_else(invalid)?;
+    Ok((name.to_string(), value.replace("\\\"", "\"").replace("\\\\", "\\")))
+}
+
+impl GameRecord {
+    /// An empty record of a game starting from `starting_position`.
+    pub fn new(starting_position: Board) -> GameRecord {
+        GameRecord {
+            tags: Vec::new(),
+            starting_position,
+            moves: Vec::new(),
+            result: RecordResult::Ongoing,
+        }
+    }
+
+    /// The value of the tag called `name`, if there is one.
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Set the tag called `name`, replacing any existing value.
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some((_, existing)) => *existing = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// Add a move to the end of the record. The move isn't checked here -
+    /// it should already have been accepted by [`Board::play`].
+    pub fn push_move(&mut self, movement: Move) {
+        self.moves.push(RecordedMove {
+            movement,
+            comment: None,
+            clock: None,
+        });
+    }
+
+    /// Play every move from the starting position, checking each one is
+    /// legal, and return the final position.
+    pub fn replay(&self) -> Result<Board, HexChessError> {
+        let mut board = self.starting_position.clone();
+        for recorded in &self.moves {
+            board.play(recorded.movement)?;
+        }
+        Ok(board)
+    }
+
+    /// Write the game out in the PGN-style format described in
+    /// [`crate::game_record`]. The moves must all be legal, but they're only
+    /// played out to name them, so a game that went on past its end is
+    /// still written out.
+    pub fn to_pgn(&self) -> String {
+        let mut pgn = String::new();
+        let escape = |value: &str| value.replace('\\', "\\\\").replace('"', "\\\"");
+        for (name, value) in &self.tags {
+            pgn.push_str(&format!("[{name} \"{}\"]\n", escape(value)));
+        }
+        pgn.push_str(&format!("[Result \"{}\"]\n", self.result.as_str()));
+        let variant = self.starting_position.variant;
+        if variant != VariantKind::Glinski {
+            pgn.push_str(&format!("[Variant \"{variant}\"]\n"));
+        }
+        if self.starting_position != Board::setup_variant(variant) {
+            pgn.push_str("[SetUp \"1\"]\n");
+            pgn.push_str(&format!("[FEN \"{}\"]\n", self.starting_position.to_notation()));
+        }
+        pgn.push('\n');
+
+        let mut tokens = Vec::<String>::new();
+        let mut board = self.starting_position.clone();
+        // black's move needs its own number if it opens the game or follows a comment
+        let mut needs_number = true;
+        for recorded in &self.moves {
+            match board.current_player {
+                Color::White => tokens.push(format!("{}.", board.fullmove_number)),
+                Color::Black if needs_number => tokens.push(format!("{}...", board.fullmove_number)),
+                Color::Black => {}
+            }
+            tokens.push(to_san(&board, recorded.movement));
+            apply_move(&mut board, recorded.movement);
+
+            let mut comment = Vec::<String>::new();
+            if let Some(clock) = recorded.clock {
+                comment.push(format!("[%clk {}]", format_clock(clock)));
+            }
+            if let Some(text) = &recorded.comment {
+                // a closing brace would end the comment early
+                comment.push(text.replace('}', ")"));
+            }
+            needs_number = !comment.is_empty();
+            if needs_number {
+                tokens.push(format!("{{{}}}", comment.join(" ")));
+            }
+        }
+        tokens.push(self.result.as_str().to_string());
+
+        // wrap the moves at 80 characters, like PGN
+        let mut line_length = 0;
+        for token in tokens {
+            if line_length > 0 && line_length + 1 + token.len() > 80 {
+                pgn.push('\n');
+                line_length = 0;
+            } else if line_length > 0 {
+                pgn.push(' ');
+                line_length += 1;
+            }
+            line_length += token.len();
+            pgn.push_str(&token);
+        }
+        pgn.push('\n');
+        pgn
+    }
+
+    /// Read a game written in the PGN-style format described in
+    /// [`crate::game_record`]. Every move is checked for legality.
+    pub fn from_pgn(pgn: &str) -> Result<GameRecord, GameRecordError> {
+        let mut record = GameRecord::new(Board::setup_default_board());
+        let mut variant: Option<VariantKind> = None;
+        let mut fen = None;
+
+        // the tags come first, one per line
+        let mut movetext = String::new();
+        let mut tag_result = None;
+        let mut lines = pgn.lines();
+        for line in lines.by_ref() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if !line.starts_with('[') {
+                movetext.push_str(line);
+                movetext.push('\n');
+                break;
+            }
+            let (name, value) = parse_tag(line)?;
+            match name.as_str() {
+                "Result" => tag_result = RecordResult::from_token(&value),
+                "SetUp" => {}
+                "FEN" => fen = Some(Board::from_notation(&value).map_err(GameRecordError::InvalidPosition)?),
+                "Variant" => {
+                    variant = Some(value.parse().map_err(|_| GameRecordError::InvalidVariant(value))?)
+                }
+                _ => record.tags.push((name, value)),
+            }
+        }
+        for line in lines {
+            movetext.push_str(line);
+            movetext.push('\n');
+        }
+        record.starting_position = match fen {
+            Some(mut board) => {
+                // the notation only names variants other than Gliński's, and
+                // records written before it did give them in the tag alone
+                if let Some(variant) = variant {
+                    board.variant = variant;
+                }
+                board
+                    .validate()
+                    .map_err(|problems| GameRecordError::InvalidPosition(NotationError::InvalidPosition(problems)))?;
+                board
+            }
+            None => Board::setup_variant(variant.unwrap_or_default()),
+        };
+
+        let mut board = record.starting_position.clone();
+        let mut result = None;
+        let mut chars = movetext.chars().peekable();
+        while let Some(&next) = chars.peek() {
+            if next.is_whitespace() {
+                chars.next();
+                continue;
+            }
+            if next == '{' {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => text.push(c),
+                        None => return Err(GameRecordError::UnterminatedComment),
+                    }
+                }
+                let (comment, clock) = parse_comment(&text)?;
+                // a comment before the first move has nowhere to go
+                if let Some(last) = record.moves.last_mut() {
+                    last.comment = comment;
+                    last.clock = clock;
+                }
+                continue;
+            }
+
+            let mut token = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == '{' {
+                    break;
+                }
+                token.push(c);
+                chars.next();
+            }
+            if result.is_some() {
+                return Err(GameRecordError::TextAfterResult(token));
+            }
+            if let Some(token_result) = RecordResult::from_token(&token) {
+                result = Some(token_result);
+                continue;
+            }
+
+            // move numbers can be separate, "1. e6", or joined on, "1.e6"
+            let san = token.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+            if san.is_empty() {
+                continue;
+            }
+            let (move_number, color) = (board.fullmove_number, board.current_player);
+            let movement = parse_san(&board, san).map_err(|error| GameRecordError::InvalidMove {
+                move_number,
+                color,
+                error,
+            })?;
+            // parse_san only checks the move, not whether the game is over
+            board.play(movement).map_err(|error| match error {
+                HexChessError::GameOver(result) => GameRecordError::MoveAfterGameEnd {
+                    move_number,
+                    color,
+                    result,
+                },
+                _ => GameRecordError::InvalidMove {
+                    move_number,
+                    color,
+                    error: SanError::IllegalMove(san.to_string()),
+                },
+            })?;
+            record.push_move(movement);
+        }
+
+        record.result = result.or(tag_result).unwrap_or(RecordResult::Ongoing);
+        Ok(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{apply_move, Hexagon, Piece, PieceType};
+
+    const SCHOLARS_GAME: &str = r#"[Event "Casual game"]
+[White "alice"]
+[Black "bumblebot"]
+[Result "1-0"]
+
+1. e6 {[%clk 0:04:58] bold} 1... Nc6 2. Qe2 {[%clk 0:04:41]} 2... Ni6 1-0
+"#;
+
+    #[test]
+    fn test_read_game_record() {
+        let record = GameRecord::from_pgn(SCHOLARS_GAME).unwrap();
+        assert_eq!(record.tag("White"), Some("alice"));
+        assert_eq!(record.tag("Black"), Some("bumblebot"));
+        assert_eq!(record.tag("Result"), None);
+        assert_eq!(record.result, RecordResult::WhiteWins);
+        assert_eq!(record.starting_position, Board::setup_default_board());
+
+        assert_eq!(record.moves.len(), 4);
+        assert_eq!(
+            record.moves[0].movement,
+            Move {
+                start_hex: Hexagon::new("E4").unwrap(),
+                final_hex: Hexagon::new("E6").unwrap(),
+                final_piece: PieceType::Pawn,
+            }
+        );
+        assert_eq!(record.moves[0].comment.as_deref(), Some("bold"));
+        assert_eq!(record.moves[0].clock, Some(Duration::from_secs(298)));
+        assert_eq!(record.moves[1].comment, None);
+        assert_eq!(record.moves[2].comment, None);
+        assert_eq!(record.moves[2].clock, Some(Duration::from_secs(281)));
+
+        let board = record.replay().unwrap();
+        assert_eq!(board.fullmove_number, 3);
+        assert_eq!(board.current_player, Color::White);
+    }
+
+    #[test]
+    fn test_game_record_round_trips() {
+        let record = GameRecord::from_pgn(SCHOLARS_GAME).unwrap();
+        let pgn = record.to_pgn();
+        assert_eq!(pgn, SCHOLARS_GAME);
+        assert_eq!(GameRecord::from_pgn(&pgn).unwrap(), record);
+    }
+
+    #[test]
+    fn test_stalemates_are_recorded() {
+        let mut board = Board::new();
+        for (hex, piece_type, color) in [
+            ("A4", PieceType::King, Color::White),
+            ("C4", PieceType::King, Color::Black),
+            ("C8", PieceType::Rook, Color::Black),
+        ] {
+            board.occupied_squares.insert(Hexagon::new(hex).unwrap(), Piece { piece_type, color });
+        }
+        board.current_player = Color::Black;
+        board.refresh_hash();
+
+        let mut record = GameRecord::new(board.clone());
+        let movement = board.parse_san("Rc7").unwrap();
+        let outcome = board.play(movement).unwrap();
+        record.push_move(movement);
+        record.result = RecordResult::from_game_result(outcome.result.unwrap(), Color::Black);
+        assert_eq!(record.result, RecordResult::BlackStalemates);
+        assert_eq!(record.result.points_for_white(), Some(0.25));
+
+        let pgn = record.to_pgn();
+        assert!(pgn.ends_with("1... Rc7 1/4-3/4\n"), "{pgn}");
+        assert_eq!(GameRecord::from_pgn(&pgn).unwrap(), record);
+    }
+
+    #[test]
+    fn test_game_record_from_a_set_up_position() {
+        let mut starting_position = Board::setup_default_board();
+        let opening = starting_position.parse_san("e6").unwrap();
+        apply_move(&mut starting_position, opening);
+
+        let mut record = GameRecord::new(starting_position.clone());
+        record.set_tag("White", "say \"hi\"");
+        let mut board = starting_position;
+        for san in ["Nc6", "Qe2"] {
+            let movement = board.parse_san(san).unwrap();
+            board.play(movement).unwrap();
+            record.push_move(movement);
+        }
+
+        let pgn = record.to_pgn();
+        assert!(pgn.contains("[White \"say \\\"hi\\\"\"]"), "{pgn}");
+        assert!(pgn.contains("[FEN \""), "{pgn}");
+        assert!(pgn.contains("1... Nc6 2. Qe2 *"), "{pgn}");
+        let read_back = GameRecord::from_pgn(&pgn).unwrap();
+        assert_eq!(read_back, record);
+        assert_eq!(read_back.replay().unwrap(), board);
+    }
+
+    #[test]
+    fn test_game_record_of_another_variant() {
+        let mut board = Board::setup_variant(VariantKind::McCooey);
+        let mut record = GameRecord::new(board.clone());
+        for san in ["d4", "d7"] {
+            let movement = board.parse_san(san).unwrap();
+            board.play(movement).unwrap();
+            record.push_move(movement);
+        }
+
+        let pgn = record.to_pgn();
+        assert!(pgn.contains("[Variant \"McCooey\"]"), "{pgn}");
+        assert!(!pgn.contains("[FEN"), "{pgn}");
+        let read_back = GameRecord::from_pgn(&pgn).unwrap();
+        assert_eq!(read_back, record);
+        assert_eq!(read_back.replay().unwrap(), board);
+
+        // a position in the notation names its variant, so the tag isn't needed
+        let mut board = Board::from_notation(&board.to_notation()).unwrap();
+        let mut record = GameRecord::new(board.clone());
+        let movement = board.parse_san("e4").unwrap();
+        board.play(movement).unwrap();
+        record.push_move(movement);
+        let pgn = record.to_pgn().replace("[Variant \"McCooey\"]\n", "");
+        assert!(pgn.contains("[FEN"), "{pgn}");
+        let read_back = GameRecord::from_pgn(&pgn).unwrap();
+        assert_eq!(read_back.starting_position.variant, VariantKind::McCooey);
+        assert_eq!(read_back, record);
+        assert_eq!(read_back.replay().unwrap(), board);
+
+        assert_eq!(
+            GameRecord::from_pgn("[Variant \"Chess960\"]\n\n*"),
+            Err(GameRecordError::InvalidVariant("Chess960".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_game_record_errors() {
+        assert_eq!(
+            GameRecord::from_pgn("[White alice]\n\n1. e6"),
+            Err(GameRecordError::InvalidTag("[White alice]".to_string()))
+        );
+        assert_eq!(
+            GameRecord::from_pgn("1. e6 f8"),
+            Err(GameRecordError::InvalidMove {
+                move_number: 1,
+                color: Color::Black,
+                error: SanError::IllegalMove("f8".to_string())
+            })
+        );
+        // black moves first here, so its second move is the game's second
+        let black_to_move = Board::setup_default_board().to_notation().replace(" w ", " b ");
+        assert_eq!(
+            GameRecord::from_pgn(&format!("[FEN \"{black_to_move}\"]\n\n1... f6 2. e5 f8")),
+            Err(GameRecordError::InvalidMove {
+                move_number: 2,
+                color: Color::Black,
+                error: SanError::IllegalMove("f8".to_string())
+            })
+        );
+        // the lone kings can't play on, but reading that shouldn't panic
+        assert_eq!(
+            GameRecord::from_pgn("[FEN \"6/7/8/9/10/K9k/10/9/8/7/6 w - 0 1\"]\n\n1. Kf2 *"),
+            Err(GameRecordError::MoveAfterGameEnd {
+                move_number: 1,
+                color: Color::White,
+                result: GameResult::InsufficientMaterial
+            })
+        );
+        assert_eq!(
+            GameRecord::from_pgn("1. e6 {[%clk 5 minutes]} Nc6"),
+            Err(GameRecordError::InvalidClock("5 minutes".to_string()))
+        );
+        assert_eq!(
+            GameRecord::from_pgn("1. e6 {a comment"),
+            Err(GameRecordError::UnterminatedComment)
+        );
+        assert_eq!(
+            GameRecord::from_pgn("1. e6 1-0 Nc6"),
+            Err(GameRecordError::TextAfterResult("Nc6".to_string()))
+        );
+        assert!(matches!(
+            GameRecord::from_pgn("[FEN \"6/P5p w - 0 1\"]\n\n*"),
+            Err(GameRecordError::InvalidPosition(_))
+        ));
+        assert_eq!(
+            GameRecordError::InvalidMove {
+                move_number: 1,
+                color: Color::Black,
+                error: SanError::IllegalMove("f8".to_string())
+            }
+            .to_string(),
+            "move 1 for black: 'f8' is not a legal move"
+        );
+    }
+}
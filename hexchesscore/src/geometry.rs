@@ -0,0 +1,335 @@
+//! The shape of Gliński's 91-hexagon board, worked out once and kept in
+//! lookup tables.
+//!
+//! Each hexagon has an index from 0 to 90, counting up the files of rank
+//! `a`, then `b`, and so on. The same index is a hexagon's bit position in a
+//! [`BitBoard`](crate::board_representations::BitBoard).
+//!
+//! Hexagons also have axial coordinates `(q, r)`, where `q` is the rank and
+//! `r` goes up one for every step up a file, and a third coordinate
+//! `s = 5 + q - r`. All three run from 0 to 10 across the board.
+//!
+//! There are twelve directions to move in: the six orthogonal ones, in order
+//! around the hexagon, then the six diagonal ones. Diagonal direction
+//! `6 + i` runs between orthogonal directions `i` and `i + 1`.
+
+use std::sync::OnceLock;
+
+use crate::{Color, Hexagon};
+
+pub const NUMBER_OF_HEXES: usize = 91;
+
+const RANK_LENGTH: [u8; 11] = [6, 7, 8, 9, 10, 11, 10, 9, 8, 7, 6];
+const CUMULATIVE_HEXES: [u8; 11] = [0, 6, 13, 21, 30, 40, 51, 61, 70, 78, 85];
+
+/// The six orthogonal directions as axial (q, r) steps, in order around the hexagon
+pub const ORTHOGONAL_DIRECTIONS: [(i8, i8); 6] = [(0, 1), (1, 1), (1, 0), (0, -1), (-1, -1), (-1, 0)];
+/// The six diagonal directions. Each one runs between a neighbouring pair of
+/// orthogonal directions.
+pub const DIAGONAL_DIRECTIONS: [(i8, i8); 6] = [(1, 2), (2, 1), (1, -1), (-1, -2), (-2, -1), (-1, 1)];
+/// Every direction, orthogonal ones first
+pub const DIRECTIONS: [(i8, i8); 12] = [
+    ORTHOGONAL_DIRECTIONS[0],
+    ORTHOGONAL_DIRECTIONS[1],
+    ORTHOGONAL_DIRECTIONS[2],
+    ORTHOGONAL_DIRECTIONS[3],
+    ORTHOGONAL_DIRECTIONS[4],
+    ORTHOGONAL_DIRECTIONS[5],
+    DIAGONAL_DIRECTIONS[0],
+    DIAGONAL_DIRECTIONS[1],
+    DIAGONAL_DIRECTIONS[2],
+    DIAGONAL_DIRECTIONS[3],
+    DIAGONAL_DIRECTIONS[4],
+    DIAGONAL_DIRECTIONS[5],
+];
+
+/// The axial steps a pawn captures along, indexed by [color]
+pub const PAWN_CAPTURE_DIRECTIONS: [[(i8, i8); 2]; 2] = [[(-1, 0), (1, 1)], [(-1, -1), (1, 0)]];
+
+struct Tables {
+    hexagons: [Hexagon; NUMBER_OF_HEXES],
+    axial: [(u8, u8); NUMBER_OF_HEXES],
+    by_axial: [[Option<u8>; 11]; 11],
+    neighbours: [[Option<Hexagon>; 12]; NUMBER_OF_HEXES],
+    /// Indexed by [position][direction], closest hexagon first
+    rays: Vec<[Vec<Hexagon>; 12]>,
+    king_moves: Vec<Vec<Hexagon>>,
+    knight_jumps: Vec<Vec<Hexagon>>,
+    /// Indexed by [color][position]
+    pawn_pushes: [[Option<Hexagon>; NUMBER_OF_HEXES]; 2],
+    pawn_captures: [Vec<Vec<Hexagon>>; 2],
+}
+
+pub(crate) fn color_index(color: Color) -> usize {
+    match color {
+        Color::White => 0,
+        Color::Black => 1,
+    }
+}
+
+fn build_tables() -> Tables {
+    let mut tables = Tables {
+        hexagons: [Hexagon { rank: 0, file: 0 }; NUMBER_OF_HEXES],
+        axial: [(0, 0); NUMBER_OF_HEXES],
+        by_axial: [[None; 11]; 11],
+        neighbours: [[None; 12]; NUMBER_OF_HEXES],
+        rays: Vec::with_capacity(NUMBER_OF_HEXES),
+        king_moves: Vec::with_capacity(NUMBER_OF_HEXES),
+        knight_jumps: Vec::with_capacity(NUMBER_OF_HEXES),
+        pawn_pushes: [[None; NUMBER_OF_HEXES]; 2],
+        pawn_captures: [Vec::with_capacity(NUMBER_OF_HEXES), Vec::with_capacity(NUMBER_OF_HEXES)],
+    };
+
+    for rank in 0..11u8 {
+        for file in 0..RANK_LENGTH[rank as usize] {
+            let hex = Hexagon { rank, file };
+            let index = index(&hex);
+            // files start further up the board past the middle rank
+            let r = file + rank.saturating_sub(5);
+            tables.hexagons[index] = hex;
+            tables.axial[index] = (rank, r);
+            tables.by_axial[rank as usize][r as usize] = Some(index as u8);
+        }
+    }
+
+    let step = |tables: &Tables, index: usize, (dq, dr): (i8, i8)| {
+        let (q, r) = tables.axial[index];
+        let q = usize::try_from(q as i8 + dq).ok()?;
+        let r = usize::try_from(r as i8 + dr).ok()?;
+        let target = (*tables.by_axial.get(q)?.get(r)?)?;
+        Some(tables.hexagons[target as usize])
+    };
+
+    // a knight moves two steps in one orthogonal direction, then one step
+    // in either of the orthogonal directions next to it
+    let mut knight_offsets = Vec::<(i8, i8)>::new();
+    for (i, (dq, dr)) in ORTHOGONAL_DIRECTIONS.iter().enumerate() {
+        for neighbour in [i + 1, i + 5] {
+            let (nq, nr) = ORTHOGONAL_DIRECTIONS[neighbour % 6];
+            knight_offsets.push((2 * dq + nq, 2 * dr + nr));
+        }
+    }
+
+    for index in 0..NUMBER_OF_HEXES {
+        for (dir, offset) in DIRECTIONS.iter().enumerate() {
+            tables.neighbours[index][dir] = step(&tables, index, *offset);
+        }
+    }
+
+    for index in 0..NUMBER_OF_HEXES {
+        let rays = std::array::from_fn(|dir| {
+            let mut ray = Vec::new();
+            let mut next = tables.neighbours[index][dir];
+            while let Some(target) = next {
+                ray.push(target);
+                next = tables.neighbours[self::index(&target)][dir];
+            }
+            ray
+        });
+        tables.rays.push(rays);
+        tables.king_moves.push(tables.neighbours[index].iter().flatten().copied().collect());
+        tables.knight_jumps.push(
+            knight_offsets
+                .iter()
+                .filter_map(|offset| step(&tables, index, *offset))
+                .collect(),
+        );
+        for color in [Color::White, Color::Black] {
+            let c = color_index(color);
+            tables.pawn_pushes[c][index] = step(&tables, index, pawn_forward(color));
+            tables.pawn_captures[c].push(
+                PAWN_CAPTURE_DIRECTIONS[c]
+                    .iter()
+                    .filter_map(|offset| step(&tables, index, *offset))
+                    .collect(),
+            );
+        }
+    }
+    tables
+}
+
+fn tables() -> &'static Tables {
+    static TABLES: OnceLock<Tables> = OnceLock::new();
+    TABLES.get_or_init(build_tables)
+}
+
+/// Whether the hexagon is one of the 91 on the board. [`Hexagon::new`]
+/// reads names like `a10` too, which are past the end of their rank.
+pub fn is_on_board(hex: &Hexagon) -> bool {
+    RANK_LENGTH.get(hex.rank as usize).is_some_and(|length| hex.file < *length)
+}
+
+/// A hexagon's position in the tables, from 0 to 90. The hexagon has to be
+/// on the board.
+pub fn index(hex: &Hexagon) -> usize {
+    CUMULATIVE_HEXES[hex.rank as usize] as usize + hex.file as usize
+}
+
+/// The hexagon at a position in the tables
+pub fn hexagon(index: usize) -> Hexagon {
+    tables().hexagons[index]
+}
+
+/// Every hexagon on the board, in index order
+pub fn hexagons() -> &'static [Hexagon; NUMBER_OF_HEXES] {
+    &tables().hexagons
+}
+
+pub fn to_axial(hex: &Hexagon) -> (u8, u8) {
+    tables().axial[index(hex)]
+}
+
+/// The hexagon at the axial coordinates, if they're on the board
+pub fn from_axial(q: u8, r: u8) -> Option<Hexagon> {
+    let target = (*tables().by_axial.get(q as usize)?.get(r as usize)?)?;
+    Some(hexagon(target as usize))
+}
+
+pub fn to_cube(hex: &Hexagon) -> (u8, u8, u8) {
+    let (q, r) = to_axial(hex);
+    (q, r, 5 + q - r)
+}
+
+/// The hexagon at the cube coordinates, if they're on the board and agree
+/// with each other
+pub fn from_cube(q: u8, r: u8, s: u8) -> Option<Hexagon> {
+    if 5 + q != r + s {
+        return None;
+    }
+    from_axial(q, r)
+}
+
+/// Step from a hexagon by an axial offset, if that stays on the board
+pub fn offset(hex: &Hexagon, (dq, dr): (i8, i8)) -> Option<Hexagon> {
+    let (q, r) = to_axial(hex);
+    let q = u8::try_from(q as i8 + dq).ok()?;
+    let r = u8::try_from(r as i8 + dr).ok()?;
+    from_axial(q, r)
+}
+
+/// The next hexagon along one of the twelve [`DIRECTIONS`]
+pub fn neighbour(hex: &Hexagon, direction: usize) -> Option<Hexagon> {
+    tables().neighbours[index(hex)][direction]
+}
+
+/// Every hexagon along one of the twelve [`DIRECTIONS`], closest first
+pub fn ray(hex: &Hexagon, direction: usize) -> &'static [Hexagon] {
+    &tables().rays[index(hex)][direction]
+}
+
+/// The hexagons a king on `hex` can step to
+pub fn king_moves(hex: &Hexagon) -> &'static [Hexagon] {
+    &tables().king_moves[index(hex)]
+}
+
+/// The hexagons a knight on `hex` can jump to
+pub fn knight_jumps(hex: &Hexagon) -> &'static [Hexagon] {
+    &tables().knight_jumps[index(hex)]
+}
+
+/// The axial step a pawn takes when it moves forward
+pub fn pawn_forward(color: Color) -> (i8, i8) {
+    match color {
+        Color::White => (0, 1),
+        Color::Black => (0, -1),
+    }
+}
+
+/// The hexagon in front of a pawn, if it isn't at the end of its file
+pub fn pawn_push(hex: &Hexagon, color: Color) -> Option<Hexagon> {
+    tables().pawn_pushes[color_index(color)][index(hex)]
+}
+
+/// The hexagons a pawn on `hex` captures on, with the usual capture
+/// directions (see [`crate::variants::Variant::pawn_captures`])
+pub fn pawn_captures(hex: &Hexagon, color: Color) -> &'static [Hexagon] {
+    &tables().pawn_captures[color_index(color)][index(hex)]
+}
+
+/// The number of orthogonal steps between two hexagons
+pub fn distance(from: &Hexagon, to: &Hexagon) -> u8 {
+    let (q1, r1, s1) = to_cube(from);
+    let (q2, r2, s2) = to_cube(to);
+    q1.abs_diff(q2).max(r1.abs_diff(r2)).max(s1.abs_diff(s2))
+}
+
+/// Which of the board's three colours a hexagon is, from 0 to 2. A diagonal
+/// step keeps to the same colour, so a bishop never leaves the colour it
+/// starts on.
+pub fn hex_color(hex: &Hexagon) -> usize {
+    let (q, r) = to_axial(hex);
+    (q as usize + r as usize) % 3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(name: &str) -> Hexagon {
+        Hexagon::new(name).unwrap()
+    }
+
+    #[test]
+    fn test_coordinates_round_trip() {
+        for (i, hex) in hexagons().iter().enumerate() {
+            assert_eq!(index(hex), i);
+            let (q, r) = to_axial(hex);
+            assert_eq!(from_axial(q, r), Some(*hex));
+            let (q, r, s) = to_cube(hex);
+            assert!(q <= 10 && r <= 10 && s <= 10);
+            assert_eq!(from_cube(q, r, s), Some(*hex));
+        }
+        assert_eq!(to_axial(&hex("L6")), (10, 10));
+        assert_eq!(from_axial(0, 6), None);
+        assert_eq!(from_cube(5, 5, 4), None);
+    }
+
+    #[test]
+    fn test_rays_and_jumps() {
+        // the middle of the board sees the edge in every direction
+        let center = hex("F6");
+        assert_eq!(king_moves(&center).len(), 12);
+        assert_eq!(knight_jumps(&center).len(), 12);
+        assert_eq!(ray(&center, 0), &[hex("F7"), hex("F8"), hex("F9"), hex("F10"), hex("F11")]);
+        assert_eq!(ray(&center, 3).len(), 5);
+        assert_eq!(ray(&center, 6), &[hex("G7"), hex("H8")]);
+
+        // a corner has three neighbours and two diagonals
+        let corner = hex("A1");
+        assert_eq!(neighbour(&corner, 3), None);
+        assert_eq!(king_moves(&corner).len(), 5);
+        assert_eq!(knight_jumps(&corner).len(), 4);
+        for (dir, step) in DIRECTIONS.iter().enumerate() {
+            assert_eq!(neighbour(&corner, dir), offset(&corner, *step));
+        }
+    }
+
+    #[test]
+    fn test_pawn_tables() {
+        assert_eq!(pawn_push(&hex("F5"), Color::White), Some(hex("F6")));
+        assert_eq!(pawn_push(&hex("F11"), Color::White), None);
+        assert_eq!(pawn_push(&hex("F7"), Color::Black), Some(hex("F6")));
+        assert_eq!(pawn_captures(&hex("F5"), Color::White), &[hex("E5"), hex("G5")]);
+        assert_eq!(pawn_captures(&hex("F7"), Color::Black), &[hex("E6"), hex("G6")]);
+    }
+
+    #[test]
+    fn test_distances_and_colors() {
+        assert_eq!(distance(&hex("F6"), &hex("F6")), 0);
+        assert_eq!(distance(&hex("F6"), &hex("A1")), 5);
+        assert_eq!(distance(&hex("A1"), &hex("L1")), 10);
+        for hex in hexagons() {
+            for direction in 6..12 {
+                assert!(ray(hex, direction).iter().all(|other| hex_color(other) == hex_color(hex)));
+            }
+            for other in king_moves(hex) {
+                if distance(hex, other) == 1 {
+                    assert_ne!(hex_color(other), hex_color(hex));
+                }
+            }
+        }
+        // each side starts with a bishop on every color
+        let colors: Vec<usize> = ["F1", "F2", "F3"].iter().map(|name| hex_color(&hex(name))).collect();
+        assert_eq!(colors, vec![2, 0, 1]);
+    }
+}
@@ -4,7 +4,11 @@ use std::collections::HashMap;
 use std::fmt;
 
 
+use crate::geometry;
 use crate::moves::{self, get_rank_length, KnightMoves, SlidingMoves};
+use crate::validation::PositionProblem;
+use crate::variants::VariantKind;
+use crate::zobrist;
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, PartialOrd, Ord)]
 pub enum PieceType {
@@ -101,6 +105,12 @@ impl fmt::Debug for Hexagon {
     }
 }
 
+impl fmt::Display for Hexagon {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", rank_int_to_char(self.rank).unwrap(), self.file + 1)
+    }
+}
+
 impl Serialize for Hexagon {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -141,33 +151,165 @@ impl<'de> Deserialize<'de> for Hexagon {
     }
 }
 
-const DEFAULT_BOARD: &str = include_str!("./starting_moves.json");
+fn first_move_number() -> u32 {
+    1
+}
+
+/// Position hashes go over the wire as strings, since JavaScript numbers
+/// can't hold all 64 bits of one.
+mod hash_strings {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(hashes: &[u64], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(hashes.iter().map(u64::to_string))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<u64>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|hash| hash.parse().map_err(de::Error::custom))
+            .collect()
+    }
+}
+
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(try_from = "BoardFields")]
 pub struct Board {
     pub occupied_squares: HashMap<Hexagon, Piece>,
     pub en_passant: Option<Hexagon>,
     pub current_player: Color,
+    /// Moves since the last capture or pawn move, used for the 50-move rule
+    pub halfmove_clock: u32,
+    /// Starts at 1 and goes up after each of black's moves
+    pub fullmove_number: u32,
+    /// The [`Board::repetition_key`] of every position reached before a
+    /// move was made with [`Board::play`], oldest first. Used to detect
+    /// repetitions.
+    #[serde(serialize_with = "hash_strings::serialize")]
+    pub position_history: Vec<u64>,
+    /// Which set of rules the game is played by
+    pub variant: VariantKind,
+    /// The position's Zobrist hash, kept up to date by [`apply_move`] and
+    /// [`revert_move`]. Anything that edits the board directly needs to
+    /// call [`Board::refresh_hash`] afterwards.
+    #[serde(skip_serializing)]
+    pub(crate) hash: u64,
+}
+
+/// Everything in a serialized [`Board`]. The hash isn't sent over the
+/// wire, so it's worked out again when a board is read back in - which
+/// can only be done for hexagons that are on the board.
+#[derive(Deserialize)]
+struct BoardFields {
+    occupied_squares: HashMap<Hexagon, Piece>,
+    en_passant: Option<Hexagon>,
+    current_player: Color,
+    #[serde(default)]
+    halfmove_clock: u32,
+    #[serde(default = "first_move_number")]
+    fullmove_number: u32,
+    #[serde(default, deserialize_with = "hash_strings::deserialize")]
+    position_history: Vec<u64>,
+    #[serde(default)]
+    variant: VariantKind,
+}
+
+impl TryFrom<BoardFields> for Board {
+    type Error = PositionProblem;
+
+    fn try_from(fields: BoardFields) -> Result<Board, PositionProblem> {
+        let mut hexagons = fields.occupied_squares.keys().chain(&fields.en_passant);
+        if let Some(hex) = hexagons.find(|hex| !geometry::is_on_board(hex)) {
+            return Err(PositionProblem::OffBoard(*hex));
+        }
+        let mut board = Board {
+            occupied_squares: fields.occupied_squares,
+            en_passant: fields.en_passant,
+            current_player: fields.current_player,
+            halfmove_clock: fields.halfmove_clock,
+            fullmove_number: fields.fullmove_number,
+            position_history: fields.position_history,
+            variant: fields.variant,
+            hash: 0,
+        };
+        board.refresh_hash();
+        Ok(board)
+    }
 }
 
-
 impl Board {
     pub fn setup_default_board() -> Board {
-        let moves: HashMap<Hexagon, Piece> =
-            serde_json::from_str(&DEFAULT_BOARD).expect("Invalid JSON format");
-        Board {
-            occupied_squares: moves,
-            en_passant: None,
-            current_player: Color::White,
-        }
+        Board::setup_variant(VariantKind::Glinski)
+    }
+    /// The starting position of the given variant
+    pub fn setup_variant(variant: VariantKind) -> Board {
+        let mut board = Board::new();
+        board.occupied_squares = variant.rules().starting_position().clone();
+        board.variant = variant;
+        board.refresh_hash();
+        board
     }
     pub fn new() -> Board {
         Board {
             occupied_squares: HashMap::<Hexagon, Piece>::new(),
             en_passant: None,
             current_player: Color::White,
+            halfmove_clock: 0,
+            fullmove_number: first_move_number(),
+            position_history: Vec::new(),
+            variant: VariantKind::default(),
+            hash: zobrist::side_to_move_key(Color::White),
         }
     }
+
+    /// The position's Zobrist hash. Positions with the same pieces on the
+    /// same hexagons, the same player to move and the same pawn open to
+    /// en passant have the same hash.
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// The hash positions are compared by when looking for repetitions. A
+    /// pawn that has just double jumped only makes the position different
+    /// if it can really be taken en passant, so otherwise it's left out.
+    pub fn repetition_key(&self) -> u64 {
+        if self.en_passant.is_some() && !self.has_en_passant_capture() {
+            self.hash ^ zobrist::en_passant_key(self.en_passant)
+        } else {
+            self.hash
+        }
+    }
+
+    /// Whether the player to move has a legal en-passant capture.
+    fn has_en_passant_capture(&self) -> bool {
+        self.legal_moves().iter().any(|movement| {
+            let is_pawn = self
+                .occupied_squares
+                .get(&movement.start_hex)
+                .is_some_and(|piece| piece.piece_type == PieceType::Pawn);
+            is_pawn
+                && movement.start_hex.rank != movement.final_hex.rank
+                && !self.occupied_squares.contains_key(&movement.final_hex)
+                && self.en_passant
+                    == Some(convert_en_passant_to_virtual_pawn(&movement.final_hex, self.current_player))
+        })
+    }
+
+    /// Work the hash out from scratch, after editing the board directly.
+    pub fn refresh_hash(&mut self) {
+        self.hash = zobrist::hash_position(
+            &self.occupied_squares,
+            self.current_player,
+            self.en_passant,
+        );
+    }
 }
 
 fn get_blocking_sliding_moves(
@@ -207,29 +349,22 @@ fn get_valid_knight_moves(moves: moves::KnightMoves, piece: &Piece, board: &Boar
     valid_moves
 }
 
-fn get_valid_moves_without_checks(
-    hexagon: &Hexagon,
-    piece: &Piece,
-    board: &Board,
-) -> (Vec<Hexagon>, Option<Hexagon>, Vec<Hexagon>) {
+fn get_valid_moves_without_checks(hexagon: &Hexagon, piece: &Piece, board: &Board) -> Vec<Hexagon> {
     // get valid pieces
     // check for friendly pieces blocking stuff
     // check for enemy pieces allowing captures
-    let (valid_moves, double_jump, promotion_moves) = match piece.piece_type {
-        PieceType::Rook | PieceType::Queen | PieceType::Bishop | PieceType::King => (
-            get_blocking_sliding_moves(SlidingMoves::new(&hexagon, &piece), piece, board),
-            None,
-            Vec::new(),
-        ),
+    let mut moves = match piece.piece_type {
+        PieceType::Rook | PieceType::Queen | PieceType::Bishop | PieceType::King => {
+            get_blocking_sliding_moves(SlidingMoves::new(hexagon, piece), piece, board)
+        }
         PieceType::Pawn => moves::pawn_moves(hexagon, &piece.color, board),
-        PieceType::Knight => (
-            get_valid_knight_moves(KnightMoves::new(hexagon), piece, board),
-            None,
-            Vec::new(),
-        ),
+        PieceType::Knight => get_valid_knight_moves(KnightMoves::new(hexagon), piece, board),
     };
-
-    (valid_moves, double_jump, promotion_moves)
+    // the variant's board is convex, so once a sliding piece's arm leaves
+    // it, the rest of the arm is off the board too
+    let rules = board.variant.rules();
+    moves.retain(|hex| rules.is_on_board(hex));
+    moves
 }
 
 pub fn get_all_pieces_of_matching_color(color: Color, board: &Board) -> Vec<(Hexagon, Piece)> {
@@ -242,312 +377,496 @@ pub fn get_all_pieces_of_matching_color(color: Color, board: &Board) -> Vec<(Hex
     vec
 }
 
-fn pieces_can_attack_king(
-    enemy_piece_hex: &Hexagon,
-    king_pos: &Hexagon,
-    enemy_piece: &Piece,
+/// Whether a piece slides along one of the twelve geometry directions -
+/// rooks along the orthogonal ones, bishops along the diagonal ones.
+fn slides_along(piece_type: PieceType, direction: usize) -> bool {
+    match piece_type {
+        PieceType::Queen => true,
+        PieceType::Rook => direction < 6,
+        PieceType::Bishop => direction >= 6,
+        _ => false,
+    }
+}
+
+/// The pieces of `color` attacking `hexagon`, on a board whose pieces are
+/// looked up with `piece_at`. Legality checks use this to see what would
+/// attack a hexagon after a move, without having to make it.
+fn attackers_of(
+    hexagon: &Hexagon,
+    color: Color,
     board: &Board,
-) -> bool {
-    let mut out = false;
-    for enemy_move in get_valid_moves_without_checks(enemy_piece_hex, enemy_piece, board).0 {
-        if &enemy_move == king_pos {
-            out = true;
-            break;
+    piece_at: &dyn Fn(&Hexagon) -> Option<Piece>,
+) -> Vec<Hexagon> {
+    let mut attackers = Vec::new();
+    // look out along each line for the first piece in the way
+    for direction in 0..12 {
+        for (distance, hex) in geometry::ray(hexagon, direction).iter().enumerate() {
+            if let Some(piece) = piece_at(hex) {
+                let attacks = slides_along(piece.piece_type, direction)
+                    || (piece.piece_type == PieceType::King && distance == 0);
+                if piece.color == color && attacks {
+                    attackers.push(*hex);
+                }
+                break;
+            }
+        }
+    }
+
+    let knight = Piece { piece_type: PieceType::Knight, color };
+    attackers.extend(geometry::knight_jumps(hexagon).iter().filter(|hex| piece_at(hex) == Some(knight)));
+
+    // a pawn attacks the hexagon if it could capture onto it
+    let pawn = Piece { piece_type: PieceType::Pawn, color };
+    for (dq, dr) in board.variant.rules().pawn_captures(color) {
+        if let Some(hex) = geometry::offset(hexagon, (-dq, -dr)) {
+            if piece_at(&hex) == Some(pawn) {
+                attackers.push(hex);
+            }
         }
     }
-    out
+    attackers
+}
+
+/// The hexagons of every piece of `color` that attacks `hexagon`.
+pub fn attacks_to(hexagon: &Hexagon, color: Color, board: &Board) -> Vec<Hexagon> {
+    attackers_of(hexagon, color, board, &|hex| board.occupied_squares.get(hex).copied())
+}
+
+/// Where the king of the given color is, if it's on the board.
+pub fn find_king(color: Color, board: &Board) -> Option<Hexagon> {
+    board
+        .occupied_squares
+        .iter()
+        .find(|(_, piece)| **piece == Piece { piece_type: PieceType::King, color })
+        .map(|(hex, _)| *hex)
+}
+
+/// The enemy pieces giving check to the king of the given color.
+pub fn checkers(color: Color, board: &Board) -> Vec<Hexagon> {
+    match find_king(color, board) {
+        Some(king) => attacks_to(&king, color.invert(), board),
+        None => Vec::new(),
+    }
+}
+
+/// The pieces of `color` that are pinned to their king, each with the
+/// direction from the king to the pinning piece. A pinned piece can only
+/// move along that line.
+pub fn pinned_pieces(color: Color, board: &Board) -> Vec<(Hexagon, usize)> {
+    let Some(king) = find_king(color, board) else {
+        return Vec::new();
+    };
+    let mut pins = Vec::new();
+    for direction in 0..12 {
+        let mut pieces = geometry::ray(&king, direction)
+            .iter()
+            .filter_map(|hex| board.occupied_squares.get(hex).map(|piece| (*hex, *piece)));
+        if let (Some((pinned, own)), Some((_, enemy))) = (pieces.next(), pieces.next()) {
+            if own.color == color && enemy.color != color && slides_along(enemy.piece_type, direction) {
+                pins.push((pinned, direction));
+            }
+        }
+    }
+    pins
 }
 
 pub fn get_attacking_pieces(
     enemy_color: Color,
-    board: &mut Board,
+    board: &Board,
     king_pos: &Hexagon,
 ) -> Option<Vec<(Hexagon, Piece)>> {
-    let mut potential_pieces = get_all_pieces_of_matching_color(enemy_color, board);
-
-    potential_pieces.retain(|(enemy_piece_hex, enemy_piece)| {
-        pieces_can_attack_king(enemy_piece_hex, king_pos, enemy_piece, board)
-    });
+    let attackers: Vec<(Hexagon, Piece)> = attacks_to(king_pos, enemy_color, board)
+        .into_iter()
+        .map(|hex| (hex, board.occupied_squares[&hex]))
+        .collect();
 
-    if !potential_pieces.is_empty() {
-        Some(potential_pieces)
+    if !attackers.is_empty() {
+        Some(attackers)
     } else {
         None
     }
 }
 
-pub fn check_moves_for_checks(
-    moves: &mut Vec<Hexagon>,
-    hexagon: &Hexagon,
-    piece: &Piece,
-    board: &mut Board,
-) {
-    let piece_is_king = matches!(piece.piece_type, PieceType::King);
-
-    let inverted_board: HashMap<Piece, Hexagon> = board
-        .occupied_squares
-        .iter()
-        .map(|(k, v)| (v.clone(), k.clone()))
-        .collect();
+/// What a player has to look out for to keep their king safe: the pieces
+/// giving check and the pieces pinned to the king. Worked out once per
+/// position and then used to check each move, without touching the board.
+struct KingSafety {
+    king: Option<Hexagon>,
+    checkers: Vec<Hexagon>,
+    pins: Vec<(Hexagon, usize)>,
+}
 
-    // remove the moving piece from the board
-    board.occupied_squares.remove(hexagon);
+impl KingSafety {
+    fn new(color: Color, board: &Board) -> KingSafety {
+        KingSafety {
+            king: find_king(color, board),
+            checkers: checkers(color, board),
+            pins: pinned_pieces(color, board),
+        }
+    }
 
-    if piece_is_king {
-        // if the piece is the king, see if there are any attacking pieces for each possible king move
-        moves.retain(|king_move| {
-            let king_is_safe;
+    /// Whether moving `piece` from `start` to `final_hex` keeps its king out of check.
+    fn allows(&self, start: &Hexagon, final_hex: &Hexagon, piece: &Piece, board: &Board) -> bool {
+        // a board without a king has nothing to protect
+        let Some(king) = self.king else {
+            return true;
+        };
+        let enemy = piece.color.invert();
+
+        if piece.piece_type == PieceType::King {
+            // the king can't hide from a sliding piece further along its line
+            let piece_at = |hex: &Hexagon| {
+                (hex != start).then(|| board.occupied_squares.get(hex).copied()).flatten()
+            };
+            return attackers_of(final_hex, enemy, board, &piece_at).is_empty();
+        }
 
-            // before we overwrite the board, check what is currently there
-            let existing_piece = board.occupied_squares.remove(king_move);
-            board.occupied_squares.insert(*king_move, *piece);
+        // en passant takes a pawn off a hexagon the move doesn't land on,
+        // which can open up a line that no pin would spot
+        if piece.piece_type == PieceType::Pawn
+            && start.rank != final_hex.rank
+            && !board.occupied_squares.contains_key(final_hex)
+        {
+            let taken = convert_en_passant_to_virtual_pawn(final_hex, piece.color);
+            let piece_at = |hex: &Hexagon| {
+                if hex == final_hex {
+                    Some(*piece)
+                } else if hex == start || *hex == taken {
+                    None
+                } else {
+                    board.occupied_squares.get(hex).copied()
+                }
+            };
+            return attackers_of(&king, enemy, board, &piece_at).is_empty();
+        }
 
-            match get_attacking_pieces(piece.color.invert(), board, king_move) {
-                Some(_) => king_is_safe = false,
-                None => king_is_safe = true,
-            }
+        // only the king can get out of a double check
+        if self.checkers.len() > 1 {
+            return false;
+        }
 
-            board.occupied_squares.remove(king_move);
-            if existing_piece.is_some() {
-                board
-                    .occupied_squares
-                    .insert(*king_move, existing_piece.unwrap());
+        if let Some((_, direction)) = self.pins.iter().find(|(pinned, _)| pinned == start) {
+            if !geometry::ray(&king, *direction).contains(final_hex) {
+                return false;
             }
-            king_is_safe
-        });
-    } else {
-        let king_pos = inverted_board
-            .get(&Piece {
-                piece_type: PieceType::King,
-                color: piece.color,
-            })
-            .expect("Couldn't find the king?!?");
-
-        // otherwise, first, see if any pieces can attack the king if the piece wasn't there.
-        // then, go through all the possible attackers
-        // We have to manually disable en-passant at this point
-        let en_passant = board.en_passant.clone();
-        board.en_passant = None;
-
-        if let Some(attackers) = get_attacking_pieces(piece.color.invert(), board, king_pos) {
-            // if they can, store all the attacking pieces
-            // that can reach the king
-
-            // for each move in the list,
-            // evaluate if any of these pieces can still attack the king
-            moves.retain(|hex| {
-                let mut king_is_safe = true;
-                // add the moving piece into its potential board position
-                let existing_piece = board.occupied_squares.remove(hex);
-                board.occupied_squares.insert(*hex, *piece);
-
-                // check for potential attacks
-                for (enemy_hex, enemy_piece) in &attackers {
-                    // if the piece can't take or block the attacker, the king isn't safe in this situation
-                    if (hex != enemy_hex)
-                        & pieces_can_attack_king(enemy_hex, king_pos, enemy_piece, board)
-                    {
-                        king_is_safe = false;
-                        break;
-                    }
-                }
-                // clean up by removing the piece inserted into the board
-                board.occupied_squares.remove(hex);
-                if existing_piece.is_some() {
-                    board.occupied_squares.insert(*hex, existing_piece.unwrap());
-                }
+        }
 
-                // if the king is safe, we can keep this move
-                king_is_safe
-            });
+        // a single check has to be answered by taking the checking piece or
+        // by stepping in between it and the king
+        if let Some(checker) = self.checkers.first() {
+            let blocks = (0..12)
+                .map(|direction| geometry::ray(&king, direction))
+                .find(|ray| ray.contains(checker))
+                .is_some_and(|ray| ray.iter().take_while(|hex| *hex != checker).any(|hex| hex == final_hex));
+            return final_hex == checker || blocks;
         }
-        board.en_passant = en_passant;
+        true
     }
-    // add the piece back to the board
-    board.occupied_squares.insert(*hexagon, *piece);
 }
 
-/// From a given starting hexagon, give all the valid
-/// moves that a piece (which might be located there) has.
-/// If a piece isn't located there, there will be no valid
-/// moves.
-pub fn get_valid_moves(
+/// Remove any moves that would leave the moving side's king in check. The
+/// checks and pins against the king are worked out once from the current
+/// position, so the board is never changed.
+pub fn check_moves_for_checks(
+    moves: &mut Vec<Hexagon>,
     hexagon: &Hexagon,
-    board: &mut Board,
-) -> (Vec<Hexagon>, Option<Hexagon>, Vec<Hexagon>) {
-    let mut valid_moves;
-    let double_jump;
-    let promotion_moves;
-    // check that the piece
-    if let Some(piece) = board.occupied_squares.get(&hexagon).cloned() {
-        (valid_moves, double_jump, promotion_moves) =
-            get_valid_moves_without_checks(hexagon, &piece, board);
-        // validate the king is not in check for any of the moves
-        // -> this in-place mutates the valid_moves vec
-        check_moves_for_checks(&mut valid_moves, hexagon, &piece, board);
-    } else {
-        (valid_moves, double_jump, promotion_moves) =
-            (Vec::<Hexagon>::new(), None, Vec::<Hexagon>::new());
-    }
-    (valid_moves, double_jump, promotion_moves)
+    piece: &Piece,
+    board: &Board,
+) {
+    let safety = KingSafety::new(piece.color, board);
+    moves.retain(|final_hex| safety.allows(hexagon, final_hex, piece, board));
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Move {
     pub start_hex: Hexagon,
     pub final_hex: Hexagon,
     pub final_piece: PieceType,
 }
 
-pub fn get_all_valid_moves(board: &mut Board) -> Vec<Move> {
+/// Every legal move of `piece` from `hexagon`, with the king's safety
+/// already worked out.
+fn legal_moves_of(hexagon: &Hexagon, piece: &Piece, board: &Board, safety: &KingSafety) -> Vec<Move> {
     let mut moves = Vec::<Move>::new();
-    for (starting_hex, moving_piece) in
-        get_all_pieces_of_matching_color(board.current_player, board)
-    {
-        let (valid_moves, _double_jump, promotion_moves) = get_valid_moves(&starting_hex, board);
-        for final_hex in valid_moves {
-            // check if we're trying to promote
-            if promotion_moves.contains(&final_hex)
-                && matches!(moving_piece.piece_type, PieceType::Pawn)
-            {
-                for piece_type in [
-                    PieceType::Bishop,
-                    PieceType::Knight,
-                    PieceType::Rook,
-                    PieceType::Queen,
-                ] {
-                    moves.push(Move {
-                        start_hex: starting_hex,
-                        final_hex: final_hex,
-                        final_piece: piece_type,
-                    })
-                }
-            } else {
+    for final_hex in get_valid_moves_without_checks(hexagon, piece, board) {
+        // validate the king is not in check after the move
+        if !safety.allows(hexagon, &final_hex, piece, board) {
+            continue;
+        }
+        // check if we're trying to promote
+        if matches!(piece.piece_type, PieceType::Pawn)
+            && board.variant.rules().is_promotion_hex(&final_hex, piece.color)
+        {
+            for piece_type in [
+                PieceType::Bishop,
+                PieceType::Knight,
+                PieceType::Rook,
+                PieceType::Queen,
+            ] {
                 moves.push(Move {
-                    start_hex: starting_hex,
-                    final_hex: final_hex,
-                    final_piece: moving_piece.piece_type,
+                    start_hex: *hexagon,
+                    final_hex,
+                    final_piece: piece_type,
                 })
             }
+        } else {
+            moves.push(Move {
+                start_hex: *hexagon,
+                final_hex,
+                final_piece: piece.piece_type,
+            })
         }
     }
     moves
 }
 
-#[derive(Debug, Clone, Copy)]
-pub enum Mate {
+/// From a given starting hexagon, give all the valid
+/// moves that a piece (which might be located there) has.
+/// If a piece isn't located there, there will be no valid
+/// moves. A pawn reaching its last hexagon gets one move
+/// per piece it can promote to.
+pub fn get_valid_moves(hexagon: &Hexagon, board: &Board) -> Vec<Move> {
+    match board.occupied_squares.get(hexagon) {
+        Some(piece) => legal_moves_of(hexagon, piece, board, &KingSafety::new(piece.color, board)),
+        None => Vec::new(),
+    }
+}
+
+pub fn get_all_valid_moves(board: &Board) -> Vec<Move> {
+    let safety = KingSafety::new(board.current_player, board);
+    let mut moves = Vec::<Move>::new();
+    for (starting_hex, moving_piece) in
+        get_all_pieces_of_matching_color(board.current_player, board)
+    {
+        moves.append(&mut legal_moves_of(&starting_hex, &moving_piece, board, &safety));
+    }
+    moves
+}
+
+/// The ways a game can finish without a resignation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameResult {
     Checkmate,
     Stalemate,
+    ThreefoldRepetition,
+    FiftyMoveRule,
+    InsufficientMaterial,
+}
+
+impl GameResult {
+    /// Whether the game ended with the point shared evenly.
+    pub fn is_draw(&self) -> bool {
+        !matches!(self, GameResult::Checkmate | GameResult::Stalemate)
+    }
+
+    /// The share of the point won by the player who made the last move.
+    /// Under Gliński's rules stalemating the opponent is worth ¾ of a win,
+    /// rather than the draw it is in orthodox chess.
+    pub fn last_mover_points(&self) -> f32 {
+        match self {
+            GameResult::Checkmate => 1.0,
+            GameResult::Stalemate => 0.75,
+            _ => 0.5,
+        }
+    }
 }
 
-pub fn check_for_mates(board: &mut Board) -> Option<Mate> {
+/// Whether the king of the given color is currently under attack.
+/// A board without that king is never in check.
+pub fn king_is_in_check(color: Color, board: &Board) -> bool {
+    !checkers(color, board).is_empty()
+}
+
+pub fn check_for_mates(board: &Board) -> Option<GameResult> {
     let current_player_color = board.current_player;
 
     // Get all pieces of the current player
     let player_pieces = get_all_pieces_of_matching_color(current_player_color, board);
+    let safety = KingSafety::new(current_player_color, board);
 
     // Check if any of the player's pieces have a valid move
-    for (start_hexagon, _piece) in player_pieces {
-        let (valid_moves, _, _) = get_valid_moves(&start_hexagon, board);
-
+    for (start_hexagon, piece) in player_pieces {
         // If any valid move exists, the player is not in checkmate
-                if !valid_moves.is_empty() {
+        if !legal_moves_of(&start_hexagon, &piece, board, &safety).is_empty() {
             return None;
         }
     }
 
-    // The player's king is in check if there are attacking pieces
-    let king_hex = get_all_pieces_of_matching_color(current_player_color, board)
-        .into_iter()
-        .find(|(_, piece)| piece.piece_type == PieceType::King)
-        .map(|(hex, _)| hex).expect("couldn't find the king");
+    // The player has no valid moves, so if their king is also under attack
+    // it's checkmate. Otherwise, it's a stalemate.
+    if king_is_in_check(current_player_color, board) {
+        Some(GameResult::Checkmate)
+    } else {
+        Some(GameResult::Stalemate)
+    }
+}
 
-    if let Some(_attacking_pieces) =
-        get_attacking_pieces(current_player_color.invert(), board, &king_hex)
-    {
-        // get_attacking_pieces will only return Some if there are attacking pieces. Hence, 
-        // the king has no valid moves and is under attack, indicating a checkmate
-        return Some(Mate::Checkmate);
+/// Neither side has enough material left to deliver checkmate. On the hex
+/// board this is a lone king against a king with at most a single knight
+/// or bishop.
+pub fn is_insufficient_material(board: &Board) -> bool {
+    let mut minor_pieces = 0;
+    for piece in board.occupied_squares.values() {
+        match piece.piece_type {
+            PieceType::King => {}
+            PieceType::Knight | PieceType::Bishop => minor_pieces += 1,
+            PieceType::Pawn | PieceType::Rook | PieceType::Queen => return false,
+        }
+    }
+    minor_pieces <= 1
+}
+
+/// Whether the current position has now occurred three times. Only the
+/// positions since the last capture or pawn move are searched, since
+/// nothing before those can repeat.
+pub fn is_threefold_repetition(board: &Board) -> bool {
+    let key = board.repetition_key();
+    let repetitions = board
+        .position_history
+        .iter()
+        .rev()
+        .take(board.halfmove_clock as usize)
+        .filter(|previous| **previous == key)
+        .count();
+    // the current position counts as one occurrence
+    repetitions + 1 >= 3
+}
+
+/// Check whether the game has finished, either by mate or by one of the
+/// draw rules.
+pub fn check_for_game_end(board: &Board) -> Option<GameResult> {
+    if let Some(mate) = check_for_mates(board) {
+        return Some(mate);
+    }
+    if is_insufficient_material(board) {
+        Some(GameResult::InsufficientMaterial)
+    } else if board.halfmove_clock >= 100 {
+        Some(GameResult::FiftyMoveRule)
+    } else if is_threefold_repetition(board) {
+        Some(GameResult::ThreefoldRepetition)
     } else {
-        return Some(Mate::Stalemate);
+        None
     }
 }
 
+/// Why a move was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum HexChessError {
-    FailedToRegisterMove,
+    /// There's no piece on the starting hexagon
+    EmptyStartHexagon(Hexagon),
+    /// The piece on the starting hexagon belongs to the other player
+    WrongColor(Hexagon),
+    /// The player asking to move isn't the one whose turn it is
     NotYourTurn,
+    /// The piece can't get from its starting hexagon to the final one
+    Unreachable { start: Hexagon, destination: Hexagon },
+    /// The move would leave the mover's own king under attack
+    LeavesKingInCheck,
+    /// A pawn reached its last hexagon without saying what to become
+    MissingPromotion,
+    /// The piece asked for isn't one this move can end up as - either a
+    /// pawn promoting to a pawn or king, or a piece that isn't promoting
+    /// at all changing type
+    InvalidPromotion(PieceType),
+    /// The game has already finished
+    GameOver(GameResult),
+}
+
+impl fmt::Display for HexChessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HexChessError::EmptyStartHexagon(hex) => write!(f, "there is no piece on {hex}"),
+            HexChessError::WrongColor(hex) => write!(f, "the piece on {hex} belongs to the other player"),
+            HexChessError::NotYourTurn => write!(f, "it is not your turn"),
+            HexChessError::Unreachable { start, destination } => {
+                write!(f, "the piece on {start} can't move to {destination}")
+            }
+            HexChessError::LeavesKingInCheck => write!(f, "the move would leave the king in check"),
+            HexChessError::MissingPromotion => write!(f, "the pawn needs a piece to promote to"),
+            HexChessError::InvalidPromotion(piece_type) => {
+                write!(f, "the move can't end with a {piece_type:?}")
+            }
+            HexChessError::GameOver(result) => write!(f, "the game is already over ({result:?})"),
+        }
+    }
 }
 
-pub fn register_move(
-    start_hexagon: &Hexagon,
-    final_hexagon: &Hexagon,
-    board: &mut Board,
-    double_jump: Option<Hexagon>,
-    promotion_moves: Vec<Hexagon>,
-    promotion_choice: Option<PieceType>,
-) -> Result<Color, HexChessError> {
-    // If succesful, return the color of the player whose turn
-    // it will now be.
-    let moving_color = board.occupied_squares.get(&start_hexagon).unwrap().color;
-    let valid_player = board.current_player;
-    // make sure the right player is trying to move
-    if !(moving_color == valid_player) {
-        return Err(HexChessError::NotYourTurn);
-    }
-
-    // try remove the moving piece from the old hex
-    match board.occupied_squares.remove(&start_hexagon) {
-        Some(piece) => {
-            // check if we're trying to promote
-            if matches!(piece.piece_type, PieceType::Pawn)
-                && promotion_moves.contains(final_hexagon)
-            {
-                if promotion_choice.is_some()
-                    && !matches!(promotion_choice.unwrap(), PieceType::Pawn | PieceType::King)
-                {
-                    // make sure a promotion choice has been selected and it isn't a pawn
-                    // or a king
-                    let newly_created_piece = Piece {
-                        piece_type: promotion_choice.unwrap(),
-                        color: moving_color,
-                    };
-                    board
-                        .occupied_squares
-                        .insert(*final_hexagon, newly_created_piece);
-                } else {
-                    board.occupied_squares.insert(*start_hexagon, piece);
-                    return Err(HexChessError::FailedToRegisterMove);
-                }
-            } else {
-                // try insert the moving piece in the new hex
-                match board.occupied_squares.insert(*final_hexagon, piece) {
-                    Some(_) => {}
-                    None => {
-                        // if we have just completed an en-passant,
-                        // we need to remove the pawn one hexagon
-                        // up or down
-                        holy_hell(board, final_hexagon, valid_player);
-                    }
-                };
-
-                // If a pawn has double jumped, then we need to register it as
-                // the latest en-passant. On the other hand, if there was no
-                // double jump, the latest en-passant will be None
-                match double_jump {
-                    Some(hex) => {
-                        if final_hexagon == &hex {
-                            board.en_passant = double_jump;
-                        }
-                    }
-                    _ => board.en_passant = None,
-                }
+impl std::error::Error for HexChessError {}
+
+/// What happened as a result of playing a move with [`Board::play`].
+#[derive(Debug, Clone, Copy)]
+pub struct MoveOutcome {
+    pub captured: Option<Piece>,
+    /// Whether the player now on move is in check
+    pub check: bool,
+    /// Set if this move ended the game
+    pub result: Option<GameResult>,
+}
+
+impl Board {
+    /// All the legal moves available to the current player.
+    pub fn legal_moves(&self) -> Vec<Move> {
+        get_all_valid_moves(self)
+    }
+
+    /// All the legal moves of the piece on `hexagon`, if there is one.
+    pub fn legal_moves_from(&self, hexagon: &Hexagon) -> Vec<Move> {
+        get_valid_moves(hexagon, self)
+    }
+
+    /// Check a move is legal in the current position, and say why not if it isn't.
+    pub fn check_move(&self, movement: Move) -> Result<(), HexChessError> {
+        if let Some(result) = check_for_game_end(self) {
+            return Err(HexChessError::GameOver(result));
+        }
+        let piece = *self
+            .occupied_squares
+            .get(&movement.start_hex)
+            .ok_or(HexChessError::EmptyStartHexagon(movement.start_hex))?;
+        if piece.color != self.current_player {
+            return Err(HexChessError::WrongColor(movement.start_hex));
+        }
+        if !get_valid_moves_without_checks(&movement.start_hex, &piece, self).contains(&movement.final_hex) {
+            return Err(HexChessError::Unreachable {
+                start: movement.start_hex,
+                destination: movement.final_hex,
+            });
+        }
+
+        let promotes = piece.piece_type == PieceType::Pawn
+            && self.variant.rules().is_promotion_hex(&movement.final_hex, piece.color);
+        match movement.final_piece {
+            PieceType::Pawn if promotes => return Err(HexChessError::MissingPromotion),
+            PieceType::King if promotes => return Err(HexChessError::InvalidPromotion(PieceType::King)),
+            final_piece if !promotes && final_piece != piece.piece_type => {
+                return Err(HexChessError::InvalidPromotion(final_piece))
             }
+            _ => {}
+        }
 
-            board.current_player = moving_color.invert();
-            Ok(moving_color.invert())
+        let mut final_hexes = vec![movement.final_hex];
+        check_moves_for_checks(&mut final_hexes, &movement.start_hex, &piece, self);
+        if final_hexes.is_empty() {
+            return Err(HexChessError::LeavesKingInCheck);
         }
-        None => Err(HexChessError::FailedToRegisterMove),
+        Ok(())
+    }
+
+    /// Validate a move against the current position and, if it is legal, apply it.
+    pub fn play(&mut self, movement: Move) -> Result<MoveOutcome, HexChessError> {
+        self.check_move(movement)?;
+
+        self.position_history.push(self.repetition_key());
+        let undo = apply_move(self, movement);
+
+        Ok(MoveOutcome {
+            captured: undo.captured.map(|(_hex, piece)| piece),
+            check: king_is_in_check(self.current_player, self),
+            result: check_for_game_end(self),
+        })
     }
+
+
 }
 
 pub fn final_hex_is_valid(final_hexagon: &Hexagon, valid_player: Color) -> bool {
@@ -557,30 +876,6 @@ pub fn final_hex_is_valid(final_hexagon: &Hexagon, valid_player: Color) -> bool
     }
 }
 
-fn holy_hell(board: &mut Board, final_hexagon: &Hexagon, valid_player: Color) {
-    if board.en_passant.is_some() {
-        if board
-            .occupied_squares
-            .get(&board.en_passant.unwrap())
-            .is_some()
-        {
-            if (board
-                .occupied_squares
-                .get(&board.en_passant.unwrap())
-                .unwrap()
-                .color
-                != valid_player)
-                & final_hex_is_valid(final_hexagon, valid_player)
-            {
-                let new_hex = convert_en_passant_to_virtual_pawn(final_hexagon, valid_player);
-                if new_hex == board.en_passant.unwrap() {
-                    board.occupied_squares.remove(&new_hex).unwrap();
-                }
-            }
-        }
-    }
-}
-
 pub fn convert_en_passant_to_virtual_pawn(final_hexagon: &Hexagon, valid_player: Color) -> Hexagon {
     let mut new_hex = final_hexagon.clone();
     let actual_pawn_file = match valid_player {
@@ -592,39 +887,121 @@ pub fn convert_en_passant_to_virtual_pawn(final_hexagon: &Hexagon, valid_player:
 }
 
 
-pub fn apply_move(board: &mut Board, movement: Move) -> (&mut Board, Option<Piece>) {
+/// Everything needed to take back a move made with [`apply_move`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MoveUndo {
+    /// The piece removed from the board by the move, and the hexagon it was
+    /// removed from. For en passant, this is not the move's final hexagon.
+    pub captured: Option<(Hexagon, Piece)>,
+    /// The board's en-passant state before the move was made.
+    pub previous_en_passant: Option<Hexagon>,
+    /// Whether the moving pawn was swapped out for `Move::final_piece`.
+    pub promotion: bool,
+    /// The board's halfmove clock before the move was made.
+    pub previous_halfmove_clock: u32,
+    /// The board's hash before the move was made.
+    pub previous_hash: u64,
+}
+
+pub fn apply_move(board: &mut Board, movement: Move) -> MoveUndo {
     // this function assumes the move is legal. The legality checking
     // should have already happened in the move generation
     let moving_piece = board
         .occupied_squares
         .remove(&movement.start_hex)
         .expect("Piece wasn't present at start hex");
-    let taken_piece = board
+    let previous_en_passant = board.en_passant;
+    let previous_hash = board.hash;
+    board.hash ^= zobrist::piece_key(moving_piece, movement.start_hex);
+    let is_pawn = matches!(moving_piece.piece_type, PieceType::Pawn);
+
+    let mut captured = board
         .occupied_squares
-        .insert(movement.final_hex, moving_piece);
+        .remove(&movement.final_hex)
+        .map(|piece| (movement.final_hex, piece));
+
+    // pawns only change rank when capturing, so a pawn landing on an empty
+    // hexagon in a different rank must be taking en passant
+    if is_pawn && captured.is_none() && movement.start_hex.rank != movement.final_hex.rank {
+        let virtual_pawn = convert_en_passant_to_virtual_pawn(&movement.final_hex, moving_piece.color);
+        if board.en_passant == Some(virtual_pawn) {
+            captured = board
+                .occupied_squares
+                .remove(&virtual_pawn)
+                .map(|piece| (virtual_pawn, piece));
+        }
+    }
+
+    if let Some((captured_hex, captured_piece)) = captured {
+        board.hash ^= zobrist::piece_key(captured_piece, captured_hex);
+    }
+
+    // a pawn that has just double jumped becomes the new en-passant target.
+    // Any other move clears the previous one.
+    let double_jump = is_pawn
+        && movement.start_hex.rank == movement.final_hex.rank
+        && movement.start_hex.file.abs_diff(movement.final_hex.file) == 2;
+    board.en_passant = if double_jump {
+        Some(movement.final_hex)
+    } else {
+        None
+    };
+    board.hash ^= zobrist::en_passant_key(previous_en_passant) ^ zobrist::en_passant_key(board.en_passant);
+
+    let promotion = moving_piece.piece_type != movement.final_piece;
+    let final_piece = Piece {
+        piece_type: movement.final_piece,
+        color: moving_piece.color,
+    };
+    board.occupied_squares.insert(movement.final_hex, final_piece);
+    board.hash ^= zobrist::piece_key(final_piece, movement.final_hex);
+
+    let previous_halfmove_clock = board.halfmove_clock;
+    board.halfmove_clock = if is_pawn || captured.is_some() {
+        0
+    } else {
+        board.halfmove_clock + 1
+    };
+    if moving_piece.color == Color::Black {
+        board.fullmove_number += 1;
+    }
+
     board.current_player = board.current_player.invert();
-    (board, taken_piece)
+    board.hash ^= zobrist::side_to_move_key(Color::Black);
+    MoveUndo {
+        captured,
+        previous_en_passant,
+        promotion,
+        previous_halfmove_clock,
+        previous_hash,
+    }
 }
 
-pub fn revert_move(
-    board: &mut Board,
-    movement: Move,
-    taken_piece: Option<Piece>,
-) -> (&mut Board, Option<Piece>) {
-    // this function assumes the move is legal. The legality checking
-    // should have already happened in the move generation
-    let moving_piece = board
+pub fn revert_move(board: &mut Board, movement: Move, undo: MoveUndo) {
+    // this function assumes the move was the last one applied to the board
+    let moved_piece = board
         .occupied_squares
         .remove(&movement.final_hex)
         .expect("Piece wasn't present at final hex");
+    let original_piece = if undo.promotion {
+        Piece {
+            piece_type: PieceType::Pawn,
+            color: moved_piece.color,
+        }
+    } else {
+        moved_piece
+    };
     board
         .occupied_squares
-        .insert(movement.start_hex, moving_piece);
-    if let Some(taken_piece) = taken_piece {
-        board
-            .occupied_squares
-            .insert(movement.final_hex, taken_piece);
+        .insert(movement.start_hex, original_piece);
+    if let Some((captured_hex, captured_piece)) = undo.captured {
+        board.occupied_squares.insert(captured_hex, captured_piece);
+    }
+    board.en_passant = undo.previous_en_passant;
+    board.halfmove_clock = undo.previous_halfmove_clock;
+    board.hash = undo.previous_hash;
+    if moved_piece.color == Color::Black {
+        board.fullmove_number -= 1;
     }
     board.current_player = board.current_player.invert();
-    (board, taken_piece)
 }
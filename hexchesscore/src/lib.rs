@@ -2,19 +2,35 @@ pub use crate::hexchesscore::*;
 
 pub mod hexchesscore;
 pub mod moves;
+pub mod geometry;
 pub mod board_representations;
+pub mod perft;
+pub mod notation;
+pub mod san;
+pub mod game_record;
+pub mod zobrist;
+pub mod variants;
+pub mod validation;
+pub mod board_diff;
+pub mod engine_protocol;
 
 #[cfg(test)]
 mod tests {
     use std::{collections::HashMap, fs::File, io::Write};
 
     use super::*;
+    use crate::variants::VariantKind;
 
     fn setup_default_board() -> Board {
         let mut default_board = Board {
             occupied_squares: HashMap::new(),
             en_passant: None,
-            current_player: Color::White
+            current_player: Color::White,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            position_history: Vec::new(),
+            variant: VariantKind::default(),
+            hash: 0,
         };
 
         default_board.occupied_squares.insert(
@@ -56,7 +72,7 @@ mod tests {
 
         output_board_representation(&board);
         
-        assert!(matches!(check_for_mates(&mut board).unwrap(), Mate::Stalemate));
+        assert!(matches!(check_for_mates(&board).unwrap(), GameResult::Stalemate));
     }
     #[test]
     fn test_check_for_mates_detects_checkmate() {
@@ -70,7 +86,7 @@ mod tests {
 
         output_board_representation(&board);
         
-        assert!(matches!(check_for_mates(&mut board).unwrap(), Mate::Checkmate));
+        assert!(matches!(check_for_mates(&board).unwrap(), GameResult::Checkmate));
     }
 
     #[test]
@@ -85,11 +101,490 @@ mod tests {
 
         output_board_representation(&board);
         
-        assert!(check_for_mates(&mut board).is_none());
+        assert!(check_for_mates(&board).is_none());
+    }
+
+    fn piece(piece_type: PieceType, color: Color) -> Piece {
+        Piece { piece_type, color }
+    }
+
+    fn setup_en_passant_board() -> Board {
+        // black is about to double jump G7 -> G5, past the white pawn on F6
+        let mut board = Board::new();
+        board.occupied_squares.insert(Hexagon::new("F1").unwrap(), piece(PieceType::King, Color::White));
+        board.occupied_squares.insert(Hexagon::new("F11").unwrap(), piece(PieceType::King, Color::Black));
+        board.occupied_squares.insert(Hexagon::new("F6").unwrap(), piece(PieceType::Pawn, Color::White));
+        board.occupied_squares.insert(Hexagon::new("G7").unwrap(), piece(PieceType::Pawn, Color::Black));
+        board.current_player = Color::Black;
+        board
+    }
+
+    fn setup_promotion_board() -> Board {
+        let mut board = Board::new();
+        board.occupied_squares.insert(Hexagon::new("A1").unwrap(), piece(PieceType::King, Color::White));
+        board.occupied_squares.insert(Hexagon::new("L6").unwrap(), piece(PieceType::King, Color::Black));
+        board.occupied_squares.insert(Hexagon::new("F10").unwrap(), piece(PieceType::Pawn, Color::White));
+        board.occupied_squares.insert(Hexagon::new("E10").unwrap(), piece(PieceType::Rook, Color::Black));
+        board
+    }
+
+    fn assert_move_reverts(board: &Board, movement: Move) {
+        let mut new_board = board.clone();
+        let undo = apply_move(&mut new_board, movement);
+        assert_ne!(&new_board, board);
+        revert_move(&mut new_board, movement, undo);
+        assert_eq!(&new_board, board);
+    }
+
+    fn assert_all_moves_revert(board: &Board) {
+        let moves = get_all_valid_moves(board);
+        assert!(!moves.is_empty());
+        for movement in moves {
+            assert_move_reverts(board, movement);
+        }
+    }
+
+    #[test]
+    fn test_apply_move_double_jump_sets_en_passant() {
+        let mut board = setup_en_passant_board();
+        let movement = Move {
+            start_hex: Hexagon::new("G7").unwrap(),
+            final_hex: Hexagon::new("G5").unwrap(),
+            final_piece: PieceType::Pawn,
+        };
+        assert_move_reverts(&board, movement);
+
+        let undo = apply_move(&mut board, movement);
+        assert_eq!(board.en_passant, Hexagon::new("G5"));
+        assert_eq!(undo.captured, None);
+        assert_eq!(undo.previous_en_passant, None);
+        assert!(!undo.promotion);
+    }
+
+    #[test]
+    fn test_apply_move_en_passant_removes_pawn() {
+        let mut board = setup_en_passant_board();
+        apply_move(
+            &mut board,
+            Move {
+                start_hex: Hexagon::new("G7").unwrap(),
+                final_hex: Hexagon::new("G5").unwrap(),
+                final_piece: PieceType::Pawn,
+            },
+        );
+        let en_passant = Move {
+            start_hex: Hexagon::new("F6").unwrap(),
+            final_hex: Hexagon::new("G6").unwrap(),
+            final_piece: PieceType::Pawn,
+        };
+        assert!(get_all_valid_moves(&board).contains(&en_passant));
+        assert_move_reverts(&board, en_passant);
+        assert_all_moves_revert(&board);
+
+        let undo = apply_move(&mut board, en_passant);
+        assert_eq!(
+            undo.captured,
+            Some((Hexagon::new("G5").unwrap(), piece(PieceType::Pawn, Color::Black)))
+        );
+        assert_eq!(undo.previous_en_passant, Hexagon::new("G5"));
+        assert!(board.occupied_squares.get(&Hexagon::new("G5").unwrap()).is_none());
+        assert_eq!(board.en_passant, None);
+    }
+
+    #[test]
+    fn test_apply_move_clears_stale_en_passant() {
+        let mut board = setup_en_passant_board();
+        board.en_passant = Hexagon::new("F6");
+        let movement = Move {
+            start_hex: Hexagon::new("F11").unwrap(),
+            final_hex: Hexagon::new("F10").unwrap(),
+            final_piece: PieceType::King,
+        };
+        assert_move_reverts(&board, movement);
+
+        apply_move(&mut board, movement);
+        assert_eq!(board.en_passant, None);
+    }
+
+    #[test]
+    fn test_apply_move_promotes() {
+        let mut board = setup_promotion_board();
+        let promotion = Move {
+            start_hex: Hexagon::new("F10").unwrap(),
+            final_hex: Hexagon::new("F11").unwrap(),
+            final_piece: PieceType::Knight,
+        };
+        let capturing_promotion = Move {
+            start_hex: Hexagon::new("F10").unwrap(),
+            final_hex: Hexagon::new("E10").unwrap(),
+            final_piece: PieceType::Queen,
+        };
+        let moves = get_all_valid_moves(&board);
+        assert!(moves.contains(&promotion));
+        assert!(moves.contains(&capturing_promotion));
+        assert_all_moves_revert(&board);
+
+        let undo = apply_move(&mut board.clone(), promotion);
+        assert!(undo.promotion);
+        assert_eq!(undo.captured, None);
+
+        let undo = apply_move(&mut board, capturing_promotion);
+        assert!(undo.promotion);
+        assert_eq!(
+            undo.captured,
+            Some((Hexagon::new("E10").unwrap(), piece(PieceType::Rook, Color::Black)))
+        );
+        assert_eq!(
+            board.occupied_squares.get(&Hexagon::new("E10").unwrap()),
+            Some(&piece(PieceType::Queen, Color::White))
+        );
+    }
+
+    #[test]
+    fn test_apply_move_reverts_from_default_board() {
+        let board = Board::setup_default_board();
+        assert_all_moves_revert(&board);
+
+        // play a few moves in, so that captures are on offer too
+        let mut board = board;
+        for (start, end) in [("E4", "E5"), ("F7", "F6")] {
+            let piece_type = board.occupied_squares[&Hexagon::new(start).unwrap()].piece_type;
+            apply_move(
+                &mut board,
+                Move {
+                    start_hex: Hexagon::new(start).unwrap(),
+                    final_hex: Hexagon::new(end).unwrap(),
+                    final_piece: piece_type,
+                },
+            );
+        }
+        assert_all_moves_revert(&board);
+    }
+
+    #[test]
+    fn test_play_applies_legal_moves() {
+        let mut board = Board::setup_default_board();
+        let movement = Move {
+            start_hex: Hexagon::new("E4").unwrap(),
+            final_hex: Hexagon::new("E6").unwrap(),
+            final_piece: PieceType::Pawn,
+        };
+        let outcome = board.play(movement).unwrap();
+        assert!(outcome.captured.is_none());
+        assert!(!outcome.check);
+        assert!(outcome.result.is_none());
+        assert_eq!(board.current_player, Color::Black);
+        assert_eq!(board.en_passant, Hexagon::new("E6"));
+    }
+
+    fn hex_move(start: &str, finish: &str, final_piece: PieceType) -> Move {
+        Move {
+            start_hex: Hexagon::new(start).unwrap(),
+            final_hex: Hexagon::new(finish).unwrap(),
+            final_piece,
+        }
+    }
+
+    #[test]
+    fn test_play_rejects_illegal_moves() {
+        let mut board = Board::setup_default_board();
+        let original = board.clone();
+
+        // not a legal pawn move
+        assert_eq!(
+            board.play(hex_move("F5", "F8", PieceType::Pawn)).unwrap_err(),
+            HexChessError::Unreachable {
+                start: Hexagon::new("F5").unwrap(),
+                destination: Hexagon::new("F8").unwrap()
+            }
+        );
+
+        // black pieces can't move on white's turn
+        assert_eq!(
+            board.play(hex_move("F7", "F6", PieceType::Pawn)).unwrap_err(),
+            HexChessError::WrongColor(Hexagon::new("F7").unwrap())
+        );
+
+        // no piece on the start hexagon
+        assert_eq!(
+            board.play(hex_move("F6", "F7", PieceType::Pawn)).unwrap_err(),
+            HexChessError::EmptyStartHexagon(Hexagon::new("F6").unwrap())
+        );
+
+        // only promoting pawns can change what they are
+        let mut knight_move = board.legal_moves_from(&Hexagon::new("D1").unwrap())[0];
+        knight_move.final_piece = PieceType::Queen;
+        assert_eq!(board.play(knight_move).unwrap_err(), HexChessError::InvalidPromotion(PieceType::Queen));
+
+        // a promoting pawn must pick a piece to become, and it can't be a king
+        let mut promotion_board = setup_promotion_board();
+        assert_eq!(
+            promotion_board.play(hex_move("F10", "F11", PieceType::Pawn)).unwrap_err(),
+            HexChessError::MissingPromotion
+        );
+        assert_eq!(
+            promotion_board.play(hex_move("F10", "F11", PieceType::King)).unwrap_err(),
+            HexChessError::InvalidPromotion(PieceType::King)
+        );
+
+        assert_eq!(board, original);
+    }
+
+    #[test]
+    fn test_play_rejects_moves_into_check_and_after_the_game() {
+        // the rook on F3 is pinned to its king by the rook on F8
+        let mut board = Board::new();
+        board.occupied_squares.insert(Hexagon::new("F1").unwrap(), piece(PieceType::King, Color::White));
+        board.occupied_squares.insert(Hexagon::new("F3").unwrap(), piece(PieceType::Rook, Color::White));
+        board.occupied_squares.insert(Hexagon::new("F8").unwrap(), piece(PieceType::Rook, Color::Black));
+        board.occupied_squares.insert(Hexagon::new("L6").unwrap(), piece(PieceType::King, Color::Black));
+        board.refresh_hash();
+        assert_eq!(
+            board.play(hex_move("F3", "E3", PieceType::Rook)).unwrap_err(),
+            HexChessError::LeavesKingInCheck
+        );
+        assert!(board.play(hex_move("F3", "F4", PieceType::Rook)).is_ok());
+
+        let mut board = Board::setup_default_board();
+        board.halfmove_clock = 100;
+        let error = board.play(hex_move("F5", "F6", PieceType::Pawn)).unwrap_err();
+        assert_eq!(error, HexChessError::GameOver(GameResult::FiftyMoveRule));
+        assert_eq!(error.to_string(), "the game is already over (FiftyMoveRule)");
+    }
+
+    #[test]
+    fn test_board_can_be_shared_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Board>();
+
+        let board = Board::setup_default_board();
+        let counts: Vec<usize> = std::thread::scope(|scope| {
+            let readers: Vec<_> = (0..4).map(|_| scope.spawn(|| board.legal_moves().len())).collect();
+            readers.into_iter().map(|reader| reader.join().unwrap()).collect()
+        });
+        assert_eq!(counts, vec![51; 4]);
+    }
+
+    #[test]
+    fn test_attacks_pins_and_checkers() {
+        let hex = |name| Hexagon::new(name).unwrap();
+        let mut board = Board::new();
+        board.occupied_squares.insert(hex("F1"), piece(PieceType::King, Color::White));
+        board.occupied_squares.insert(hex("F3"), piece(PieceType::Rook, Color::White));
+        board.occupied_squares.insert(hex("F8"), piece(PieceType::Rook, Color::Black));
+        board.occupied_squares.insert(hex("L6"), piece(PieceType::King, Color::Black));
+        board.refresh_hash();
+
+        assert_eq!(attacks_to(&hex("F4"), Color::Black, &board), vec![hex("F8")]);
+        assert!(attacks_to(&hex("F2"), Color::Black, &board).is_empty());
+        assert_eq!(pinned_pieces(Color::White, &board), vec![(hex("F3"), 0)]);
+        assert!(checkers(Color::White, &board).is_empty());
+
+        // a knight check can't be blocked, and the pinned rook can't take it
+        board.occupied_squares.insert(hex("G3"), piece(PieceType::Knight, Color::Black));
+        board.refresh_hash();
+        assert_eq!(checkers(Color::White, &board), vec![hex("G3")]);
+        let moves = board.legal_moves();
+        assert!(!moves.is_empty());
+        assert!(moves.iter().all(|movement| movement.start_hex == hex("F1")));
+    }
+
+    #[test]
+    fn test_play_reports_checks_and_mates() {
+        let mut board = Board::new();
+        board.occupied_squares.insert(Hexagon::new("A4").unwrap(), piece(PieceType::King, Color::White));
+        board.occupied_squares.insert(Hexagon::new("C4").unwrap(), piece(PieceType::King, Color::Black));
+        board.occupied_squares.insert(Hexagon::new("C8").unwrap(), piece(PieceType::Rook, Color::Black));
+        board.current_player = Color::Black;
+
+        let mut stalemate_board = board.clone();
+        let outcome = stalemate_board
+            .play(Move {
+                start_hex: Hexagon::new("C8").unwrap(),
+                final_hex: Hexagon::new("C7").unwrap(),
+                final_piece: PieceType::Rook,
+            })
+            .unwrap();
+        assert!(!outcome.check);
+        assert!(matches!(outcome.result, Some(GameResult::Stalemate)));
+        // which under Gliński's rules is mostly a win
+        assert!(!outcome.result.unwrap().is_draw());
+
+        let outcome = board
+            .play(Move {
+                start_hex: Hexagon::new("C8").unwrap(),
+                final_hex: Hexagon::new("A6").unwrap(),
+                final_piece: PieceType::Rook,
+            })
+            .unwrap();
+        assert!(outcome.check);
+        assert!(matches!(outcome.result, Some(GameResult::Checkmate)));
+    }
+
+    #[test]
+    fn test_halfmove_clock_resets_and_reverts() {
+        let mut board = Board::setup_default_board();
+        board.halfmove_clock = 7;
+
+        // a knight move ticks the clock on
+        let knight_move = board.legal_moves_from(&Hexagon::new("D1").unwrap())[0];
+        let undo = apply_move(&mut board, knight_move);
+        assert_eq!(board.halfmove_clock, 8);
+        revert_move(&mut board, knight_move, undo);
+        assert_eq!(board.halfmove_clock, 7);
+
+        // a pawn move resets it
+        let pawn_move = Move {
+            start_hex: Hexagon::new("E4").unwrap(),
+            final_hex: Hexagon::new("E5").unwrap(),
+            final_piece: PieceType::Pawn,
+        };
+        let undo = apply_move(&mut board, pawn_move);
+        assert_eq!(board.halfmove_clock, 0);
+        revert_move(&mut board, pawn_move, undo);
+        assert_eq!(board.halfmove_clock, 7);
+    }
+
+    #[test]
+    fn test_play_detects_threefold_repetition() {
+        let mut board = Board::setup_default_board();
+        let white_out = board.legal_moves_from(&Hexagon::new("D1").unwrap())[0];
+        let white_back = Move {
+            start_hex: white_out.final_hex,
+            final_hex: white_out.start_hex,
+            final_piece: PieceType::Knight,
+        };
+        board.play(white_out).unwrap();
+        let black_out = board.legal_moves_from(&Hexagon::new("H9").unwrap())[0];
+        let black_back = Move {
+            start_hex: black_out.final_hex,
+            final_hex: black_out.start_hex,
+            final_piece: PieceType::Knight,
+        };
+        board.play(black_out).unwrap();
+        board.play(white_back).unwrap();
+        // the starting position occurs for the second time
+        assert!(board.play(black_back).unwrap().result.is_none());
+
+        board.play(white_out).unwrap();
+        board.play(black_out).unwrap();
+        board.play(white_back).unwrap();
+        // and now the third
+        let outcome = board.play(black_back).unwrap();
+        assert_eq!(outcome.result, Some(GameResult::ThreefoldRepetition));
+        assert!(outcome.result.unwrap().is_draw());
+    }
+
+    #[test]
+    fn test_repetitions_ignore_en_passant_nobody_can_take() {
+        let mut board = Board::setup_default_board();
+        let double_jump = board
+            .legal_moves_from(&Hexagon::new("E4").unwrap())
+            .into_iter()
+            .find(|movement| movement.final_hex == Hexagon::new("E6").unwrap())
+            .unwrap();
+        board.play(double_jump).unwrap();
+        // no black pawn is next to e6, so the double jump changes nothing
+        assert_ne!(board.repetition_key(), board.hash());
+
+        let black_out = board.legal_moves_from(&Hexagon::new("H9").unwrap())[0];
+        let black_back = Move {
+            start_hex: black_out.final_hex,
+            final_hex: black_out.start_hex,
+            final_piece: PieceType::Knight,
+        };
+        board.play(black_out).unwrap();
+        let white_out = board.legal_moves_from(&Hexagon::new("D1").unwrap())[0];
+        let white_back = Move {
+            start_hex: white_out.final_hex,
+            final_hex: white_out.start_hex,
+            final_piece: PieceType::Knight,
+        };
+        board.play(white_out).unwrap();
+        board.play(black_back).unwrap();
+        // the position after the double jump occurs for the second time
+        assert!(board.play(white_back).unwrap().result.is_none());
+
+        board.play(black_out).unwrap();
+        board.play(white_out).unwrap();
+        board.play(black_back).unwrap();
+        // and now the third
+        let outcome = board.play(white_back).unwrap();
+        assert_eq!(outcome.result, Some(GameResult::ThreefoldRepetition));
+
+        // a black pawn on d5 could take it, so then it does count
+        let mut board = Board::setup_default_board();
+        board.occupied_squares.insert(Hexagon::new("D5").unwrap(), piece(PieceType::Pawn, Color::Black));
+        board.refresh_hash();
+        board.play(double_jump).unwrap();
+        assert_eq!(board.repetition_key(), board.hash());
+    }
+
+    #[test]
+    fn test_position_history_is_sent_as_strings() {
+        let mut board = Board::setup_default_board();
+        let movement = board.legal_moves()[0];
+        board.play(movement).unwrap();
+
+        let json = serde_json::to_value(&board).unwrap();
+        assert_eq!(json["position_history"], serde_json::json!([board.position_history[0].to_string()]));
+        let read_back: Board = serde_json::from_value(json).unwrap();
+        assert_eq!(read_back, board);
+    }
+
+    #[test]
+    fn test_boards_with_off_board_hexagons_are_not_read() {
+        // l11 is past the end of the tables, and a10 past the end of its rank
+        for (square, en_passant) in [("l11", "null"), ("a10", "null"), ("f1", "\"a10\"")] {
+            let json = format!(
+                r#"{{"occupied_squares": {{"{square}": {{"piece_type": "King", "color": "White"}}}},
+                    "en_passant": {en_passant}, "current_player": "White"}}"#
+            );
+            let error = serde_json::from_str::<Board>(&json).unwrap_err();
+            assert!(error.to_string().contains("is not on the board"), "{error}");
+        }
+    }
+
+    #[test]
+    fn test_play_detects_fifty_move_rule() {
+        let mut board = Board::setup_default_board();
+        board.halfmove_clock = 99;
+        let knight_move = board.legal_moves_from(&Hexagon::new("D1").unwrap())[0];
+        let outcome = board.play(knight_move).unwrap();
+        assert_eq!(outcome.result, Some(GameResult::FiftyMoveRule));
+
+        // a pawn move just in time resets the count
+        let mut board = Board::setup_default_board();
+        board.halfmove_clock = 99;
+        let outcome = board
+            .play(Move {
+                start_hex: Hexagon::new("E4").unwrap(),
+                final_hex: Hexagon::new("E5").unwrap(),
+                final_piece: PieceType::Pawn,
+            })
+            .unwrap();
+        assert!(outcome.result.is_none());
+    }
+
+    #[test]
+    fn test_insufficient_material() {
+        let mut board = Board::new();
+        board.occupied_squares.insert(Hexagon::new("F1").unwrap(), piece(PieceType::King, Color::White));
+        board.occupied_squares.insert(Hexagon::new("F11").unwrap(), piece(PieceType::King, Color::Black));
+        assert!(is_insufficient_material(&board));
+        assert_eq!(check_for_game_end(&board), Some(GameResult::InsufficientMaterial));
+
+        board.occupied_squares.insert(Hexagon::new("C1").unwrap(), piece(PieceType::Knight, Color::White));
+        assert!(is_insufficient_material(&board));
+
+        board.occupied_squares.insert(Hexagon::new("C8").unwrap(), piece(PieceType::Bishop, Color::Black));
+        assert!(!is_insufficient_material(&board));
+
+        board.occupied_squares.remove(&Hexagon::new("C8").unwrap());
+        board.occupied_squares.insert(Hexagon::new("B7").unwrap(), piece(PieceType::Pawn, Color::Black));
+        assert!(!is_insufficient_material(&board));
     }
 
-      
-    
     fn output_board_representation(board: &Board) {
         let mut f = File::create("../server/debug/board.json").expect("Couldn't open file");
 
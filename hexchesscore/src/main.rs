@@ -1,2 +1,35 @@
+use std::env;
+
+use hexchesscore::{perft::perft_divide, Board};
+
+/// Print a perft divide, e.g. `hexchesscore 3`, for comparing node counts
+/// against another move generator. A position in hexchesscore's notation
+/// can be given after the depth; otherwise the starting position is used.
 fn main() {
+    let depth = env::args()
+        .nth(1)
+        .and_then(|depth| depth.parse().ok())
+        .unwrap_or(3);
+    let board = match env::args().nth(2) {
+        Some(notation) => Board::from_notation(&notation).unwrap_or_else(|err| {
+            eprintln!("Couldn't read position: {err}");
+            std::process::exit(1)
+        }),
+        None => Board::setup_default_board(),
+    };
+
+    let mut total = 0;
+    for (movement, nodes) in perft_divide(&mut board.clone(), depth) {
+        let piece = board.occupied_squares[&movement.start_hex];
+        if piece.piece_type == movement.final_piece {
+            println!("{}{}: {}", movement.start_hex, movement.final_hex, nodes);
+        } else {
+            println!(
+                "{}{}={:?}: {}",
+                movement.start_hex, movement.final_hex, movement.final_piece, nodes
+            );
+        }
+        total += nodes;
+    }
+    println!("\nNodes searched: {total}");
 }
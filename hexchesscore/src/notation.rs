@@ -0,0 +1,458 @@
+//! A compact text form of a position, modelled on chess FEN. For example,
+//! the starting position is
+//!
+//! ```text
+//! 6/P5p/RP4pr/N1P3p1n/Q2P2p2q/BBB1P1p1bbb/K2P2p2k/N1P3p1n/RP4pr/P5p/6 w - 0 1
+//! ```
+//!
+//! The fields, separated by spaces, are:
+//!
+//! 1. Piece placement, one rank at a time from `a` to `l`, separated by `/`.
+//!    Each rank lists its hexagons from file 1 upwards. White pieces are
+//!    upper case and black pieces lower case (`KQRBNP`), and a number
+//!    stands for that many empty hexagons in a row.
+//! 2. The side to move, `w` or `b`.
+//! 3. The hexagon a pawn can be taken on en passant - the one the pawn
+//!    jumped over - or `-`.
+//! 4. The halfmove clock.
+//! 5. The fullmove number.
+//! 6. The variant, like `McCooey`, for any but Gliński's. It's left off for
+//!    Gliński's, so a position in the other five fields is played by his
+//!    rules.
+//!
+//! Position history isn't part of the notation, so a board read back in
+//! starts with an empty history.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::moves::get_rank_length;
+use crate::validation::PositionProblem;
+use crate::variants::VariantKind;
+use crate::{rank_int_to_char, Board, Color, Hexagon, Piece, PieceType};
+
+const NUMBER_OF_RANKS: u8 = 11;
+
+/// Why a position string couldn't be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotationError {
+    /// There should be five space separated fields, or six with a variant
+    WrongNumberOfFields(usize),
+    /// There should be exactly eleven ranks in the piece placement
+    WrongNumberOfRanks(usize),
+    /// The rank describes more or fewer hexagons than it has
+    WrongRankLength { rank: char, expected: u8, found: usize },
+    /// A character in the piece placement isn't a piece or a number
+    InvalidPiece { rank: char, character: char },
+    InvalidSideToMove(String),
+    InvalidEnPassant(String),
+    InvalidHalfmoveClock(String),
+    InvalidFullmoveNumber(String),
+    /// The sixth field isn't one of the variants in [`VariantKind`]
+    InvalidVariant(String),
+    /// The notation is fine, but the position it describes isn't one a
+    /// game could reach
+    InvalidPosition(Vec<PositionProblem>),
+}
+
+impl fmt::Display for NotationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotationError::WrongNumberOfFields(found) => {
+                write!(f, "expected 5 or 6 space separated fields, found {found}")
+            }
+            NotationError::WrongNumberOfRanks(found) => {
+                write!(f, "expected {NUMBER_OF_RANKS} ranks, found {found}")
+            }
+            NotationError::WrongRankLength {
+                rank,
+                expected,
+                found,
+            } => write!(f, "rank {rank} has {expected} hexagons, but {found} were given"),
+            NotationError::InvalidPiece { rank, character } => {
+                write!(f, "'{character}' in rank {rank} is not a piece or a number")
+            }
+            NotationError::InvalidSideToMove(side) => {
+                write!(f, "side to move must be 'w' or 'b', not '{side}'")
+            }
+            NotationError::InvalidEnPassant(hex) => {
+                write!(f, "'{hex}' is not '-' or a hexagon a pawn can be taken en passant on")
+            }
+            NotationError::InvalidHalfmoveClock(clock) => {
+                write!(f, "'{clock}' is not a valid halfmove clock")
+            }
+            NotationError::InvalidFullmoveNumber(number) => {
+                write!(f, "'{number}' is not a valid fullmove number")
+            }
+            NotationError::InvalidVariant(variant) => write!(f, "unknown variant '{variant}'"),
+            NotationError::InvalidPosition(problems) => {
+                let problems: Vec<String> = problems.iter().map(|problem| problem.to_string()).collect();
+                write!(f, "the position can't come up in a game: {}", problems.join(", "))
+            }
+        }
+    }
+}
+
+impl Error for NotationError {}
+
+fn piece_to_char(piece: &Piece) -> char {
+    let c = match piece.piece_type {
+        PieceType::King => 'k',
+        PieceType::Queen => 'q',
+        PieceType::Rook => 'r',
+        PieceType::Bishop => 'b',
+        PieceType::Knight => 'n',
+        PieceType::Pawn => 'p',
+    };
+    match piece.color {
+        Color::White => c.to_ascii_uppercase(),
+        Color::Black => c,
+    }
+}
+
+fn char_to_piece(c: char) -> Option<Piece> {
+    let piece_type = match c.to_ascii_lowercase() {
+        'k' => PieceType::King,
+        'q' => PieceType::Queen,
+        'r' => PieceType::Rook,
+        'b' => PieceType::Bishop,
+        'n' => PieceType::Knight,
+        'p' => PieceType::Pawn,
+        _ => return None,
+    };
+    let color = if c.is_ascii_uppercase() {
+        Color::White
+    } else {
+        Color::Black
+    };
+    Some(Piece { piece_type, color })
+}
+
+/// The board stores the pawn that can be taken en passant, but the notation
+/// names the hexagon it jumped over, which is where the capturing pawn lands.
+fn en_passant_target(pawn: &Hexagon, current_player: Color) -> Hexagon {
+    match current_player {
+        // black pawns jump down the files, white pawns up them
+        Color::White => Hexagon {
+            rank: pawn.rank,
+            file: pawn.file + 1,
+        },
+        Color::Black => Hexagon {
+            rank: pawn.rank,
+            file: pawn.file - 1,
+        },
+    }
+}
+
+fn parse_rank(rank: u8, placement: &str, board: &mut Board) -> Result<(), NotationError> {
+    let rank_char = rank_int_to_char(rank).unwrap();
+    let rank_length = get_rank_length(rank).unwrap();
+
+    // count hexagons as usize, saturating, so an overlong rank or a huge
+    // number of empty hexagons is reported rather than overflowing
+    let mut file: usize = 0;
+    let mut empty_run: usize = 0;
+    for character in placement.chars() {
+        if let Some(digit) = character.to_digit(10) {
+            empty_run = empty_run.saturating_mul(10).saturating_add(digit as usize);
+            continue;
+        }
+        file = file.saturating_add(empty_run);
+        empty_run = 0;
+
+        let piece = char_to_piece(character).ok_or(NotationError::InvalidPiece {
+            rank: rank_char,
+            character,
+        })?;
+        if file < rank_length as usize {
+            board.occupied_squares.insert(
+                Hexagon {
+                    rank,
+                    file: file as u8,
+                },
+                piece,
+            );
+        }
+        file = file.saturating_add(1);
+    }
+    file = file.saturating_add(empty_run);
+
+    if file != rank_length as usize {
+        return Err(NotationError::WrongRankLength {
+            rank: rank_char,
+            expected: rank_length,
+            found: file,
+        });
+    }
+    Ok(())
+}
+
+fn parse_en_passant(field: &str, current_player: Color) -> Result<Option<Hexagon>, NotationError> {
+    if field == "-" {
+        return Ok(None);
+    }
+    let invalid = || NotationError::InvalidEnPassant(field.to_string());
+
+    let target = Hexagon::new(field).ok_or_else(invalid)?;
+    let rank_length = get_rank_length(target.rank).ok_or_else(invalid)?;
+    // the pawn being taken sits one hexagon further on from the target,
+    // so the target can never be at the end of a file
+    let pawn_file = match current_player {
+        Color::White => target.file.checked_sub(1),
+        Color::Black => Some(target.file + 1).filter(|file| *file < rank_length),
+    };
+    match pawn_file {
+        Some(file) if target.file < rank_length => Ok(Some(Hexagon {
+            rank: target.rank,
+            file,
+        })),
+        _ => Err(invalid()),
+    }
+}
+
+impl Board {
+    /// Write the position out in the notation described in [`crate::notation`].
+    pub fn to_notation(&self) -> String {
+        let mut ranks = Vec::<String>::new();
+        for rank in 0..NUMBER_OF_RANKS {
+            let mut placement = String::new();
+            let mut empty_run = 0;
+            for file in 0..get_rank_length(rank).unwrap() {
+                match self.occupied_squares.get(&Hexagon { rank, file }) {
+                    Some(piece) => {
+                        if empty_run > 0 {
+                            placement.push_str(&empty_run.to_string());
+                            empty_run = 0;
+                        }
+                        placement.push(piece_to_char(piece));
+                    }
+                    None => empty_run += 1,
+                }
+            }
+            if empty_run > 0 {
+                placement.push_str(&empty_run.to_string());
+            }
+            ranks.push(placement);
+        }
+
+        let side_to_move = match self.current_player {
+            Color::White => "w",
+            Color::Black => "b",
+        };
+        let en_passant = match self.en_passant {
+            Some(pawn) => en_passant_target(&pawn, self.current_player).to_string(),
+            None => "-".to_string(),
+        };
+
+        let mut notation = format!(
+            "{} {} {} {} {}",
+            ranks.join("/"),
+            side_to_move,
+            en_passant,
+            self.halfmove_clock,
+            self.fullmove_number
+        );
+        if self.variant != VariantKind::Glinski {
+            notation.push_str(&format!(" {}", self.variant));
+        }
+        notation
+    }
+
+    /// Read a position written in the notation described in [`crate::notation`].
+    pub fn from_notation(notation: &str) -> Result<Board, NotationError> {
+        let fields: Vec<&str> = notation.split_whitespace().collect();
+        let (placement, side_to_move, en_passant, halfmove_clock, fullmove_number, variant) = match fields[..] {
+            [placement, side_to_move, en_passant, halfmove_clock, fullmove_number] => {
+                (placement, side_to_move, en_passant, halfmove_clock, fullmove_number, None)
+            }
+            [placement, side_to_move, en_passant, halfmove_clock, fullmove_number, variant] => {
+                (placement, side_to_move, en_passant, halfmove_clock, fullmove_number, Some(variant))
+            }
+            _ => return Err(NotationError::WrongNumberOfFields(fields.len())),
+        };
+
+        let mut board = Board::new();
+        if let Some(variant) = variant {
+            board.variant = variant
+                .parse()
+                .map_err(|_| NotationError::InvalidVariant(variant.to_string()))?;
+        }
+
+        let ranks: Vec<&str> = placement.split('/').collect();
+        if ranks.len() != NUMBER_OF_RANKS as usize {
+            return Err(NotationError::WrongNumberOfRanks(ranks.len()));
+        }
+        for (rank, rank_placement) in ranks.iter().enumerate() {
+            parse_rank(rank as u8, rank_placement, &mut board)?;
+        }
+
+        board.current_player = match side_to_move {
+            "w" => Color::White,
+            "b" => Color::Black,
+            _ => return Err(NotationError::InvalidSideToMove(side_to_move.to_string())),
+        };
+        board.en_passant = parse_en_passant(en_passant, board.current_player)?;
+        board.halfmove_clock = halfmove_clock
+            .parse()
+            .map_err(|_| NotationError::InvalidHalfmoveClock(halfmove_clock.to_string()))?;
+        board.fullmove_number = fullmove_number
+            .parse()
+            .ok()
+            .filter(|number| *number > 0)
+            .ok_or(NotationError::InvalidFullmoveNumber(fullmove_number.to_string()))?;
+        board.refresh_hash();
+        board.validate().map_err(NotationError::InvalidPosition)?;
+
+        Ok(board)
+    }
+}
+
+/// Allows a board to be read with `notation.parse::<Board>()`.
+impl std::str::FromStr for Board {
+    type Err = NotationError;
+
+    fn from_str(notation: &str) -> Result<Board, NotationError> {
+        Board::from_notation(notation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply_move;
+    use crate::Move;
+
+    const STARTING_POSITION: &str =
+        "6/P5p/RP4pr/N1P3p1n/Q2P2p2q/BBB1P1p1bbb/K2P2p2k/N1P3p1n/RP4pr/P5p/6 w - 0 1";
+
+    #[test]
+    fn test_default_board_notation() {
+        let board = Board::setup_default_board();
+        assert_eq!(board.to_notation(), STARTING_POSITION);
+        assert_eq!(Board::from_notation(STARTING_POSITION).unwrap(), board);
+    }
+
+    #[test]
+    fn test_notation_keeps_the_variant() {
+        for variant in VariantKind::ALL {
+            let board = Board::setup_variant(variant);
+            let notation = board.to_notation();
+            assert_eq!(notation.ends_with(&variant.to_string()), variant != VariantKind::Glinski, "{notation}");
+            let read_back = Board::from_notation(&notation).unwrap();
+            assert_eq!(read_back, board);
+            assert_eq!(read_back.legal_moves().len(), board.legal_moves().len());
+        }
+    }
+
+    #[test]
+    fn test_notation_round_trips_en_passant_and_counters() {
+        let mut board = Board::setup_default_board();
+        apply_move(
+            &mut board,
+            Move {
+                start_hex: Hexagon::new("E4").unwrap(),
+                final_hex: Hexagon::new("E6").unwrap(),
+                final_piece: PieceType::Pawn,
+            },
+        );
+        let knight_move = board.legal_moves_from(&Hexagon::new("H9").unwrap())[0];
+        apply_move(&mut board, knight_move);
+        let notation = board.to_notation();
+        assert!(notation.ends_with(" w - 1 2"), "{notation}");
+        assert_eq!(Board::from_notation(&notation).unwrap(), board);
+
+        // white's double jump from e4 to e6 passes over e5
+        let mut board = Board::setup_default_board();
+        apply_move(
+            &mut board,
+            Move {
+                start_hex: Hexagon::new("E4").unwrap(),
+                final_hex: Hexagon::new("E6").unwrap(),
+                final_piece: PieceType::Pawn,
+            },
+        );
+        let notation = board.to_notation();
+        assert!(notation.ends_with(" b e5 0 1"), "{notation}");
+        let parsed: Board = notation.parse().unwrap();
+        assert_eq!(parsed.en_passant, Hexagon::new("E6"));
+        assert_eq!(parsed, board);
+    }
+
+    #[test]
+    fn test_notation_errors() {
+        assert_eq!(
+            Board::from_notation("6/P5p w - 0 1"),
+            Err(NotationError::WrongNumberOfRanks(2))
+        );
+        assert_eq!(
+            Board::from_notation(&STARTING_POSITION.replace(" 0 1", "")),
+            Err(NotationError::WrongNumberOfFields(3))
+        );
+        assert_eq!(
+            Board::from_notation(&STARTING_POSITION.replacen("P5p", "P6p", 1)),
+            Err(NotationError::WrongRankLength {
+                rank: 'b',
+                expected: 7,
+                found: 8
+            })
+        );
+        // too many empty hexagons to count
+        let huge_run = "9".repeat(40);
+        assert_eq!(
+            Board::from_notation(&STARTING_POSITION.replacen("6/", &format!("{huge_run}/"), 1)),
+            Err(NotationError::WrongRankLength {
+                rank: 'a',
+                expected: 6,
+                found: usize::MAX
+            })
+        );
+        assert_eq!(
+            Board::from_notation(&STARTING_POSITION.replacen("6/", &format!("{huge_run}P{huge_run}/"), 1)),
+            Err(NotationError::WrongRankLength {
+                rank: 'a',
+                expected: 6,
+                found: usize::MAX
+            })
+        );
+        assert_eq!(
+            Board::from_notation(&STARTING_POSITION.replacen("RP4pr", "RX4pr", 1)),
+            Err(NotationError::InvalidPiece {
+                rank: 'c',
+                character: 'X'
+            })
+        );
+        assert_eq!(
+            Board::from_notation(&STARTING_POSITION.replace(" w ", " x ")),
+            Err(NotationError::InvalidSideToMove("x".to_string()))
+        );
+        assert_eq!(
+            Board::from_notation(&STARTING_POSITION.replace(" - ", " a7 ")),
+            Err(NotationError::InvalidEnPassant("a7".to_string()))
+        );
+        assert_eq!(
+            Board::from_notation(&format!("{STARTING_POSITION} Chess960")),
+            Err(NotationError::InvalidVariant("Chess960".to_string()))
+        );
+        assert_eq!(
+            Board::from_notation(&STARTING_POSITION.replace(" 0 1", " x 1")),
+            Err(NotationError::InvalidHalfmoveClock("x".to_string()))
+        );
+        assert_eq!(
+            Board::from_notation(&STARTING_POSITION.replace(" 0 1", " 0 0")),
+            Err(NotationError::InvalidFullmoveNumber("0".to_string()))
+        );
+        assert_eq!(
+            Board::from_notation(&STARTING_POSITION.replacen("K2P", "3P", 1)),
+            Err(NotationError::InvalidPosition(vec![PositionProblem::MissingKing(Color::White)]))
+        );
+        assert_eq!(
+            NotationError::WrongRankLength {
+                rank: 'b',
+                expected: 7,
+                found: 8
+            }
+            .to_string(),
+            "rank b has 7 hexagons, but 8 were given"
+        );
+    }
+}
@@ -0,0 +1,444 @@
+//! Perft walks the tree of legal moves to a fixed depth and counts the
+//! positions at the bottom. Comparing those counts against known values is
+//! the standard way of checking a move generator: a single missing or
+//! extra move anywhere in the tree changes the total.
+
+use crate::board_representations::BitBoard;
+use crate::{apply_move, revert_move, Board, Move};
+
+/// Count the positions reachable from `board` in exactly `depth` moves.
+pub fn perft(board: &mut Board, depth: u8) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let moves = board.legal_moves();
+    if depth == 1 {
+        // no need to play the last layer of moves out
+        return moves.len() as u64;
+    }
+
+    let mut nodes = 0;
+    for movement in moves {
+        let undo = apply_move(board, movement);
+        nodes += perft(board, depth - 1);
+        revert_move(board, movement, undo);
+    }
+    nodes
+}
+
+/// Perft on a [`BitBoard`]. It should always agree with [`perft`].
+pub fn perft_bitboard(board: &BitBoard, depth: u8) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let moves = board.legal_moves();
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+
+    let mut nodes = 0;
+    for movement in moves {
+        let mut child = *board;
+        child.apply_move(movement);
+        nodes += perft_bitboard(&child, depth - 1);
+    }
+    nodes
+}
+
+/// Perft, broken down by the first move. When a count disagrees with a
+/// reference, dividing again from the position after the offending move
+/// narrows the disagreement down to a single position.
+pub fn perft_divide(board: &mut Board, depth: u8) -> Vec<(Move, u64)> {
+    let mut divided = Vec::<(Move, u64)>::new();
+    if depth == 0 {
+        return divided;
+    }
+
+    let mut moves = board.legal_moves();
+    moves.sort();
+    for movement in moves {
+        let undo = apply_move(board, movement);
+        divided.push((movement, perft(board, depth - 1)));
+        revert_move(board, movement, undo);
+    }
+    divided
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Color, Hexagon, Piece, PieceType};
+
+    /// Build a board from space separated pieces, e.g. "Kg1 Pf5"
+    fn setup_board(white: &str, black: &str, current_player: Color, en_passant: Option<&str>) -> Board {
+        let mut board = Board::new();
+        for (pieces, color) in [(white, Color::White), (black, Color::Black)] {
+            for piece in pieces.split_whitespace() {
+                let piece_type = match &piece[..1] {
+                    "K" => PieceType::King,
+                    "Q" => PieceType::Queen,
+                    "R" => PieceType::Rook,
+                    "B" => PieceType::Bishop,
+                    "N" => PieceType::Knight,
+                    "P" => PieceType::Pawn,
+                    _ => panic!("unknown piece {piece}"),
+                };
+                board
+                    .occupied_squares
+                    .insert(Hexagon::new(&piece[1..]).unwrap(), Piece { piece_type, color });
+            }
+        }
+        board.current_player = current_player;
+        board.en_passant = en_passant.map(|hex| Hexagon::new(hex).unwrap());
+        board.refresh_hash();
+        board
+    }
+
+    /// A second move generator for Gliński's rules, written straight from
+    /// the rules in cube coordinates, to check the expected counts against.
+    /// It shares no code with the crate's generators - not the geometry
+    /// tables, the variant rules or apply_move - and is slow but simple:
+    /// every move is played on a copy, and then every enemy piece is asked
+    /// whether it attacks the king.
+    mod reference {
+        use std::collections::HashMap;
+
+        use crate::{Board, Color, PieceType};
+
+        /// Axial (q, r) coordinates, with f6 at the origin and white's pawns
+        /// moving towards -r
+        type Cell = (i8, i8);
+
+        const ORTHOGONAL: [Cell; 6] = [(0, -1), (0, 1), (1, -1), (-1, 1), (1, 0), (-1, 0)];
+        const DIAGONAL: [Cell; 6] = [(2, -1), (-2, 1), (1, 1), (-1, -1), (1, -2), (-1, 2)];
+        const KNIGHT: [Cell; 12] = [
+            (1, 2), (1, -3), (2, 1), (2, -3), (-3, 1), (-3, 2),
+            (-1, -2), (-1, 3), (-2, -1), (-2, 3), (3, -1), (3, -2),
+        ];
+        const PROMOTIONS: [PieceType; 4] = [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight];
+
+        #[derive(Clone)]
+        struct Position {
+            pieces: HashMap<Cell, (Color, PieceType)>,
+            to_move: Color,
+            /// The hexagon a pawn just skipped, and the pawn
+            en_passant: Option<(Cell, Cell)>,
+        }
+
+        fn on_board((q, r): Cell) -> bool {
+            q.abs() <= 5 && r.abs() <= 5 && (q + r).abs() <= 5
+        }
+
+        fn step((q, r): Cell, (dq, dr): Cell) -> Cell {
+            (q + dq, r + dr)
+        }
+
+        fn forward(color: Color) -> Cell {
+            match color {
+                Color::White => (0, -1),
+                Color::Black => (0, 1),
+            }
+        }
+
+        fn pawn_captures(color: Color) -> [Cell; 2] {
+            match color {
+                Color::White => [(1, -1), (-1, 0)],
+                Color::Black => [(-1, 1), (1, 0)],
+            }
+        }
+
+        /// Pawns double step from where they start: b1 to f5 to k1 for
+        /// white, and the seventh rank for black
+        fn pawn_start(color: Color, (q, r): Cell) -> bool {
+            match color {
+                Color::White => q.abs() <= 4 && r == 1 - q.min(0),
+                Color::Black => q.abs() <= 4 && r == -1 - q.max(0),
+            }
+        }
+
+        fn from_board(board: &Board) -> Position {
+            let cell = |hex: &crate::Hexagon| {
+                let q = hex.rank as i8 - 5;
+                let bottom = if q <= 0 { 5 } else { 5 - q };
+                (q, bottom - hex.file as i8)
+            };
+            let pieces = board
+                .occupied_squares
+                .iter()
+                .map(|(hex, piece)| (cell(hex), (piece.color, piece.piece_type)))
+                .collect::<HashMap<_, _>>();
+            let en_passant = board.en_passant.map(|hex| {
+                let pawn = cell(&hex);
+                let (color, _) = pieces[&pawn];
+                let (dq, dr) = forward(color);
+                ((pawn.0 - dq, pawn.1 - dr), pawn)
+            });
+            Position {
+                pieces,
+                to_move: board.current_player,
+                en_passant,
+            }
+        }
+
+        /// Whether any of `attacker`'s pieces attacks `target`
+        fn attacked(position: &Position, target: Cell, attacker: Color) -> bool {
+            position.pieces.iter().any(|(&from, &(color, piece_type))| {
+                if color != attacker {
+                    return false;
+                }
+                let slides = |directions: &[Cell], max: usize| {
+                    directions.iter().any(|&direction| {
+                        let mut cell = from;
+                        for _ in 0..max {
+                            cell = step(cell, direction);
+                            if cell == target {
+                                return true;
+                            }
+                            if !on_board(cell) || position.pieces.contains_key(&cell) {
+                                return false;
+                            }
+                        }
+                        false
+                    })
+                };
+                match piece_type {
+                    PieceType::Rook => slides(&ORTHOGONAL, 10),
+                    PieceType::Bishop => slides(&DIAGONAL, 10),
+                    PieceType::Queen => slides(&ORTHOGONAL, 10) || slides(&DIAGONAL, 10),
+                    PieceType::King => slides(&ORTHOGONAL, 1) || slides(&DIAGONAL, 1),
+                    PieceType::Knight => KNIGHT.iter().any(|&jump| step(from, jump) == target),
+                    PieceType::Pawn => pawn_captures(color).iter().any(|&capture| step(from, capture) == target),
+                }
+            })
+        }
+
+        /// Every position reachable in one legal move
+        fn children(position: &Position) -> Vec<Position> {
+            let mover = position.to_move;
+            let mut children = Vec::new();
+            let mut add = |from: Cell, to: Cell, piece_type: PieceType, taken: Option<Cell>, skipped: Option<Cell>| {
+                let mut child = position.clone();
+                child.pieces.remove(&from);
+                if let Some(taken) = taken {
+                    child.pieces.remove(&taken);
+                }
+                child.pieces.insert(to, (mover, piece_type));
+                child.to_move = mover.invert();
+                child.en_passant = skipped.map(|skipped| (skipped, to));
+                let king = child
+                    .pieces
+                    .iter()
+                    .find(|(_, &piece)| piece == (mover, PieceType::King))
+                    .map(|(&cell, _)| cell);
+                if !king.is_some_and(|king| attacked(&child, king, mover.invert())) {
+                    children.push(child);
+                }
+            };
+
+            for (&from, &(color, piece_type)) in &position.pieces {
+                if color != mover {
+                    continue;
+                }
+                let mut slide = |directions: &[Cell], max: usize| {
+                    for &direction in directions {
+                        let mut cell = from;
+                        for _ in 0..max {
+                            cell = step(cell, direction);
+                            if !on_board(cell) {
+                                break;
+                            }
+                            match position.pieces.get(&cell) {
+                                Some(&(other, _)) if other == mover => break,
+                                Some(_) => {
+                                    add(from, cell, piece_type, Some(cell), None);
+                                    break;
+                                }
+                                None => add(from, cell, piece_type, None, None),
+                            }
+                        }
+                    }
+                };
+                match piece_type {
+                    PieceType::Rook => slide(&ORTHOGONAL, 10),
+                    PieceType::Bishop => slide(&DIAGONAL, 10),
+                    PieceType::Queen => {
+                        slide(&ORTHOGONAL, 10);
+                        slide(&DIAGONAL, 10);
+                    }
+                    PieceType::King => {
+                        slide(&ORTHOGONAL, 1);
+                        slide(&DIAGONAL, 1);
+                    }
+                    PieceType::Knight => {
+                        for &jump in &KNIGHT {
+                            let to = step(from, jump);
+                            match position.pieces.get(&to) {
+                                _ if !on_board(to) => {}
+                                Some(&(other, _)) if other == mover => {}
+                                Some(_) => add(from, to, piece_type, Some(to), None),
+                                None => add(from, to, piece_type, None, None),
+                            }
+                        }
+                    }
+                    PieceType::Pawn => {
+                        let promotes = |to: Cell| !on_board(step(to, forward(mover)));
+                        let mut pawn_to = |to: Cell, taken: Option<Cell>, skipped: Option<Cell>| {
+                            if promotes(to) {
+                                for promotion in PROMOTIONS {
+                                    add(from, to, promotion, taken, None);
+                                }
+                            } else {
+                                add(from, to, PieceType::Pawn, taken, skipped);
+                            }
+                        };
+                        let single = step(from, forward(mover));
+                        if on_board(single) && !position.pieces.contains_key(&single) {
+                            pawn_to(single, None, None);
+                            let double = step(single, forward(mover));
+                            if pawn_start(mover, from) && on_board(double) && !position.pieces.contains_key(&double) {
+                                pawn_to(double, None, Some(single));
+                            }
+                        }
+                        for capture in pawn_captures(mover) {
+                            let to = step(from, capture);
+                            match position.pieces.get(&to) {
+                                Some(&(other, _)) if other != mover => pawn_to(to, Some(to), None),
+                                Some(_) => {}
+                                None => {
+                                    if let Some((skipped, pawn)) = position.en_passant {
+                                        if skipped == to {
+                                            pawn_to(to, Some(pawn), None);
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            children
+        }
+
+        fn count(position: &Position, depth: u8) -> u64 {
+            if depth == 0 {
+                return 1;
+            }
+            children(position).iter().map(|child| count(child, depth - 1)).sum()
+        }
+
+        pub fn perft(board: &Board, depth: u8) -> u64 {
+            count(&from_board(board), depth)
+        }
+    }
+
+    /// Check the counts from each depth, starting at one. The expected
+    /// counts come from the reference generator, so each is checked against
+    /// it as well as against the crate's two generators.
+    fn assert_perft(board: &mut Board, expected: &[u64]) {
+        let original = board.clone();
+        let bitboard = BitBoard::from_board(board);
+        for (depth, nodes) in expected.iter().enumerate() {
+            assert_eq!(reference::perft(board, depth as u8 + 1), *nodes, "reference depth {}", depth + 1);
+            assert_eq!(perft(board, depth as u8 + 1), *nodes, "depth {}", depth + 1);
+            assert_eq!(perft_bitboard(&bitboard, depth as u8 + 1), *nodes, "bitboard depth {}", depth + 1);
+        }
+        // perft must leave the board the way it found it
+        assert_eq!(board, &original);
+    }
+
+    /// Check both boards generate exactly the same moves, all the way down
+    /// the tree, and keep the same hash as they go
+    fn assert_same_moves(board: &mut Board, depth: u8) {
+        if depth == 0 {
+            return;
+        }
+        let mut moves = board.legal_moves();
+        let bitboard = BitBoard::from_board(board);
+        let mut bitboard_moves = bitboard.legal_moves();
+        moves.sort();
+        bitboard_moves.sort();
+        assert_eq!(moves, bitboard_moves, "{}", board.to_notation());
+
+        for movement in moves {
+            let hash = board.hash();
+            let undo = apply_move(board, movement);
+            let mut child = bitboard;
+            child.apply_move(movement);
+            assert_eq!(child.hash(), board.hash(), "{} after {:?}", board.to_notation(), movement);
+            let mut refreshed = board.clone();
+            refreshed.refresh_hash();
+            assert_eq!(refreshed.hash(), board.hash(), "{} after {:?}", board.to_notation(), movement);
+
+            assert_same_moves(board, depth - 1);
+            revert_move(board, movement, undo);
+            assert_eq!(board.hash(), hash);
+        }
+    }
+
+    #[test]
+    fn test_perft_default_board() {
+        assert_perft(&mut Board::setup_default_board(), &[51, 2586]);
+    }
+
+    #[test]
+    #[ignore = "slow - run with --release -- --ignored"]
+    fn test_perft_default_board_deep() {
+        assert_perft(&mut Board::setup_default_board(), &[51, 2586, 137858, 7282418]);
+    }
+
+    #[test]
+    fn test_bitboard_matches_board_move_for_move() {
+        assert_same_moves(&mut Board::setup_default_board(), 2);
+        for (white, black, color, en_passant) in [
+            ("Kd3 Ph5 Pf6 Nc1 Be1", "Kg10 Pg5 Rk5 Pb7 Bf9", Color::White, Some("G5")),
+            ("Kf5 Ph5 Pf6 Ra1", "Kf10 Pg5 Ph7 Nd9", Color::White, Some("G5")),
+            ("Ka1 Pf10 Pc7 Nc1 Ri8", "Kl6 Re10 Pd2 Pe2 Bg9", Color::White, None),
+            ("Kf1 Rf3 Bg2 Ne1 Pg1 Pb1", "Kl6 Rf8 Qh3 Rc1 Rh1 Pk7", Color::White, None),
+            ("Kb1 Rf2 Nf5 Bc4 Pe4", "Kf10 Ra6 Pe7 Pg7 Nh9 Qk7", Color::White, None),
+        ] {
+            assert_same_moves(&mut setup_board(white, black, color, en_passant), 3);
+        }
+    }
+
+    #[test]
+    fn test_perft_divide_sums_to_perft() {
+        let mut board = Board::setup_default_board();
+        let divided = perft_divide(&mut board, 2);
+        assert_eq!(divided.len(), 51);
+        assert_eq!(divided.iter().map(|(_, nodes)| nodes).sum::<u64>(), 2586);
+    }
+
+    #[test]
+    fn test_perft_en_passant() {
+        // h5xg6 would expose the king on d3 to the rook on k5, f6xg6 is fine
+        let mut board = setup_board("Kd3 Ph5 Pf6 Nc1 Be1", "Kg10 Pg5 Rk5 Pb7 Bf9", Color::White, Some("G5"));
+        assert_perft(&mut board, &[26, 824, 20642]);
+    }
+
+    #[test]
+    fn test_perft_en_passant_check_evasion() {
+        // the double jumped pawn gives check, and taking it en passant is one way out
+        let mut board = setup_board("Kf5 Ph5 Pf6 Ra1", "Kf10 Pg5 Ph7 Nd9", Color::White, Some("G5"));
+        assert_perft(&mut board, &[12, 201, 4893]);
+    }
+
+    #[test]
+    fn test_perft_promotion() {
+        let mut board = setup_board("Ka1 Pf10 Pc7 Nc1 Ri8", "Kl6 Re10 Pd2 Pe2 Bg9", Color::White, None);
+        assert_perft(&mut board, &[42, 711, 24418]);
+    }
+
+    #[test]
+    fn test_perft_pins() {
+        // every white piece but the b1 pawn is pinned against the king
+        let mut board = setup_board("Kf1 Rf3 Bg2 Ne1 Pg1 Pb1", "Kl6 Rf8 Qh3 Rc1 Rh1 Pk7", Color::White, None);
+        assert_perft(&mut board, &[12, 540, 9434]);
+    }
+
+    #[test]
+    fn test_perft_discovered_check() {
+        // moving the knight off f5 uncovers the rook on f2
+        let mut board = setup_board("Kb1 Rf2 Nf5 Bc4 Pe4", "Kf10 Ra6 Pe7 Pg7 Nh9 Qk7", Color::White, None);
+        assert_perft(&mut board, &[47, 831, 35268]);
+    }
+}
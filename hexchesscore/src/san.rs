@@ -0,0 +1,408 @@
+//! Standard algebraic notation (SAN) for moves, e.g. `Nc5`, `exf6`,
+//! `Rd3xd8+` or `f10=Q#`.
+//!
+//! A move is written as:
+//!
+//! - the piece letter (`K`, `Q`, `R`, `B` or `N`), left off for pawns
+//! - the start hexagon's rank, file, or both, if that's needed to tell two
+//!   identical pieces apart. Pawn captures always give the start rank.
+//! - `x` if the move captures
+//! - the final hexagon
+//! - `=` and the piece letter if a pawn promotes
+//! - `+` for check, or `#` for checkmate
+//!
+//! Moves depend on the position, so both writing and reading them need the
+//! board the move is played from.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::moves::get_rank_length;
+use crate::{
+    apply_move, check_for_mates, king_is_in_check, rank_char_to_int, rank_int_to_char,
+    Board, GameResult, Hexagon, Move, PieceType,
+};
+
+/// Why a move in algebraic notation couldn't be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SanError {
+    /// The text isn't shaped like a move at all
+    InvalidSyntax(String),
+    /// No legal move in the position matches the text
+    IllegalMove(String),
+    /// More than one legal move matches, starting from each of these hexagons
+    AmbiguousMove { san: String, starts: Vec<Hexagon> },
+    /// The move takes a pawn to its last hexagon without saying what it becomes
+    MissingPromotion(String),
+}
+
+impl fmt::Display for SanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SanError::InvalidSyntax(san) => write!(f, "'{san}' is not a move"),
+            SanError::IllegalMove(san) => write!(f, "'{san}' is not a legal move"),
+            SanError::AmbiguousMove { san, starts } => {
+                let starts: Vec<String> = starts.iter().map(|hex| hex.to_string()).collect();
+                write!(f, "'{san}' is ambiguous, it could start from {}", starts.join(" or "))
+            }
+            SanError::MissingPromotion(san) => {
+                write!(f, "'{san}' needs a piece to promote to, e.g. '{san}=Q'")
+            }
+        }
+    }
+}
+
+impl Error for SanError {}
+
+fn piece_letter(piece_type: PieceType) -> Option<char> {
+    match piece_type {
+        PieceType::King => Some('K'),
+        PieceType::Queen => Some('Q'),
+        PieceType::Rook => Some('R'),
+        PieceType::Bishop => Some('B'),
+        PieceType::Knight => Some('N'),
+        PieceType::Pawn => None,
+    }
+}
+
+fn letter_to_piece(letter: char) -> Option<PieceType> {
+    match letter {
+        'K' => Some(PieceType::King),
+        'Q' => Some(PieceType::Queen),
+        'R' => Some(PieceType::Rook),
+        'B' => Some(PieceType::Bishop),
+        'N' => Some(PieceType::Knight),
+        _ => None,
+    }
+}
+
+/// Write `movement`, which must be legal on `board`, in algebraic notation.
+pub fn to_san(board: &Board, movement: Move) -> String {
+    let piece = board
+        .occupied_squares
+        .get(&movement.start_hex)
+        .copied()
+        .expect("No piece on the move's start hexagon");
+    let is_pawn = piece.piece_type == PieceType::Pawn;
+    // pawns only change rank when they capture, including en passant
+    let is_capture = board.occupied_squares.contains_key(&movement.final_hex)
+        || (is_pawn && movement.start_hex.rank != movement.final_hex.rank);
+
+    let mut san = String::new();
+    if let Some(letter) = piece_letter(piece.piece_type) {
+        san.push(letter);
+
+        // find any other identical pieces that could also reach the final hexagon
+        let rivals: Vec<Hexagon> = board
+            .legal_moves()
+            .into_iter()
+            .filter(|other| {
+                other.final_hex == movement.final_hex
+                    && other.start_hex != movement.start_hex
+                    && board.occupied_squares.get(&other.start_hex) == Some(&piece)
+            })
+            .map(|other| other.start_hex)
+            .collect();
+
+        if !rivals.is_empty() {
+            let rank = rank_int_to_char(movement.start_hex.rank).unwrap();
+            let file = movement.start_hex.file + 1;
+            if rivals.iter().all(|hex| hex.rank != movement.start_hex.rank) {
+                san.push(rank);
+            } else if rivals.iter().all(|hex| hex.file != movement.start_hex.file) {
+                san.push_str(&file.to_string());
+            } else {
+                san.push_str(&movement.start_hex.to_string());
+            }
+        }
+    } else if is_capture {
+        san.push(rank_int_to_char(movement.start_hex.rank).unwrap());
+    }
+
+    if is_capture {
+        san.push('x');
+    }
+    san.push_str(&movement.final_hex.to_string());
+
+    if movement.final_piece != piece.piece_type {
+        san.push('=');
+        san.push(piece_letter(movement.final_piece).expect("Pawns can't promote to pawns"));
+    }
+
+    // play the move on a copy, to see whether it gives check or mate
+    let mut after = board.clone();
+    apply_move(&mut after, movement);
+    if king_is_in_check(after.current_player, &after) {
+        match check_for_mates(&after) {
+            Some(GameResult::Checkmate) => san.push('#'),
+            _ => san.push('+'),
+        }
+    }
+
+    san
+}
+
+/// Read a move in algebraic notation and find the legal move on `board` it
+/// refers to. Capture, check and mate markers are optional, and aren't
+/// checked against the move.
+pub fn parse_san(board: &Board, san: &str) -> Result<Move, SanError> {
+    let invalid = || SanError::InvalidSyntax(san.to_string());
+
+    let mut text = san.trim().trim_end_matches(['+', '#', '!', '?']);
+
+    // a promotion suffix like `=Q`
+    let mut promotion = None;
+    if let Some((rest, piece)) = text.split_once('=') {
+        let mut letters = piece.chars();
+        promotion = match (letters.next().and_then(letter_to_piece), letters.next()) {
+            (Some(PieceType::King), _) | (None, _) | (_, Some(_)) => return Err(invalid()),
+            (piece_type, None) => piece_type,
+        };
+        text = rest;
+    }
+
+    let mut chars = text.chars().peekable();
+    let piece_type = match chars.peek().copied().and_then(letter_to_piece) {
+        Some(piece_type) => {
+            chars.next();
+            piece_type
+        }
+        None => PieceType::Pawn,
+    };
+    let rest: String = chars.filter(|c| *c != 'x').collect();
+
+    // the final hexagon is the last rank letter and the digits after it
+    let final_start = rest.rfind(|c: char| c.is_ascii_lowercase()).ok_or_else(invalid)?;
+    let final_hex = parse_hexagon(&rest[final_start..]).ok_or_else(invalid)?;
+
+    // anything left over says which rank and/or file the piece starts from
+    let disambiguation = &rest[..final_start];
+    let (start_rank, start_file) = match disambiguation.find(|c: char| c.is_ascii_digit()) {
+        _ if disambiguation.is_empty() => (None, None),
+        Some(0) => (None, Some(parse_file(disambiguation).ok_or_else(invalid)?)),
+        Some(1) => {
+            let start = parse_hexagon(disambiguation).ok_or_else(invalid)?;
+            (Some(start.rank), Some(start.file))
+        }
+        None if disambiguation.len() == 1 => {
+            let rank = disambiguation.chars().next().and_then(rank_char_to_int);
+            (Some(rank.ok_or_else(invalid)?), None)
+        }
+        _ => return Err(invalid()),
+    };
+
+    let mut candidates: Vec<Move> = board
+        .legal_moves()
+        .into_iter()
+        .filter(|movement| {
+            movement.final_hex == final_hex
+                && board
+                    .occupied_squares
+                    .get(&movement.start_hex)
+                    .is_some_and(|piece| piece.piece_type == piece_type)
+                && start_rank.is_none_or(|rank| movement.start_hex.rank == rank)
+                && start_file.is_none_or(|file| movement.start_hex.file == file)
+        })
+        .collect();
+
+    let is_promotion = candidates
+        .iter()
+        .any(|movement| movement.final_piece != piece_type);
+    match (is_promotion, promotion) {
+        (true, None) => return Err(SanError::MissingPromotion(san.to_string())),
+        (true, Some(promotion)) => candidates.retain(|movement| movement.final_piece == promotion),
+        (false, Some(_)) => return Err(SanError::IllegalMove(san.to_string())),
+        (false, None) => {}
+    }
+
+    match candidates[..] {
+        [movement] => Ok(movement),
+        [] => Err(SanError::IllegalMove(san.to_string())),
+        _ => {
+            let mut starts: Vec<Hexagon> =
+                candidates.iter().map(|movement| movement.start_hex).collect();
+            starts.sort();
+            Err(SanError::AmbiguousMove {
+                san: san.to_string(),
+                starts,
+            })
+        }
+    }
+}
+
+/// A file number, written from 1 like in a hexagon's name
+fn parse_file(file: &str) -> Option<u8> {
+    match file.parse::<u8>() {
+        Ok(file @ 1..=11) => Some(file - 1),
+        _ => None,
+    }
+}
+
+/// A hexagon name, e.g. `f10`, that is actually on the board
+fn parse_hexagon(name: &str) -> Option<Hexagon> {
+    let mut chars = name.chars();
+    let rank = chars.next().and_then(rank_char_to_int)?;
+    let file = parse_file(chars.as_str())?;
+    if file < get_rank_length(rank)? {
+        Some(Hexagon { rank, file })
+    } else {
+        None
+    }
+}
+
+impl Board {
+    /// Write a legal move in algebraic notation. See [`crate::san`].
+    pub fn move_to_san(&self, movement: Move) -> String {
+        to_san(self, movement)
+    }
+
+    /// Find the legal move written in algebraic notation. See [`crate::san`].
+    pub fn parse_san(&self, san: &str) -> Result<Move, SanError> {
+        parse_san(self, san)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Color, Piece};
+
+    fn piece(piece_type: PieceType, color: Color) -> Piece {
+        Piece { piece_type, color }
+    }
+
+    fn hex(name: &str) -> Hexagon {
+        Hexagon::new(name).unwrap()
+    }
+
+    fn movement(start: &str, end: &str, final_piece: PieceType) -> Move {
+        Move {
+            start_hex: hex(start),
+            final_hex: hex(end),
+            final_piece,
+        }
+    }
+
+    #[test]
+    fn test_san_round_trips_every_legal_move() {
+        let board = Board::setup_default_board();
+        let moves = board.legal_moves();
+        let mut written: Vec<String> = Vec::new();
+        for legal_move in moves {
+            let san = to_san(&board, legal_move);
+            assert_eq!(parse_san(&board, &san), Ok(legal_move), "{san}");
+            written.push(san);
+        }
+        // every move has its own name
+        written.sort();
+        written.dedup();
+        assert_eq!(written.len(), 51);
+    }
+
+    #[test]
+    fn test_san_pawn_moves() {
+        let board = Board::setup_default_board();
+        assert_eq!(to_san(&board, movement("E4", "E6", PieceType::Pawn)), "e6");
+        assert_eq!(parse_san(&board, "e6"), Ok(movement("E4", "E6", PieceType::Pawn)));
+
+        // en passant
+        let mut board = Board::new();
+        board.occupied_squares.insert(hex("F1"), piece(PieceType::King, Color::White));
+        board.occupied_squares.insert(hex("F11"), piece(PieceType::King, Color::Black));
+        board.occupied_squares.insert(hex("F6"), piece(PieceType::Pawn, Color::White));
+        board.occupied_squares.insert(hex("G5"), piece(PieceType::Pawn, Color::Black));
+        board.en_passant = Some(hex("G5"));
+        let en_passant = movement("F6", "G6", PieceType::Pawn);
+        assert_eq!(to_san(&board, en_passant), "fxg6");
+        assert_eq!(parse_san(&board, "fxg6"), Ok(en_passant));
+        assert_eq!(parse_san(&board, "fg6"), Ok(en_passant));
+    }
+
+    #[test]
+    fn test_san_promotion() {
+        let mut board = Board::new();
+        board.occupied_squares.insert(hex("A1"), piece(PieceType::King, Color::White));
+        board.occupied_squares.insert(hex("L6"), piece(PieceType::King, Color::Black));
+        board.occupied_squares.insert(hex("F10"), piece(PieceType::Pawn, Color::White));
+        board.occupied_squares.insert(hex("E10"), piece(PieceType::Rook, Color::Black));
+
+        let promotion = movement("F10", "F11", PieceType::Queen);
+        assert_eq!(to_san(&board, promotion), "f11=Q+");
+        assert_eq!(parse_san(&board, "f11=Q"), Ok(promotion));
+        assert_eq!(
+            to_san(&board, movement("F10", "E10", PieceType::Knight)),
+            "fxe10=N"
+        );
+        assert_eq!(
+            parse_san(&board, "f11"),
+            Err(SanError::MissingPromotion("f11".to_string()))
+        );
+        assert_eq!(
+            parse_san(&board, "f11=K"),
+            Err(SanError::InvalidSyntax("f11=K".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_san_disambiguation() {
+        let mut board = Board::new();
+        board.occupied_squares.insert(hex("A1"), piece(PieceType::King, Color::White));
+        board.occupied_squares.insert(hex("L6"), piece(PieceType::King, Color::Black));
+        // both rooks can reach f5 along the rank
+        board.occupied_squares.insert(hex("F2"), piece(PieceType::Rook, Color::White));
+        board.occupied_squares.insert(hex("F9"), piece(PieceType::Rook, Color::White));
+        // and this one can reach it too, from the same file as f2
+        board.occupied_squares.insert(hex("C2"), piece(PieceType::Rook, Color::White));
+
+        let from_f2 = movement("F2", "F5", PieceType::Rook);
+        let from_f9 = movement("F9", "F5", PieceType::Rook);
+        let from_c2 = movement("C2", "F5", PieceType::Rook);
+        assert_eq!(to_san(&board, from_c2), "Rcf5");
+        assert_eq!(to_san(&board, from_f9), "R9f5");
+        assert_eq!(to_san(&board, from_f2), "Rf2f5");
+        assert_eq!(parse_san(&board, "Rcf5"), Ok(from_c2));
+        assert_eq!(parse_san(&board, "R9f5"), Ok(from_f9));
+        assert_eq!(parse_san(&board, "Rf2f5"), Ok(from_f2));
+
+        let error = parse_san(&board, "Rff5").unwrap_err();
+        assert!(matches!(error, SanError::AmbiguousMove { .. }), "{error:?}");
+        assert_eq!(error.to_string(), "'Rff5' is ambiguous, it could start from f2 or f9");
+    }
+
+    #[test]
+    fn test_san_checks_and_mates() {
+        let mut board = Board::new();
+        board.occupied_squares.insert(hex("A4"), piece(PieceType::King, Color::White));
+        board.occupied_squares.insert(hex("C4"), piece(PieceType::King, Color::Black));
+        board.occupied_squares.insert(hex("C8"), piece(PieceType::Rook, Color::Black));
+        board.current_player = Color::Black;
+
+        let mate = movement("C8", "A6", PieceType::Rook);
+        assert_eq!(to_san(&board, mate), "Ra6#");
+        assert_eq!(parse_san(&board, "Ra6#"), Ok(mate));
+        assert_eq!(parse_san(&board, "Ra6"), Ok(mate));
+        // writing a move mustn't change the board
+        assert_eq!(board.current_player, Color::Black);
+        assert_eq!(board.occupied_squares.len(), 3);
+    }
+
+    #[test]
+    fn test_san_errors() {
+        let board = Board::setup_default_board();
+        for bad in ["", "Z", "Nz3", "e", "Qe99", "Qa0a1"] {
+            assert_eq!(
+                parse_san(&board, bad),
+                Err(SanError::InvalidSyntax(bad.to_string())),
+                "{bad}"
+            );
+        }
+        // a8 isn't on the board, since the a rank only has six hexagons
+        assert_eq!(parse_san(&board, "a8"), Err(SanError::InvalidSyntax("a8".to_string())));
+        assert_eq!(parse_san(&board, "f8"), Err(SanError::IllegalMove("f8".to_string())));
+        assert_eq!(parse_san(&board, "e5=Q"), Err(SanError::IllegalMove("e5=Q".to_string())));
+        assert_eq!(
+            SanError::IllegalMove("f8".to_string()).to_string(),
+            "'f8' is not a legal move"
+        );
+    }
+}
@@ -0,0 +1,194 @@
+//! Checking that a board describes a position that could come up in a game.
+//!
+//! A [`Board`] can be read from JSON or notation sent by anyone, and the
+//! move generator assumes it's been given something sensible - one king
+//! each, pieces on the board, and so on. [`Board::validate`] lists
+//! everything wrong with a position, so boards from outside can be turned
+//! away before anything tries to play from them.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::geometry;
+use crate::{king_is_in_check, Board, Color, Hexagon, Piece, PieceType};
+
+/// Something that makes a position impossible to play from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PositionProblem {
+    /// A piece is on a hexagon that isn't part of the variant's board
+    OffBoard(Hexagon),
+    MissingKing(Color),
+    /// A player has more than one king
+    ExtraKing(Color),
+    /// A pawn is on a hexagon it would have promoted on
+    PawnOnPromotionHexagon(Hexagon),
+    /// The pawn marked as open to en passant can't have just double jumped
+    ImpossibleEnPassant(Hexagon),
+    /// The player who just moved has left their king in check
+    OpponentInCheck(Color),
+}
+
+impl fmt::Display for PositionProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PositionProblem::OffBoard(hex) => write!(f, "{hex} is not on the board"),
+            PositionProblem::MissingKing(color) => write!(f, "{color:?} has no king"),
+            PositionProblem::ExtraKing(color) => write!(f, "{color:?} has more than one king"),
+            PositionProblem::PawnOnPromotionHexagon(hex) => {
+                write!(f, "the pawn on {hex} should already have promoted")
+            }
+            PositionProblem::ImpossibleEnPassant(hex) => {
+                write!(f, "the pawn on {hex} can't have just double jumped")
+            }
+            PositionProblem::OpponentInCheck(color) => {
+                write!(f, "{color:?} is in check, but it's not their move")
+            }
+        }
+    }
+}
+
+impl Error for PositionProblem {}
+
+impl Board {
+    /// Check the position could come up in a game, listing every problem
+    /// found if it couldn't.
+    pub fn validate(&self) -> Result<(), Vec<PositionProblem>> {
+        let rules = self.variant.rules();
+        let mut problems = Vec::new();
+
+        let mut pieces: Vec<(&Hexagon, &Piece)> = self.occupied_squares.iter().collect();
+        pieces.sort_by_key(|(hex, _)| **hex);
+        for (hex, piece) in &pieces {
+            let on_board = geometry::is_on_board(hex) && rules.is_on_board(hex);
+            if !on_board {
+                problems.push(PositionProblem::OffBoard(**hex));
+            } else if piece.piece_type == PieceType::Pawn && rules.is_promotion_hex(hex, piece.color) {
+                problems.push(PositionProblem::PawnOnPromotionHexagon(**hex));
+            }
+        }
+        // the rest of the checks look pieces up in the move tables, which
+        // only cover real hexagons
+        if !problems.is_empty() {
+            return Err(problems);
+        }
+
+        for color in [Color::White, Color::Black] {
+            let king = Piece { piece_type: PieceType::King, color };
+            match pieces.iter().filter(|(_, piece)| **piece == king).count() {
+                0 => problems.push(PositionProblem::MissingKing(color)),
+                1 => {}
+                _ => problems.push(PositionProblem::ExtraKing(color)),
+            }
+        }
+
+        if let Some(pawn) = self.en_passant {
+            if !self.en_passant_is_possible(&pawn) {
+                problems.push(PositionProblem::ImpossibleEnPassant(pawn));
+            }
+        }
+
+        let opponent = self.current_player.invert();
+        if king_is_in_check(opponent, self) {
+            problems.push(PositionProblem::OpponentInCheck(opponent));
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Whether the opponent's pawn on `pawn` can have double jumped there
+    /// with the move just played - leaving the two hexagons behind it empty.
+    fn en_passant_is_possible(&self, pawn: &Hexagon) -> bool {
+        let mover = self.current_player.invert();
+        if self.occupied_squares.get(pawn) != Some(&Piece { piece_type: PieceType::Pawn, color: mover }) {
+            return false;
+        }
+        // stepping forward for the side to move is stepping back for the pawn
+        let Some(jumped) = geometry::pawn_push(pawn, self.current_player) else {
+            return false;
+        };
+        let Some(start) = geometry::pawn_push(&jumped, self.current_player) else {
+            return false;
+        };
+        !self.occupied_squares.contains_key(&jumped)
+            && !self.occupied_squares.contains_key(&start)
+            && self.variant.rules().can_double_step(&start, mover)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variants::VariantKind;
+
+    fn hex(name: &str) -> Hexagon {
+        Hexagon::new(name).unwrap()
+    }
+
+    fn piece(piece_type: PieceType, color: Color) -> Piece {
+        Piece { piece_type, color }
+    }
+
+    #[test]
+    fn test_starting_positions_are_valid() {
+        for variant in VariantKind::ALL {
+            assert_eq!(Board::setup_variant(variant).validate(), Ok(()), "{variant}");
+        }
+        let mut board = Board::setup_default_board();
+        let double_jump = board
+            .legal_moves_from(&hex("E4"))
+            .into_iter()
+            .find(|movement| movement.final_hex == hex("E6"))
+            .unwrap();
+        board.play(double_jump).unwrap();
+        assert_eq!(board.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_lists_every_problem() {
+        let mut board = Board::new();
+        board.occupied_squares.insert(hex("F1"), piece(PieceType::King, Color::White));
+        board.occupied_squares.insert(hex("F2"), piece(PieceType::King, Color::White));
+        board.occupied_squares.insert(hex("E4"), piece(PieceType::Pawn, Color::Black));
+        board.en_passant = Some(hex("E4"));
+        assert_eq!(
+            board.validate(),
+            Err(vec![
+                PositionProblem::ExtraKing(Color::White),
+                PositionProblem::MissingKing(Color::Black),
+                PositionProblem::ImpossibleEnPassant(hex("E4")),
+            ])
+        );
+
+        // it's white's move, so black can't have left their king in check
+        board.occupied_squares.remove(&hex("F2"));
+        board.occupied_squares.insert(hex("F8"), piece(PieceType::King, Color::Black));
+        board.occupied_squares.insert(hex("F4"), piece(PieceType::Rook, Color::White));
+        board.en_passant = None;
+        assert_eq!(board.validate(), Err(vec![PositionProblem::OpponentInCheck(Color::Black)]));
+    }
+
+    #[test]
+    fn test_validate_rejects_pieces_that_cant_be_there() {
+        let mut board = Board::setup_default_board();
+        board.occupied_squares.insert(hex("F11"), piece(PieceType::Pawn, Color::White));
+        board.occupied_squares.insert(Hexagon { rank: 0, file: 9 }, piece(PieceType::Knight, Color::Black));
+        let problems = board.validate().unwrap_err();
+        assert_eq!(
+            problems,
+            vec![
+                PositionProblem::OffBoard(Hexagon { rank: 0, file: 9 }),
+                PositionProblem::PawnOnPromotionHexagon(hex("F11")),
+            ]
+        );
+        assert_eq!(problems[1].to_string(), "the pawn on f11 should already have promoted");
+
+        // the corner hexagons aren't part of Shafran's board
+        let mut board = Board::setup_variant(VariantKind::Shafran);
+        board.occupied_squares.insert(hex("A1"), piece(PieceType::Rook, Color::White));
+        assert_eq!(board.validate(), Err(vec![PositionProblem::OffBoard(hex("A1"))]));
+    }
+}
@@ -0,0 +1,301 @@
+//! The rules that differ between the hexagonal chess variants.
+//!
+//! Every variant is played on (part of) Gliński's 91-hexagon board and uses
+//! its names for the hexagons. A variant decides which of those hexagons
+//! are in play, where the pieces start, which way pawns capture, which
+//! pawns may double step and where they promote. Pieces move the same way
+//! in every variant, and pawns always move along their file - up it for
+//! white and down it for black.
+//!
+//! A [`Board`](crate::Board) carries a [`VariantKind`], which is what gets
+//! sent over the wire, and looks its rules up with [`VariantKind::rules`].
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::geometry;
+use crate::moves::get_rank_length;
+use crate::{Color, Hexagon, Piece, PieceType};
+
+/// The rules a variant can change.
+///
+/// Only which way pawns capture is up to the variant. The direction they
+/// push in is fixed: one hexagon along their file ([`geometry::pawn_push`]),
+/// or two from where they start. [`crate::apply_move`] and
+/// [`crate::convert_en_passant_to_virtual_pawn`] rely on that too, so they
+/// treat a pawn that moved two hexagons along its file as the en passant
+/// target and look for it one hexagon behind where the capturing pawn
+/// lands. A variant whose pawns push another way would need a push
+/// direction here, and those functions to use it.
+pub trait Variant: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Where every piece starts
+    fn starting_position(&self) -> &'static HashMap<Hexagon, Piece>;
+
+    /// Whether a hexagon is part of this variant's board. The board has to
+    /// be convex - a line of hexagons that leaves it never comes back on.
+    fn is_on_board(&self, _hexagon: &Hexagon) -> bool {
+        true
+    }
+
+    /// The axial (q, r) steps a pawn captures along
+    fn pawn_captures(&self, color: Color) -> [(i8, i8); 2] {
+        geometry::PAWN_CAPTURE_DIRECTIONS[geometry::color_index(color)]
+    }
+
+    /// Whether a pawn on this hexagon may move two hexagons at once
+    fn can_double_step(&self, hexagon: &Hexagon, color: Color) -> bool {
+        self.starting_position().get(hexagon)
+            == Some(&Piece {
+                piece_type: PieceType::Pawn,
+                color,
+            })
+    }
+
+    /// Whether a pawn reaching this hexagon promotes. By default, that's
+    /// the last hexagon of each file on the board.
+    fn is_promotion_hex(&self, hexagon: &Hexagon, color: Color) -> bool {
+        !geometry::pawn_push(hexagon, color).is_some_and(|next| self.is_on_board(&next))
+    }
+}
+
+/// The variants that come with the crate.
+#[derive(Debug, Serialize, Deserialize, Default, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum VariantKind {
+    #[default]
+    Glinski,
+    McCooey,
+    Shafran,
+}
+
+impl VariantKind {
+    pub const ALL: [VariantKind; 3] = [VariantKind::Glinski, VariantKind::McCooey, VariantKind::Shafran];
+
+    pub fn rules(&self) -> &'static dyn Variant {
+        match self {
+            VariantKind::Glinski => &Glinski,
+            VariantKind::McCooey => &McCooey,
+            VariantKind::Shafran => &Shafran,
+        }
+    }
+}
+
+impl fmt::Display for VariantKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.rules().name())
+    }
+}
+
+impl FromStr for VariantKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        VariantKind::ALL
+            .into_iter()
+            .find(|variant| variant.rules().name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("unknown variant {name}"))
+    }
+}
+
+/// Build a starting position from white's pieces, e.g. "Kg1", and a
+/// function that finds the matching hexagon on black's side.
+fn mirrored_position(white: &[&str], mirror: impl Fn(Hexagon) -> Hexagon) -> HashMap<Hexagon, Piece> {
+    let mut position = HashMap::new();
+    for piece in white {
+        let piece_type = match &piece[..1] {
+            "K" => PieceType::King,
+            "Q" => PieceType::Queen,
+            "R" => PieceType::Rook,
+            "B" => PieceType::Bishop,
+            "N" => PieceType::Knight,
+            "P" => PieceType::Pawn,
+            _ => panic!("unknown piece {piece}"),
+        };
+        let hex = Hexagon::new(&piece[1..]).expect("Invalid hexagon in starting position");
+        position.insert(hex, Piece { piece_type, color: Color::White });
+        position.insert(mirror(hex), Piece { piece_type, color: Color::Black });
+    }
+    position
+}
+
+/// The hexagon at the same height from the other end of its rank
+fn mirror_rank(hex: Hexagon) -> Hexagon {
+    Hexagon {
+        rank: hex.rank,
+        file: get_rank_length(hex.rank).unwrap() - 1 - hex.file,
+    }
+}
+
+/// Gliński's hexagonal chess, the most widely played variant.
+pub struct Glinski;
+
+impl Variant for Glinski {
+    fn name(&self) -> &'static str {
+        "Glinski"
+    }
+
+    fn starting_position(&self) -> &'static HashMap<Hexagon, Piece> {
+        static POSITION: OnceLock<HashMap<Hexagon, Piece>> = OnceLock::new();
+        POSITION.get_or_init(|| {
+            serde_json::from_str(include_str!("./starting_moves.json")).expect("Invalid JSON format")
+        })
+    }
+}
+
+/// McCooey's hexagonal chess. It's played on Gliński's board, but with
+/// seven pawns each and the pieces packed in closer behind them.
+pub struct McCooey;
+
+impl Variant for McCooey {
+    fn name(&self) -> &'static str {
+        "McCooey"
+    }
+
+    fn starting_position(&self) -> &'static HashMap<Hexagon, Piece> {
+        static POSITION: OnceLock<HashMap<Hexagon, Piece>> = OnceLock::new();
+        POSITION.get_or_init(|| {
+            mirrored_position(
+                &[
+                    "Kg1", "Qe1", "Rd1", "Rh1", "Ne2", "Ng2", "Bf1", "Bf2", "Bf3", "Pc1", "Pd2",
+                    "Pe3", "Pf4", "Pg3", "Ph2", "Pi1",
+                ],
+                mirror_rank,
+            )
+        })
+    }
+}
+
+/// Shafran's hexagonal chess, on a 70-hexagon board nine files wide. It
+/// sits inside Gliński's board, without the `a` and `l` files or the top
+/// hexagon of every other file, and is named the same way - so Shafran's
+/// `a` file is `b` here, and so on.
+pub struct Shafran;
+
+impl Variant for Shafran {
+    fn name(&self) -> &'static str {
+        "Shafran"
+    }
+
+    fn starting_position(&self) -> &'static HashMap<Hexagon, Piece> {
+        static POSITION: OnceLock<HashMap<Hexagon, Piece>> = OnceLock::new();
+        POSITION.get_or_init(|| {
+            mirrored_position(
+                &[
+                    "Rc1", "Nd1", "Qe1", "Bf1", "Bf2", "Bf3", "Kg1", "Nh1", "Ri1", "Pb2", "Pc2",
+                    "Pd2", "Pe2", "Pf4", "Pg2", "Ph2", "Pi2", "Pk2",
+                ],
+                |hex| Hexagon {
+                    rank: hex.rank,
+                    file: get_rank_length(hex.rank).unwrap() - 2 - hex.file,
+                },
+            )
+        })
+    }
+
+    fn is_on_board(&self, hexagon: &Hexagon) -> bool {
+        (1..=9).contains(&hexagon.rank)
+            && get_rank_length(hexagon.rank).is_some_and(|length| hexagon.file + 1 < length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board_representations::BitBoard;
+    use crate::perft::{perft, perft_bitboard};
+    use crate::Board;
+
+    fn on_board_count(variant: &dyn Variant) -> usize {
+        (0..11)
+            .flat_map(|rank| (0..get_rank_length(rank).unwrap()).map(move |file| Hexagon { rank, file }))
+            .filter(|hex| variant.is_on_board(hex))
+            .count()
+    }
+
+    #[test]
+    fn test_variant_boards_and_armies() {
+        for (variant, hexes) in [
+            (VariantKind::Glinski, 91),
+            (VariantKind::McCooey, 91),
+            (VariantKind::Shafran, 70),
+        ] {
+            let rules = variant.rules();
+            assert_eq!(on_board_count(rules), hexes, "{variant}");
+
+            let position = rules.starting_position();
+            assert!(position.keys().all(|hex| rules.is_on_board(hex)), "{variant}");
+            for color in [Color::White, Color::Black] {
+                let count = |piece_type| {
+                    position
+                        .values()
+                        .filter(|piece| **piece == Piece { piece_type, color })
+                        .count()
+                };
+                assert_eq!(count(PieceType::King), 1, "{variant}");
+                assert_eq!(count(PieceType::Bishop), 3, "{variant}");
+            }
+        }
+        assert_eq!(Glinski.starting_position().len(), 36);
+        assert_eq!(McCooey.starting_position().len(), 32);
+        assert_eq!(Shafran.starting_position().len(), 36);
+    }
+
+    #[test]
+    fn test_variant_names_round_trip() {
+        for variant in VariantKind::ALL {
+            assert_eq!(variant.to_string().parse::<VariantKind>(), Ok(variant));
+        }
+        assert_eq!("mccooey".parse::<VariantKind>(), Ok(VariantKind::McCooey));
+        assert!("chess".parse::<VariantKind>().is_err());
+    }
+
+    #[test]
+    fn test_shafran_pawns_promote_at_the_edge_of_its_board() {
+        // the top hexagon of the f file isn't part of Shafran's board
+        assert!(Glinski.is_promotion_hex(&Hexagon::new("F11").unwrap(), Color::White));
+        assert!(!Shafran.is_promotion_hex(&Hexagon::new("F9").unwrap(), Color::White));
+        assert!(Shafran.is_promotion_hex(&Hexagon::new("F10").unwrap(), Color::White));
+        assert!(Shafran.is_promotion_hex(&Hexagon::new("F1").unwrap(), Color::Black));
+        assert!(Shafran.is_promotion_hex(&Hexagon::new("K6").unwrap(), Color::White));
+    }
+
+    #[test]
+    fn test_moves_stay_on_the_variant_board() {
+        for variant in [VariantKind::McCooey, VariantKind::Shafran] {
+            let mut board = Board::setup_variant(variant);
+            let moves = board.legal_moves();
+            assert!(!moves.is_empty());
+            assert!(moves.iter().all(|movement| variant.rules().is_on_board(&movement.final_hex)));
+
+            let mut bitboard_moves = BitBoard::from_board(&board).legal_moves();
+            let mut moves = moves;
+            moves.sort();
+            bitboard_moves.sort();
+            assert_eq!(moves, bitboard_moves, "{variant}");
+
+            assert_eq!(perft(&mut board, 2), perft_bitboard(&BitBoard::from_board(&board), 2), "{variant}");
+        }
+    }
+
+    #[test]
+    fn test_variant_pawns_double_step_from_their_own_start() {
+        let board = Board::setup_variant(VariantKind::McCooey);
+        let pawn_moves = board.legal_moves_from(&Hexagon::new("D2").unwrap());
+        let finals: Vec<Hexagon> = pawn_moves.iter().map(|movement| movement.final_hex).collect();
+        assert!(finals.contains(&Hexagon::new("D4").unwrap()));
+
+        // a Gliński starting hexagon isn't one in McCooey's game
+        let mut board = Board::setup_variant(VariantKind::McCooey);
+        board.occupied_squares.clear();
+        board.occupied_squares.insert(
+            Hexagon::new("D3").unwrap(),
+            Piece { piece_type: PieceType::Pawn, color: Color::White },
+        );
+        assert_eq!(board.legal_moves_from(&Hexagon::new("D3").unwrap()).len(), 1);
+    }
+}
@@ -0,0 +1,196 @@
+//! Zobrist keys, for identifying positions with a single `u64`.
+//!
+//! Every (piece, hexagon) pair, the side to move and each possible
+//! en-passant pawn gets its own random key, and a position's hash is all of
+//! its keys xored together. Since xor undoes itself, a move only has to xor
+//! in and out the handful of keys it changes, so boards keep their hash up
+//! to date as moves are made and unmade.
+//!
+//! The keys are generated at compile time from a fixed seed, so hashes are
+//! the same from one run, or one program, to the next.
+
+use crate::geometry::{self, NUMBER_OF_HEXES};
+use crate::{Color, Hexagon, Piece, PieceType};
+
+struct ZobristKeys {
+    /// Indexed by [piece][bit position]
+    pieces: [[u64; NUMBER_OF_HEXES]; 12],
+    black_to_move: u64,
+    /// Indexed by the bit position of the pawn that can be taken en passant
+    en_passant: [u64; NUMBER_OF_HEXES],
+}
+
+/// One step of splitmix64, a small, good quality random number generator
+const fn splitmix64(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    (state, z ^ (z >> 31))
+}
+
+const fn generate_keys() -> ZobristKeys {
+    let mut keys = ZobristKeys {
+        pieces: [[0; NUMBER_OF_HEXES]; 12],
+        black_to_move: 0,
+        en_passant: [0; NUMBER_OF_HEXES],
+    };
+    let mut state = 0x6865_7863_6865_7373; // "hexchess"
+    let mut key;
+
+    let mut piece = 0;
+    while piece < 12 {
+        let mut hex = 0;
+        while hex < NUMBER_OF_HEXES {
+            (state, key) = splitmix64(state);
+            keys.pieces[piece][hex] = key;
+            hex += 1;
+        }
+        piece += 1;
+    }
+
+    (state, key) = splitmix64(state);
+    keys.black_to_move = key;
+
+    let mut hex = 0;
+    while hex < NUMBER_OF_HEXES {
+        (state, key) = splitmix64(state);
+        keys.en_passant[hex] = key;
+        hex += 1;
+    }
+    keys
+}
+
+static KEYS: ZobristKeys = generate_keys();
+
+fn piece_index(piece: Piece) -> usize {
+    let piece_type = match piece.piece_type {
+        PieceType::King => 0,
+        PieceType::Queen => 1,
+        PieceType::Rook => 2,
+        PieceType::Bishop => 3,
+        PieceType::Knight => 4,
+        PieceType::Pawn => 5,
+    };
+    match piece.color {
+        Color::White => piece_type,
+        Color::Black => piece_type + 6,
+    }
+}
+
+/// The key for a piece standing on a hexagon
+pub fn piece_key(piece: Piece, hex: Hexagon) -> u64 {
+    KEYS.pieces[piece_index(piece)][geometry::index(&hex)]
+}
+
+/// The key xored in whenever it's black's turn
+pub fn side_to_move_key(color: Color) -> u64 {
+    match color {
+        Color::White => 0,
+        Color::Black => KEYS.black_to_move,
+    }
+}
+
+/// The key for a pawn that has just double jumped, if there is one
+pub fn en_passant_key(en_passant: Option<Hexagon>) -> u64 {
+    en_passant.map_or(0, |hex| KEYS.en_passant[geometry::index(&hex)])
+}
+
+/// Hash a position from scratch.
+pub fn hash_position<'a>(
+    pieces: impl IntoIterator<Item = (&'a Hexagon, &'a Piece)>,
+    current_player: Color,
+    en_passant: Option<Hexagon>,
+) -> u64 {
+    pieces
+        .into_iter()
+        .fold(0, |hash, (hex, piece)| hash ^ piece_key(*piece, *hex))
+        ^ side_to_move_key(current_player)
+        ^ en_passant_key(en_passant)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{apply_move, revert_move, Board, Color, Hexagon, Move, PieceType};
+
+    fn hex(name: &str) -> Hexagon {
+        Hexagon::new(name).unwrap()
+    }
+
+    fn quiet_move(start: &str, finish: &str, piece_type: PieceType) -> Move {
+        Move {
+            start_hex: hex(start),
+            final_hex: hex(finish),
+            final_piece: piece_type,
+        }
+    }
+
+    #[test]
+    fn test_keys_are_distinct() {
+        let mut keys: Vec<u64> = super::KEYS.pieces.iter().flatten().copied().collect();
+        keys.extend(super::KEYS.en_passant);
+        keys.push(super::KEYS.black_to_move);
+        let count = keys.len();
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys.len(), count);
+        assert!(!keys.contains(&0));
+    }
+
+    #[test]
+    fn test_transpositions_have_the_same_hash() {
+        let start = Board::setup_default_board();
+        let mut board = start.clone();
+        let knight_out = board.legal_moves_from(&hex("D1"))[0];
+        let knight_back = quiet_move(&knight_out.final_hex.to_string(), "D1", PieceType::Knight);
+        apply_move(&mut board, knight_out);
+        let black_out = board.legal_moves_from(&hex("H9"))[0];
+        let black_back = quiet_move(&black_out.final_hex.to_string(), "H9", PieceType::Knight);
+        for movement in [black_out, knight_back, black_back] {
+            apply_move(&mut board, movement);
+        }
+        assert_eq!(board.occupied_squares, start.occupied_squares);
+        assert_eq!(board.hash(), start.hash());
+
+        // same pieces, other player to move
+        let mut other_side = start.clone();
+        other_side.current_player = Color::Black;
+        other_side.refresh_hash();
+        assert_ne!(other_side.hash(), start.hash());
+    }
+
+    #[test]
+    fn test_hash_tracks_en_passant() {
+        let mut board = Board::setup_default_board();
+        let double_jump = quiet_move("F5", "F7", PieceType::Pawn);
+        let undo = apply_move(&mut board, double_jump);
+        assert_eq!(board.en_passant, Some(hex("F7")));
+
+        let mut without_en_passant = board.clone();
+        without_en_passant.en_passant = None;
+        without_en_passant.refresh_hash();
+        assert_ne!(board.hash(), without_en_passant.hash());
+
+        let mut refreshed = board.clone();
+        refreshed.refresh_hash();
+        assert_eq!(board.hash(), refreshed.hash());
+
+        revert_move(&mut board, double_jump, undo);
+        assert_eq!(board.hash(), Board::setup_default_board().hash());
+    }
+
+    #[test]
+    fn test_hash_is_rebuilt_when_deserializing() {
+        let mut board = Board::setup_default_board();
+        apply_move(&mut board, quiet_move("D1", "E3", PieceType::Knight));
+
+        let json = serde_json::to_string(&board).unwrap();
+        assert!(!json.contains("hash"));
+        let read_back: Board = serde_json::from_str(&json).unwrap();
+        assert_eq!(read_back.hash(), board.hash());
+
+        let from_notation = Board::from_notation(&board.to_notation()).unwrap();
+        assert_eq!(from_notation.hash(), board.hash());
+        assert_eq!(from_notation.current_player, Color::Black);
+    }
+}
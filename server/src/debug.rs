@@ -1,10 +1,12 @@
-use std::fs::File;
+use std::fs;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 
 use futures::{SinkExt, StreamExt, TryFutureExt};
-use hexchesscore::{Board};
+use hexchesscore::board_diff::infer_move;
+use hexchesscore::{Board, Move};
 
 use tokio::sync::Mutex;
 use tokio::{self, sync::mpsc};
@@ -16,26 +18,54 @@ use warp::Filter;
 
 use uuid::{self, Uuid};
 
-use std::io::BufReader;
-
 use api::{OutgoingMessage, PlayerColor};
 
-pub fn send_board(transmitter: &mpsc::UnboundedSender<Message>, board: Board) {
+pub fn send_board(transmitter: &mpsc::UnboundedSender<Message>, board: Board, last_move: Option<Move>) {
     let _result = transmitter.send(Message::text(
-        serde_json::to_string(&OutgoingMessage::BoardState { board: board }).unwrap(),
+        serde_json::to_string(&OutgoingMessage::BoardState { board, last_move }).unwrap(),
     ));
 }
 
+/// How long to wait before looking at the debug file again
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Read the board in the debug file. It can either be a JSON dump of the
+/// board, or a position written in hexchesscore's notation, and it's only
+/// read if it's a position the client can draw.
+pub fn read_debug_board(contents: &str) -> Result<Board, String> {
+    let board = serde_json::from_str::<Board>(contents).or_else(|json_error| {
+        Board::from_notation(contents)
+            .map_err(|notation_error| format!("neither JSON ({json_error}) nor notation ({notation_error})"))
+    })?;
+    board.validate().map_err(|problems| format!("invalid board: {problems:?}"))?;
+    Ok(board)
+}
+
 pub async fn debug_sender(tx: Arc<Mutex<mpsc::UnboundedSender<Message>>>, board: &'static str) {
+    // remember the move between the last two different boards in the file
+    let mut previous: Option<Board> = None;
+    let mut last_move = None;
+    let mut last_contents = None;
     loop {
-        let f = File::open(board).expect("Couldn't open file");
-        let reader = BufReader::new(f);
-        let board: Result<Board, serde_json::Error>  = serde_json::from_reader(reader);
-
-        if let Ok(board) = board {
-            let tx = tx.lock().await;
-            send_board(&tx, board.clone());
+        let contents = fs::read_to_string(board).expect("Couldn't open file");
+        // only a change to the file is worth reading, or complaining about
+        if last_contents.as_ref() != Some(&contents) {
+            match read_debug_board(&contents) {
+                Ok(board) => {
+                    if let Some(previous) = &previous {
+                        last_move = infer_move(previous, &board).ok();
+                    }
+                    previous = Some(board.clone());
+
+                    let tx = tx.lock().await;
+                    send_board(&tx, board, last_move);
+                }
+                // a half-edited file can describe a position the client can't draw
+                Err(e) => eprintln!("Not sending the board: {e}"),
+            }
+            last_contents = Some(contents);
         }
+        tokio::time::sleep(POLL_INTERVAL).await;
     }
 }
 
@@ -84,3 +114,27 @@ pub async fn spawn_debug_server(_file_to_watch: PathBuf) {
 
     warp::serve(websocket).run(([127, 0, 0, 1], 7878)).await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reading_the_debug_board() {
+        let json = serde_json::to_string(&Board::setup_default_board()).unwrap();
+        assert_eq!(read_debug_board(&json), Ok(Board::setup_default_board()));
+        let notation = Board::setup_default_board().to_notation();
+        assert_eq!(read_debug_board(&notation), Ok(Board::setup_default_board()));
+
+        // hexagons off the board can't even be read
+        let off_board = r#"{"occupied_squares": {"l11": {"piece_type": "King", "color": "White"}},
+            "en_passant": null, "current_player": "White"}"#;
+        let error = read_debug_board(off_board).unwrap_err();
+        assert!(error.contains("l11 is not on the board"), "{error}");
+
+        let no_kings = r#"{"occupied_squares": {"f5": {"piece_type": "Pawn", "color": "White"}},
+            "en_passant": null, "current_player": "White"}"#;
+        let error = read_debug_board(no_kings).unwrap_err();
+        assert!(error.starts_with("invalid board"), "{error}");
+    }
+}
@@ -0,0 +1,309 @@
+//! Bot opponents, played by an engine that the server runs as a subprocess
+//! and talks to over [`hexchesscore::engine_protocol`].
+//!
+//! Each single-player game owns its own engine. Whenever it's the bot's turn
+//! the engine is sent the game so far and asked for a move, and the move it
+//! answers with comes back through a channel to be played for the bot. The
+//! engine is shut down when the game ends or is deleted.
+//!
+//! The engine's input and output are each handled by a thread of their own,
+//! so a slow or stuck engine never holds up the server while it has the
+//! sessions locked.
+
+use std::env;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc as std_mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use hexchesscore::engine_protocol::{self, CoordinateMove, GoLimits, Info, Response};
+use hexchesscore::game_record::GameRecord;
+use hexchesscore::{apply_move, Color};
+use tokio::sync::mpsc;
+
+use crate::session_handling::PlayerID;
+
+/// Where bumblebot might be, relative to the directory the server's executable
+/// is in: installed alongside it, or built in release mode in the same source
+/// tree, where the server is built into `server/target/<profile>/`
+const DEFAULT_ENGINE_PATHS: [&str; 2] = ["bumblebot", "../../../bumblebot/target/release/bumblebot"];
+const DEFAULT_MOVE_TIME_MS: u64 = 2000;
+/// How long an engine gets to quit by itself before it's killed
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(1);
+
+/// Which engine to run for bot opponents, and how long it gets per move.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EngineConfig {
+    pub program: String,
+    pub args: Vec<String>,
+    pub move_time: Duration,
+}
+
+impl EngineConfig {
+    /// The engine run by `command`, a program followed by its arguments.
+    pub fn from_command(command: &str, move_time: Duration) -> Option<EngineConfig> {
+        let mut words = command.split_whitespace().map(str::to_string);
+        Some(EngineConfig {
+            program: words.next()?,
+            args: words.collect(),
+            move_time,
+        })
+    }
+
+    /// The engine set by `HEXCHESS_ENGINE` and `HEXCHESS_ENGINE_MOVE_MS`,
+    /// falling back to bumblebot with two seconds a move.
+    pub fn from_env() -> EngineConfig {
+        let move_time = env::var("HEXCHESS_ENGINE_MOVE_MS")
+            .ok()
+            .and_then(|ms| ms.parse().ok())
+            .map_or(Duration::from_millis(DEFAULT_MOVE_TIME_MS), Duration::from_millis);
+        env::var("HEXCHESS_ENGINE")
+            .ok()
+            .and_then(|command| EngineConfig::from_command(&command, move_time))
+            .unwrap_or(EngineConfig {
+                move_time,
+                ..EngineConfig::default()
+            })
+    }
+}
+
+/// bumblebot, looked for next to the server's executable rather than in the
+/// directory the server happens to be run from. If it isn't there, it's left
+/// to be found on the `PATH`.
+fn default_engine_program() -> String {
+    let exe_dir = env::current_exe().ok().and_then(|exe| exe.parent().map(PathBuf::from));
+    exe_dir
+        .and_then(|dir| DEFAULT_ENGINE_PATHS.iter().map(|path| dir.join(path)).find(|path| path.is_file()))
+        .map_or_else(|| "bumblebot".to_string(), |path| path.to_string_lossy().into_owned())
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            program: default_engine_program(),
+            args: vec!["protocol".to_string()],
+            move_time: Duration::from_millis(DEFAULT_MOVE_TIME_MS),
+        }
+    }
+}
+
+/// An engine playing one side of a game. Dropping it shuts the engine down.
+#[derive(Debug)]
+pub struct EngineOpponent {
+    /// The bot's place in the game, as if it were any other player
+    pub player: PlayerID,
+    pub color: Color,
+    move_time: Duration,
+    child: Option<Child>,
+    /// Commands for the thread writing to the engine's input
+    commands: std_mpsc::Sender<engine_protocol::Command>,
+}
+
+impl EngineOpponent {
+    /// Start the engine for a new game. Each move it answers with is sent to
+    /// `moves` - None means it found nothing to play.
+    pub fn start(
+        config: &EngineConfig,
+        player: PlayerID,
+        color: Color,
+        moves: mpsc::UnboundedSender<Option<CoordinateMove>>,
+    ) -> io::Result<EngineOpponent> {
+        let mut child = Command::new(&config.program)
+            .args(&config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut stdin = child.stdin.take().expect("the engine's stdin is piped");
+        let stdout = child.stdout.take().expect("the engine's stdout is piped");
+
+        // writing can block too, if the engine stops reading. The thread
+        // finishes when the engine can't be written to, or when the opponent
+        // is dropped, which closes the engine's input
+        let (commands, queued) = std_mpsc::channel::<engine_protocol::Command>();
+        thread::spawn(move || {
+            for command in queued {
+                if let Err(e) = writeln!(stdin, "{command}").and_then(|_| stdin.flush()) {
+                    eprintln!("couldn't write to the engine: {e}");
+                    break;
+                }
+            }
+        });
+
+        // reading blocks, so it gets a thread of its own, which finishes when
+        // the engine closes its output
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                match Response::parse(&line) {
+                    Ok(Response::BestMove(movement)) => {
+                        if moves.send(movement).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(Response::Info(Info { string: Some(string), .. })) => eprintln!("engine says: {string}"),
+                    Ok(_) => {}
+                    Err(e) => eprintln!("couldn't read the engine's line '{line}': {e}"),
+                }
+            }
+        });
+
+        let mut opponent = EngineOpponent {
+            player,
+            color,
+            move_time: config.move_time,
+            child: Some(child),
+            commands,
+        };
+        opponent.send(&engine_protocol::Command::Uci)?;
+        opponent.send(&engine_protocol::Command::NewGame)?;
+        Ok(opponent)
+    }
+
+    /// Queue `command` for the engine. This doesn't wait for it to be
+    /// written, and only fails once the engine can't be written to at all.
+    fn send(&mut self, command: &engine_protocol::Command) -> io::Result<()> {
+        self.commands
+            .send(command.clone())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the engine has stopped taking commands"))
+    }
+
+    /// Ask for a move in the game so far. The whole game is sent, rather
+    /// than just the position, so the engine can see repetitions coming.
+    pub fn request_move(&mut self, record: &GameRecord) -> io::Result<()> {
+        let mut board = record.starting_position.clone();
+        let mut moves = Vec::new();
+        for recorded in &record.moves {
+            moves.push(CoordinateMove::from_move(&board, recorded.movement));
+            apply_move(&mut board, recorded.movement);
+        }
+        self.send(&engine_protocol::Command::Position {
+            start: record.starting_position.clone(),
+            moves,
+        })?;
+        self.send(&engine_protocol::Command::Go(GoLimits {
+            movetime: Some(self.move_time),
+            ..GoLimits::default()
+        }))
+    }
+}
+
+impl Drop for EngineOpponent {
+    fn drop(&mut self) {
+        // the engine may already be gone, in which case there's no one to tell
+        let _ = self.send(&engine_protocol::Command::Quit);
+        if let Some(mut child) = self.child.take() {
+            // don't hold up the server while the engine winds down
+            thread::spawn(move || {
+                let deadline = Instant::now() + SHUTDOWN_GRACE;
+                while Instant::now() < deadline {
+                    if let Ok(Some(_)) = child.try_wait() {
+                        return;
+                    }
+                    thread::sleep(Duration::from_millis(10));
+                }
+                eprintln!("the engine didn't quit, so it's being killed");
+                let _ = child.kill();
+                let _ = child.wait();
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use hexchesscore::variants::VariantKind;
+    use hexchesscore::Board;
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn test_engine_commands_are_split_into_words() {
+        let config = EngineConfig::from_command("  ./engine  protocol mcts ", Duration::from_millis(500)).unwrap();
+        assert_eq!(config.program, "./engine");
+        assert_eq!(config.args, vec!["protocol", "mcts"]);
+        assert_eq!(config.move_time, Duration::from_millis(500));
+        assert_eq!(EngineConfig::from_command(" ", Duration::from_millis(500)), None);
+
+        // the only test to touch these variables, so there's nothing to race
+        env::set_var("HEXCHESS_ENGINE", "stockfish-hex --threads 2");
+        env::set_var("HEXCHESS_ENGINE_MOVE_MS", "750");
+        let config = EngineConfig::from_env();
+        assert_eq!(config.program, "stockfish-hex");
+        assert_eq!(config.args, vec!["--threads", "2"]);
+        assert_eq!(config.move_time, Duration::from_millis(750));
+
+        env::remove_var("HEXCHESS_ENGINE");
+        env::set_var("HEXCHESS_ENGINE_MOVE_MS", "soon");
+        let config = EngineConfig::from_env();
+        assert_eq!(config.args, vec!["protocol"]);
+        assert_eq!(config.move_time, Duration::from_millis(DEFAULT_MOVE_TIME_MS));
+        env::remove_var("HEXCHESS_ENGINE_MOVE_MS");
+    }
+
+    /// Everything the engine was sent, once it's been told to quit.
+    fn commands_sent(starting_position: Board) -> (Vec<String>, Vec<String>) {
+        let mut record = GameRecord::new(starting_position.clone());
+        let mut board = starting_position;
+        let mut expected_moves = Vec::new();
+        for _ in 0..2 {
+            let movement = board.legal_moves()[0];
+            expected_moves.push(CoordinateMove::from_move(&board, movement).to_string());
+            board.play(movement).unwrap();
+            record.push_move(movement);
+        }
+
+        let log = env::temp_dir().join(format!("hexchess-engine-{}.log", Uuid::new_v4()));
+        // an engine that writes down whatever it's told
+        let config = EngineConfig {
+            program: "sh".to_string(),
+            args: vec![
+                "-c".to_string(),
+                r#"cat > "$1""#.to_string(),
+                "engine".to_string(),
+                log.to_string_lossy().into_owned(),
+            ],
+            move_time: Duration::from_millis(300),
+        };
+        let (moves, _replies) = mpsc::unbounded_channel();
+        let mut opponent = EngineOpponent::start(&config, Uuid::new_v4(), Color::Black, moves).unwrap();
+        opponent.request_move(&record).unwrap();
+        drop(opponent);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let sent = loop {
+            let sent = fs::read_to_string(&log).unwrap_or_default();
+            if sent.ends_with("quit\n") || Instant::now() > deadline {
+                break sent;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        let _ = fs::remove_file(&log);
+        (sent.lines().map(str::to_string).collect(), expected_moves)
+    }
+
+    #[test]
+    fn test_the_whole_game_is_sent_to_the_engine() {
+        let (sent, moves) = commands_sent(Board::setup_default_board());
+        assert_eq!(
+            sent,
+            vec![
+                "uci".to_string(),
+                "ucinewgame".to_string(),
+                format!("position startpos moves {} {}", moves[0], moves[1]),
+                "go movetime 300".to_string(),
+                "quit".to_string(),
+            ]
+        );
+
+        // other variants start from their own position
+        let mccooey = Board::setup_variant(VariantKind::McCooey);
+        let (sent, moves) = commands_sent(mccooey.clone());
+        assert_eq!(sent[2], format!("position fen {} moves {} {}", mccooey.to_notation(), moves[0], moves[1]));
+        assert!(sent[2].contains(" McCooey moves "), "{}", sent[2]);
+    }
+}
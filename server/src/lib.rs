@@ -3,6 +3,7 @@ use std::{
     thread,
 };
 
+pub mod engine_adapter;
 pub mod session_handling;
 pub mod websocket_messaging;
 pub mod debug;
@@ -2,6 +2,7 @@
 use futures::{SinkExt, StreamExt, TryFutureExt};
 
 use server::{session_handling, websocket_messaging, debug};
+use server::engine_adapter::EngineConfig;
 use std::env;
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -81,7 +82,9 @@ async fn main() {
     
         let pages = warp::fs::dir("./server_files/");
     
-        let sessions: Arc<RwLock<session_handling::SessionHandler>> = Arc::new(RwLock::new(session_handling::SessionHandler::new()));
+        let mut handler = session_handling::SessionHandler::new();
+        handler.engine = EngineConfig::from_env();
+        let sessions: Arc<RwLock<session_handling::SessionHandler>> = Arc::new(RwLock::new(handler));
     
         let sessions = warp::any().map(move || sessions.clone());
     
@@ -1,9 +1,13 @@
 use std::collections::{HashMap, VecDeque};
 use warp::ws::Message;
-use hexchesscore::{Board, Color};
+use hexchesscore::{Board, Color, Move};
+use hexchesscore::game_record::{GameRecord, RecordResult};
+use hexchesscore::variants::VariantKind;
 use uuid::Uuid;
 
-use api::PlayerColor;
+use api::{GameEndReason, GameOutcome, OutgoingMessage, PlayerColor};
+
+use crate::engine_adapter::{EngineConfig, EngineOpponent};
 
 #[derive(Debug, Clone, Copy)]
 pub struct PlayersPerGame {
@@ -51,6 +55,16 @@ impl PlayersPerGame {
         players_color
     }
 
+    /// The colour `try_add_player` would give the next player, if there's a
+    /// seat left.
+    pub fn free_color(&self) -> Option<PlayerColor> {
+        match (self.black, self.white) {
+            (None, _) => Some(PlayerColor::Black),
+            (Some(_), None) => Some(PlayerColor::White),
+            (Some(_), Some(_)) => None,
+        }
+    }
+
     pub fn check_color(&self, player: PlayerID, color: Color) -> bool {
         // Look at whether it is a player's turn, given the player's ID
         // and the color that is currently allowed to move
@@ -90,17 +104,69 @@ pub type PlayerID = Uuid;
 pub struct Game {
     pub board: Board,
     pub players: PlayersPerGame,
-    pub channels: HashMap<PlayerID, tokio::sync::mpsc::UnboundedSender<Message>>
+    pub channels: HashMap<PlayerID, tokio::sync::mpsc::UnboundedSender<Message>>,
+    /// Every move played so far, so finished games can be archived
+    pub record: GameRecord,
+    /// The engine playing one side, in single-player games
+    pub bot: Option<EngineOpponent>,
 }
 
 impl Game {
-    pub fn new(user_id: PlayerID, transmitter: &tokio::sync::mpsc::UnboundedSender<Message>) -> (SessionID, Game, PlayerColor) {
-        let board = Board::setup_default_board();
+    pub fn new(user_id: PlayerID, variant: VariantKind, transmitter: &tokio::sync::mpsc::UnboundedSender<Message>) -> (SessionID, Game, PlayerColor) {
+        let board = Board::setup_variant(variant);
         let (color, players) = PlayersPerGame::new(user_id);
         let session_id = Uuid::new_v4();
         let mut channels = HashMap::new();
         channels.insert(user_id, transmitter.clone());
-        (session_id, Game {board: board, players: players, channels: channels}, color)
+        let mut record = GameRecord::new(board.clone());
+        record.set_tag("Event", "Hexchess");
+        record.set_tag("Site", &session_id.to_string());
+        (session_id, Game {board: board, players: players, channels: channels, record, bot: None}, color)
+    }
+
+    /// The most recent move of the game, if any have been played.
+    pub fn last_move(&self) -> Option<Move> {
+        self.record.moves.last().map(|recorded| recorded.movement)
+    }
+
+    /// Note the result in the game's record, along with who played it.
+    pub fn finish_record(&mut self, result: RecordResult) {
+        for (tag, player) in [("White", self.players.white), ("Black", self.players.black)] {
+            if let Some(player) = player {
+                self.record.set_tag(tag, &player.to_string());
+            }
+        }
+        self.record.result = result;
+    }
+
+    /// Ask the bot for its move, if it's the bot's turn in a game that's
+    /// still going.
+    pub fn request_bot_move(&mut self) {
+        if self.record.result != RecordResult::Ongoing {
+            return;
+        }
+        if let Some(bot) = &mut self.bot {
+            if bot.color == self.board.current_player {
+                if let Err(e) = bot.request_move(&self.record) {
+                    eprintln!("couldn't ask the engine for a move: {e}");
+                    self.forfeit_bot();
+                }
+            }
+        }
+    }
+
+    /// End the game as a loss for the bot, whose engine can't come up with
+    /// a move it can play, and shut the engine down.
+    pub fn forfeit_bot(&mut self) {
+        let Some(bot) = self.bot.take() else {
+            return;
+        };
+        let result = match bot.color {
+            Color::White => RecordResult::BlackWins,
+            Color::Black => RecordResult::WhiteWins,
+        };
+        self.finish_record(result);
+        send_resignation(bot.player, &self.channels);
     }
 }
 
@@ -108,7 +174,9 @@ impl Game {
 pub struct SessionHandler {
     pub sessions: HashMap<SessionID, Game>,
     pub players: HashMap<PlayerID, SessionID>,
-    pub joinable_sessions: VecDeque<SessionID>
+    pub joinable_sessions: VecDeque<SessionID>,
+    /// The engine that plays the bot in single-player games
+    pub engine: EngineConfig,
 }
 
 impl SessionHandler {
@@ -116,7 +184,8 @@ impl SessionHandler {
         SessionHandler {
             sessions: HashMap::<SessionID, Game>::new(),
             players: HashMap::<PlayerID, SessionID>::new(),
-            joinable_sessions: VecDeque::<SessionID>::new()
+            joinable_sessions: VecDeque::<SessionID>::new(),
+            engine: EngineConfig::default(),
         }
     }
 
@@ -136,8 +205,8 @@ impl SessionHandler {
         }
     }
 
-    pub fn add_session(&mut self, user_id: Uuid, is_multiplayer: bool, joinable: bool, transmitter: tokio::sync::mpsc::UnboundedSender<Message>) -> (SessionID, &mut Game, PlayerColor) {
-        let (session_id, mut new_session, mut player_color) = Game::new(user_id, &transmitter);
+    pub fn add_session(&mut self, user_id: Uuid, is_multiplayer: bool, joinable: bool, variant: VariantKind, transmitter: tokio::sync::mpsc::UnboundedSender<Message>) -> (SessionID, &mut Game, PlayerColor) {
+        let (session_id, mut new_session, mut player_color) = Game::new(user_id, variant, &transmitter);
         // if multiplayer, just add the one player for the moment,
         // which is performed in the session::new() setup.
         // if single-player, both player slots are the same player
@@ -199,7 +268,7 @@ impl SessionHandler {
         None
     }
 
-    pub fn reconnect_player(&mut self, user_id: PlayerID, transmitter: tokio::sync::mpsc::UnboundedSender<Message>) -> Option<(PlayerColor, &Board)> {
+    pub fn reconnect_player(&mut self, user_id: PlayerID, transmitter: tokio::sync::mpsc::UnboundedSender<Message>) -> Option<(PlayerColor, &Game)> {
         let game = self.get_mut_session_if_exists(user_id);
         if let Some(valid_game) = game {
             let color = valid_game.players.check_for_player(user_id);
@@ -208,7 +277,7 @@ impl SessionHandler {
                 valid_game.channels.insert(user_id, transmitter.clone());
             }
 
-            Some((color.unwrap(), &valid_game.board))
+            Some((color.unwrap(), &*valid_game))
         }
         else {
             None
@@ -232,7 +301,7 @@ impl SessionHandler {
             // delete it from the queue if you can't join
         }
         // can't find any games for some reason; time to make one
-        let (session_id, game, color) = self.add_session(user_id, true, true, transmitter.clone());
+        let (session_id, game, color) = self.add_session(user_id, true, true, VariantKind::default(), transmitter.clone());
 
         (session_id, game, color)
     }
@@ -246,9 +315,21 @@ impl SessionHandler {
     }
 }
 
-// TODO this is a really hacky way of killing bots for the moment
-pub fn send_resignation(_initiating_player: PlayerID, channels: &HashMap<PlayerID, tokio::sync::mpsc::UnboundedSender<Message>>) {
-    for channel in channels.values() {
-        let _ = channel.send(warp::ws::Message::text("{{\"op\"= \"GameEnded\"}}"));
+/// Tell everyone else in the game that they've won, as `initiating_player`
+/// has resigned. A bot has no channel, and its engine is shut down along
+/// with the game.
+pub fn send_resignation(initiating_player: PlayerID, channels: &HashMap<PlayerID, tokio::sync::mpsc::UnboundedSender<Message>>) {
+    let message = OutgoingMessage::GameEnded {
+        game_outcome: GameOutcome::Won,
+        reason: GameEndReason::Resignation,
+    };
+    let Ok(message) = serde_json::to_string(&message) else {
+        eprintln!("Failed to send resignation");
+        return;
+    };
+    for (player, channel) in channels {
+        if *player != initiating_player {
+            let _ = channel.send(warp::ws::Message::text(message.clone()));
+        }
     }
 }
\ No newline at end of file
@@ -1,13 +1,17 @@
-use hexchesscore::{check_for_mates, get_valid_moves, register_move, Board, Mate};
+use hexchesscore::{Color, GameResult, HexChessError, Move, PieceType};
+use hexchesscore::engine_protocol::CoordinateMove;
+use hexchesscore::game_record::RecordResult;
 use uuid::Uuid;
 
 use tokio::sync::{mpsc, RwLock};
 
-use std::{sync::Arc, process::Command};
+use std::io;
+use std::sync::Arc;
 
 use warp::ws::Message;
 
-use crate::session_handling::{self};
+use crate::engine_adapter::{EngineConfig, EngineOpponent};
+use crate::session_handling::{self, PlayerID, SessionID};
 use api::{GameEndReason, GameOutcome, IncomingMessage, OutgoingMessage, PlayerColor};
 
 pub async fn handle_incoming_ws_message(
@@ -23,22 +27,31 @@ pub async fn handle_incoming_ws_message(
         IncomingMessage::CreateGame {
             user_id,
             is_multiplayer,
+            variant,
         } => {
             uuid_user_id = Uuid::parse_str(&user_id).unwrap();
 
             let mut session = sessions.write().await;
+            let engine = session.engine.clone();
 
             let multiplayer = true;
             
-            let (session_id, session, color) =
-            session.add_session(uuid_user_id, multiplayer, false, tx.clone());
+            let (session_id, game, color) =
+            session.add_session(uuid_user_id, multiplayer, false, variant, tx.clone());
 
             if !is_multiplayer {
-                // spawn a bot
-                Command::new("../bumblebot/target/release/bumblebot").arg(session_id.to_string()).spawn().expect("failed to spawn bot");
+                if let Err(e) = add_bot(session_id, game, &engine, sessions) {
+                    eprintln!("Failed to start the engine {}: {e}", engine.program);
+                    // a game with no one to play against is no use to anyone
+                    session.delete_player(uuid_user_id);
+                    send_join_failure(tx);
+                    return;
+                }
             }
             
-            send_join_success(color, session_id, tx, &session.board);
+            send_join_success(color, session_id, tx, game);
+            // the bot might be playing white
+            game.request_bot_move();
         }
         IncomingMessage::JoinAnyGame { user_id } => {
             uuid_user_id = Uuid::parse_str(&user_id).unwrap();
@@ -47,7 +60,7 @@ pub async fn handle_incoming_ws_message(
             let (session_id, session, color) =
                 session.try_join_any_sessions(uuid_user_id, tx.clone());
 
-            send_join_success(color, session_id, tx, &session.board);
+            send_join_success(color, session_id, tx, session);
         }
         IncomingMessage::GetBoard { user_id } => {
             // Get the state of the board associated with the user's ID
@@ -58,7 +71,7 @@ pub async fn handle_incoming_ws_message(
             let session = sessions.read().await;
 
             if let Some(valid_session) = session.get_session_if_exists(uuid_user_id) {
-                send_board(&valid_session.board, tx);
+                send_board(valid_session, tx);
             } else {
                 eprintln!("User doesn't have an existing game");
             }
@@ -67,14 +80,31 @@ pub async fn handle_incoming_ws_message(
         IncomingMessage::GetMoves { user_id, hexagon } => {
             uuid_user_id = Uuid::parse_str(&user_id).unwrap();
 
-            // we need the board mutable because we do some intermediate mutations
-            // while checking for check, before returning the board to its original state.
-            // probably, we should just clone the board if doing so is fast enough.
-            let mut session = sessions.write().await;
+            // finding the legal moves doesn't change the board, so other
+            // players can look at their games at the same time
+            let session = sessions.read().await;
 
-            if let Some(valid_session) = session.get_mut_session_if_exists(uuid_user_id) {
-                let (moves, _, promotion_moves) =
-                    get_valid_moves(&hexagon,  &mut valid_session.board);
+            if let Some(valid_session) = session.get_session_if_exists(uuid_user_id) {
+                let piece_type = valid_session
+                    .board
+                    .occupied_squares
+                    .get(&hexagon)
+                    .map(|piece| piece.piece_type);
+
+                // a promoting pawn has one move per promotion choice, but the client
+                // only needs to know which hexagons it can reach
+                let mut moves = Vec::new();
+                let mut promotion_moves = Vec::new();
+                for valid_move in valid_session.board.legal_moves_from(&hexagon) {
+                    if !moves.contains(&valid_move.final_hex) {
+                        moves.push(valid_move.final_hex);
+                    }
+                    if Some(valid_move.final_piece) != piece_type
+                        && !promotion_moves.contains(&valid_move.final_hex)
+                    {
+                        promotion_moves.push(valid_move.final_hex);
+                    }
+                }
 
                 let outgoing = OutgoingMessage::ValidMoves {
                     moves: moves,
@@ -89,9 +119,9 @@ pub async fn handle_incoming_ws_message(
         },
         IncomingMessage::GetGameState { user_id } => {
             uuid_user_id = Uuid::parse_str(&user_id).unwrap();
-            let mut session = sessions.write().await;
+            let session = sessions.read().await;
 
-            let maybe_session = session.get_mut_session_if_exists(uuid_user_id);
+            let maybe_session = session.get_session_if_exists(uuid_user_id);
 
             if let Some(valid_session) = maybe_session {
                 let outgoing;
@@ -118,64 +148,23 @@ pub async fn handle_incoming_ws_message(
 
             let mut session = sessions.write().await;
 
-            // check if it is the player's turn to make a move
             let maybe_session = session.get_mut_session_if_exists(uuid_user_id);
 
             if let Some(valid_session) = maybe_session {
-                let board = &mut valid_session.board;
-                // check this player really has the right to play the next move
-                if valid_session
-                    .players
-                    .check_color(uuid_user_id, board.current_player)
-                {
-                    // try and process the move
-                    // match piece type to valid moves
-                    let (moves, double_jump, promotion_moves) =
-                        get_valid_moves(&start_hexagon, board);
-
-                    if moves.contains(&final_hexagon) {
-                        let _ = register_move(
-                            &start_hexagon,
-                            &final_hexagon,
-                            board,
-                            double_jump,
-                            promotion_moves,
-                            promotion_choice,
-                        );
-                        // if the game has ended, send some ending messages
-                        if let Some(mate) = check_for_mates(board) {
-                            // the player registering the move has just won
-
-                            // send a win message to the player
-                            send_game_end(
-                                Some(mate),
-                                true,
-                                valid_session.channels.get(&uuid_user_id).expect(
-                                    "No channels
-                            to communicate with the player who sent this move in!",
-                                ),
-                            );
-
-                            // send a lose message to the opponent
-                            let loser_channel =
-                                valid_session.channels.iter().find_map(|(player, channel)| {
-                                    if player != &uuid_user_id {
-                                        Some(channel)
-                                    } else {
-                                        None
-                                    }
-                                });
-
-                            if loser_channel.is_some() {
-                                send_game_end(Some(mate), false, loser_channel.unwrap());
-                            }
-                        }
-
-                        // broadcast an update to both the players
-                        for (_, transmitter) in &valid_session.channels {
-                            send_board(&valid_session.board, transmitter);
-                        }
-                    }
+                let board = &valid_session.board;
+                // a promotion choice is only sent for pawns reaching their last
+                // hexagon - every other move keeps the piece it started with
+                let final_piece = promotion_choice
+                    .or(board.occupied_squares.get(&start_hexagon).map(|piece| piece.piece_type))
+                    .unwrap_or(PieceType::Pawn);
+                let movement = Move {
+                    start_hex: start_hexagon,
+                    final_hex: final_hexagon,
+                    final_piece,
+                };
+
+                if let Err(reason) = play_move(valid_session, uuid_user_id, movement) {
+                    send_move_rejected(reason, tx);
                 }
             }
             drop(session);
@@ -192,7 +181,7 @@ pub async fn handle_incoming_ws_message(
             if let (Some(valid_session), Some(color)) =
                 (session.get_mut_session_if_exists(uuid_user_id), color)
             {
-                send_join_success(color, session_id, tx, &valid_session.board);
+                send_join_success(color, session_id, tx, valid_session);
             }
 
             drop(session);
@@ -211,20 +200,123 @@ pub async fn handle_incoming_ws_message(
                 // have to identify a different way of figuring out if the player doesn't exist
                 let res = session.reconnect_player(uuid_user_id, tx.clone());
 
-                if let Some((color, board)) = res {
+                if let Some((color, game)) = res {
                     println!("trying to send a success message");
-                    send_join_success(color, session_id, tx, board)
+                    send_join_success(color, session_id, tx, game)
                 }
             }
         }
     }
 }
 
+/// Play `movement` for `player`, if it's their turn and the move is legal,
+/// and let everyone in the game know. The bot is asked for its reply, or
+/// shut down if the game is over.
+fn play_move(
+    game: &mut session_handling::Game,
+    player: PlayerID,
+    movement: Move,
+) -> Result<(), HexChessError> {
+    let board = &mut game.board;
+    // check this player really has the right to play the next move
+    if !game.players.check_color(player, board.current_player) {
+        return Err(HexChessError::NotYourTurn);
+    }
+
+    let mover = board.current_player;
+    let outcome = board.play(movement)?;
+    game.record.push_move(movement);
+
+    // if the game has ended, send some ending messages
+    if let Some(result) = outcome.result {
+        game.finish_record(RecordResult::from_game_result(result, mover));
+        game.bot = None;
+
+        // the player registering the move has either just won - if only
+        // by ¾ of the point, with a stalemate - or the game has been drawn
+        for (other_player, channel) in &game.channels {
+            send_game_end(Some(result), *other_player == player, channel);
+        }
+    }
+
+    // broadcast an update to both the players
+    for transmitter in game.channels.values() {
+        send_board(game, transmitter);
+    }
+    game.request_bot_move();
+    Ok(())
+}
+
+/// Give the game's empty seat to a bot, played by the configured engine. The
+/// seat is only taken once the engine is running, so if it can't be started
+/// the game is left as it was.
+fn add_bot(
+    session_id: SessionID,
+    game: &mut session_handling::Game,
+    engine: &EngineConfig,
+    sessions: &Arc<RwLock<session_handling::SessionHandler>>,
+) -> io::Result<()> {
+    let bot_player = Uuid::new_v4();
+    let color = match game.players.free_color() {
+        Some(PlayerColor::White) => Color::White,
+        Some(_) => Color::Black,
+        None => return Err(io::Error::other("there's no seat left for the bot")),
+    };
+    let (moves_tx, moves_rx) = mpsc::unbounded_channel();
+    let bot = EngineOpponent::start(engine, bot_player, color, moves_tx)?;
+    game.players.try_add_player(bot_player);
+    game.bot = Some(bot);
+    tokio::task::spawn(play_bot_moves(sessions.clone(), session_id, moves_rx));
+    Ok(())
+}
+
+/// Play the moves the bot's engine comes up with, until the engine stops. An
+/// engine that answers with a move that can't be played, or with no move at
+/// all while the game is still going, forfeits the game, as does one that
+/// stops answering.
+async fn play_bot_moves(
+    sessions: Arc<RwLock<session_handling::SessionHandler>>,
+    session_id: SessionID,
+    mut moves: mpsc::UnboundedReceiver<Option<CoordinateMove>>,
+) {
+    while let Some(reply) = moves.recv().await {
+        let mut session = sessions.write().await;
+        let Some(game) = session.sessions.get_mut(&session_id) else {
+            // the game has been deleted, taking the engine with it
+            return;
+        };
+        let Some(bot) = &game.bot else {
+            // the game is over, and the engine is on its way out
+            continue;
+        };
+        let bot_player = bot.player;
+        let Some(reply) = reply else {
+            eprintln!("The bot found no move to play, so it forfeits");
+            game.forfeit_bot();
+            continue;
+        };
+        let movement = reply.to_move(&game.board);
+        if let Err(reason) = play_move(game, bot_player, movement) {
+            eprintln!("The bot's move {reply} was rejected: {reason}, so it forfeits");
+            game.forfeit_bot();
+        }
+    }
+
+    // the engine has gone, so if it was still meant to be playing, it loses
+    let mut session = sessions.write().await;
+    if let Some(game) = session.sessions.get_mut(&session_id) {
+        if game.bot.is_some() {
+            eprintln!("The bot's engine stopped, so it forfeits");
+            game.forfeit_bot();
+        }
+    }
+}
+
 fn send_join_success(
     color: PlayerColor,
     session_id: Uuid,
     tx: &mpsc::UnboundedSender<warp::ws::Message>,
-    board: &Board,
+    game: &session_handling::Game,
 ) {
     let message = OutgoingMessage::JoinGameSuccess {
         color: color,
@@ -234,14 +326,25 @@ fn send_join_success(
         tx.send(warp::ws::Message::text(success_message)).unwrap();
 
         // send back the new board state
-        send_board(&board, tx);
+        send_board(game, tx);
     } else {
         eprintln!("Failed to send back join confirmation");
     }
 }
 
-fn send_board(board: &Board, tx: &mpsc::UnboundedSender<warp::ws::Message>) {
-    let message = OutgoingMessage::BoardState { board: board.clone() };
+fn send_join_failure(tx: &mpsc::UnboundedSender<warp::ws::Message>) {
+    if let Ok(failure_message) = serde_json::to_string(&OutgoingMessage::JoinGameFailure) {
+        tx.send(warp::ws::Message::text(failure_message)).unwrap();
+    } else {
+        eprintln!("Failed to send join failure");
+    }
+}
+
+fn send_board(game: &session_handling::Game, tx: &mpsc::UnboundedSender<warp::ws::Message>) {
+    let message = OutgoingMessage::BoardState {
+        board: game.board.clone(),
+        last_move: game.last_move(),
+    };
     if let Ok(new_board_state) = serde_json::to_string(&message) {
         tx.send(warp::ws::Message::text(new_board_state)).unwrap();
     } else {
@@ -249,11 +352,20 @@ fn send_board(board: &Board, tx: &mpsc::UnboundedSender<warp::ws::Message>) {
     }
 }
 
-fn send_game_end(mate: Option<Mate>, winner: bool, tx: &mpsc::UnboundedSender<warp::ws::Message>) {
-    let (reason, outcome) = match (mate, winner) {
-        (Some(Mate::Checkmate), true) => (GameEndReason::Checkmate, GameOutcome::Won),
-        (Some(Mate::Stalemate), _) => (GameEndReason::Stalemate, GameOutcome::Drew),
-        (Some(Mate::Checkmate), false) => (GameEndReason::Checkmate, GameOutcome::Lost),
+fn send_move_rejected(reason: HexChessError, tx: &mpsc::UnboundedSender<warp::ws::Message>) {
+    let message = OutgoingMessage::MoveRejected { reason };
+    if let Ok(rejection) = serde_json::to_string(&message) {
+        tx.send(warp::ws::Message::text(rejection)).unwrap();
+    } else {
+        eprintln!("Failed to send move rejection");
+    }
+}
+
+fn send_game_end(result: Option<GameResult>, winner: bool, tx: &mpsc::UnboundedSender<warp::ws::Message>) {
+    let (reason, outcome) = match (result, winner) {
+        (Some(result), _) if result.is_draw() => (result.into(), GameOutcome::Drew),
+        (Some(result), true) => (result.into(), GameOutcome::Won),
+        (Some(result), false) => (result.into(), GameOutcome::Lost),
         (None, true) => (GameEndReason::Resignation, GameOutcome::Won),
         (None, false) => (GameEndReason::Resignation, GameOutcome::Lost),
     };
@@ -268,4 +380,75 @@ fn send_game_end(mate: Option<Mate>, winner: bool, tx: &mpsc::UnboundedSender<wa
         // do something at this point to make sure all the clients recieved their outcome message
         eprintln!("Failed to send outcome message");
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Play a single-player game against the engine `script` stands in for,
+    /// until the game ends. The player takes black, so the bot moves first.
+    async fn play_against(script: &str) -> (OutgoingMessage, RecordResult, bool) {
+        let mut handler = session_handling::SessionHandler::new();
+        handler.engine = EngineConfig {
+            program: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+            move_time: Duration::from_millis(100),
+        };
+        let sessions = Arc::new(RwLock::new(handler));
+        // the first half of the ID decides the colour, and even means black
+        let user_id = Uuid::nil();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let create = IncomingMessage::CreateGame {
+            user_id: user_id.to_string(),
+            is_multiplayer: false,
+            variant: Default::default(),
+        };
+        handle_incoming_ws_message(Message::text(serde_json::to_string(&create).unwrap()), &sessions, &tx).await;
+
+        let ended = loop {
+            let message = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+                .await
+                .expect("the game didn't end")
+                .unwrap();
+            let message: OutgoingMessage = serde_json::from_str(message.to_str().unwrap()).unwrap();
+            if matches!(message, OutgoingMessage::GameEnded { .. }) {
+                break message;
+            }
+        };
+        let session = sessions.read().await;
+        let game = session.get_session_if_exists(user_id).unwrap();
+        (ended, game.record.result, game.bot.is_some())
+    }
+
+    fn assert_bot_forfeited((ended, result, bot_left): (OutgoingMessage, RecordResult, bool)) {
+        assert!(
+            matches!(
+                ended,
+                OutgoingMessage::GameEnded { game_outcome: GameOutcome::Won, reason: GameEndReason::Resignation }
+            ),
+            "{ended:?}"
+        );
+        // the bot was white
+        assert_eq!(result, RecordResult::BlackWins);
+        assert!(!bot_left);
+    }
+
+    #[tokio::test]
+    async fn test_bots_without_a_move_forfeit() {
+        let no_move = r#"while read line; do case "$line" in go*) echo "bestmove (none)";; esac; done"#;
+        assert_bot_forfeited(play_against(no_move).await);
+    }
+
+    #[tokio::test]
+    async fn test_bots_playing_illegal_moves_forfeit() {
+        let illegal = r#"while read line; do case "$line" in go*) echo "bestmove f1f11";; esac; done"#;
+        assert_bot_forfeited(play_against(illegal).await);
+    }
+
+    #[tokio::test]
+    async fn test_bots_whose_engine_stops_forfeit() {
+        assert_bot_forfeited(play_against("exit 0").await);
+    }
+}